@@ -143,8 +143,9 @@ impl ResonantEngine {
 
     /// Performs a search query against the indexed documents.
     /// Returns a vector of `SearchResult`s, sorted by score in descending order.
-    pub fn search(&mut self, query: &str, top_k: usize) -> Vec<SearchResult> {
-        let query_tokens = self.tokenizer.tokenize(query);
+    /// Takes `&self`: unknown query words are skipped rather than added to the vocabulary.
+    pub fn search(&self, query: &str, top_k: usize) -> Vec<SearchResult> {
+        let query_tokens = self.tokenizer.tokenize_known(query).primes;
         // If query tokens are empty, return no results
          if query_tokens.is_empty() {
              return Vec::new();
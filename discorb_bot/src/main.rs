@@ -85,13 +85,12 @@ impl EventHandler for Handler {
                 data_read.get::<EngineContainer>().expect("FATAL: Expected ResonantEngine in TypeMap.").clone()
             }; // The read lock on context data is released here.
 
-            // Perform the search. This requires mutable access (`write` lock) to the engine,
-            // primarily because the tokenizer might need to add new words encountered in the query
-            // to its internal vocabulary, which modifies its state.
+            // Perform the search. Searching only looks words up in the existing vocabulary,
+            // so a shared `read` lock is enough and concurrent queries don't block each other.
             let results: Vec<SearchResult> = {
-                let mut engine = engine_lock.write().await; // Acquire write lock. Waits if another write or reads are active.
+                let engine = engine_lock.read().await; // Acquire read lock. Only waits if a writer is active.
                 engine.search(query, 5) // Execute the search, requesting top 5 results.
-                // The write lock (`engine` guard) is automatically released when it goes out of scope here.
+                // The read lock (`engine` guard) is automatically released when it goes out of scope here.
             }; // The `results` vector is now owned by this scope.
 
 
@@ -14,6 +14,15 @@ use std::collections::HashMap;
 // use primal::arith::prime_after; // Remove this line
 use primal::Primes; // Import the Primes struct
 
+/// Query words looked up against an existing vocabulary.
+#[derive(Debug, Clone, Default)]
+pub struct QueryTokens {
+    /// Primes of the words the vocabulary already knows, in query order.
+    pub primes: Vec<u64>,
+    /// Words with no prime assigned; they cannot match any indexed document.
+    pub unknown: Vec<String>,
+}
+
 /// A tokenizer that maps words to unique prime numbers.
 pub struct PrimeTokenizer {
     token_to_prime: HashMap<String, u64>,
@@ -65,6 +74,23 @@ impl PrimeTokenizer {
         primes_list
     }
 
+    /// Looks up the words of `text` without assigning primes to unseen words.
+    ///
+    /// Use this for queries so typos and one-off words never enter the vocabulary.
+    pub fn tokenize_known(&self, text: &str) -> QueryTokens {
+        let lower_text = text.to_lowercase();
+        let mut query_tokens = QueryTokens::default();
+
+        for mat in self.word_regex.find_iter(&lower_text) {
+            match self.token_to_prime.get(mat.as_str()) {
+                Some(&prime) => query_tokens.primes.push(prime),
+                None => query_tokens.unknown.push(mat.as_str().to_string()),
+            }
+        }
+
+        query_tokens
+    }
+
     #[allow(dead_code)]
     /// Prints the current vocabulary (token to prime mapping).
     pub fn print_vocab(&self) {
@@ -1,6 +1,7 @@
 // src/advanced_crawler.rs
 
 use crate::crawler::CrawledDocument;
+use crate::language::detect_language;
 use reqwest::{Client, Url, header};
 use scraper::{Html, Selector};
 use std::collections::{HashSet, VecDeque, HashMap};
@@ -529,6 +530,23 @@ impl AdvancedCrawler {
                 clean_fragment.root_element().text().collect::<Vec<_>>().join(" ")
             })
             .unwrap_or_default();
+
+        // Extract section headings
+        let heading_selector = Selector::parse("h1, h2, h3").unwrap();
+        let headings = document
+            .select(&heading_selector)
+            .map(|element| element.text().collect::<Vec<_>>().join(" ").trim().to_string())
+            .filter(|heading| !heading.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        // Tag the language, falling back on what the page declares
+        let html_selector = Selector::parse("html").unwrap();
+        let declared_lang = document
+            .select(&html_selector)
+            .next()
+            .and_then(|element| element.value().attr("lang"));
+        let lang = detect_language(&text, declared_lang);
             
         // Extract links if we're below max depth
         if depth < max_depth {
@@ -563,7 +581,9 @@ impl AdvancedCrawler {
         Ok(Some(CrawledDocument {
             url: url.to_string(),
             title,
+            headings,
             text,
+            lang,
         }))
     }
     
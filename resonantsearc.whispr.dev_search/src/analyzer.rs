@@ -0,0 +1,1069 @@
+// src/analyzer.rs
+
+use regex::Regex;
+use rust_stemmers::{Algorithm, Stemmer};
+use std::collections::HashSet;
+use std::ops::Range;
+use unicode_normalization::char::{decompose_canonical, is_combining_mark};
+use unicode_normalization::UnicodeNormalization;
+
+/// Common English function words that carry little meaning on their own.
+pub const ENGLISH_STOP_WORDS: &[&str] = &[
+    "a", "about", "above", "after", "again", "against", "all", "am", "an", "and",
+    "any", "are", "as", "at", "be", "because", "been", "before", "being", "below",
+    "between", "both", "but", "by", "can", "did", "do", "does", "doing", "down",
+    "during", "each", "few", "for", "from", "further", "had", "has", "have", "having",
+    "he", "her", "here", "hers", "herself", "him", "himself", "his", "how", "i",
+    "if", "in", "into", "is", "it", "its", "itself", "just", "me", "more",
+    "most", "my", "myself", "no", "nor", "not", "now", "of", "off", "on",
+    "once", "only", "or", "other", "our", "ours", "ourselves", "out", "over", "own",
+    "same", "she", "should", "so", "some", "such", "than", "that", "the", "their",
+    "theirs", "them", "themselves", "then", "there", "these", "they", "this", "those", "through",
+    "to", "too", "under", "until", "up", "very", "was", "we", "were", "what",
+    "when", "where", "which", "while", "who", "whom", "why", "will", "with", "would",
+    "you", "your", "yours", "yourself", "yourselves",
+];
+
+/// Common German function words.
+pub const GERMAN_STOP_WORDS: &[&str] = &[
+    "aber", "alle", "als", "also", "am", "an", "auch", "auf", "aus", "bei",
+    "bin", "bis", "bist", "da", "damit", "dann", "das", "dass", "dem", "den",
+    "denn", "der", "des", "die", "dies", "diese", "dieser", "doch", "dort", "du",
+    "durch", "ein", "eine", "einem", "einen", "einer", "eines", "er", "es", "fur",
+    "für", "hat", "hatte", "ich", "ihr", "ihre", "im", "in", "ist", "ja",
+    "jede", "kann", "kein", "keine", "man", "mein", "mit", "muss", "nach", "nicht",
+    "noch", "nun", "nur", "ob", "oder", "ohne", "sehr", "sein", "sich", "sie",
+    "sind", "so", "uber", "über", "um", "und", "uns", "unter", "vom", "von",
+    "vor", "war", "waren", "was", "weil", "wenn", "wer", "wie", "wir", "wird",
+    "wo", "zu", "zum", "zur",
+];
+
+/// Words German compounds are commonly built from, used to split them.
+///
+/// Only compounds made entirely of these words are split, so a short list
+/// misses compounds but never invents parts.
+pub const GERMAN_COMPOUND_PARTS: &[&str] = &[
+    "amt", "anlage", "apfel", "arbeit", "arzt", "auto", "bahn", "bank", "bau", "baum",
+    "berg", "bericht", "betrieb", "bild", "blatt", "boot", "brief", "brot", "brücke", "buch",
+    "bund", "bürger", "büro", "dach", "dampf", "daten", "dienst", "dorf", "donau", "druck",
+    "eisen", "energie", "erde", "fahrer", "fahrt", "fahrzeug", "familie", "feld", "fenster", "feuer",
+    "film", "firma", "fisch", "flug", "fluss", "forschung", "frau", "freund", "fuß", "garten",
+    "gebäude", "geber", "geld", "gericht", "gesellschaft", "gesetz", "gesundheit", "glas", "grund", "gruppe",
+    "hafen", "hand", "handel", "haus", "heim", "herz", "hilfe", "hof", "holz", "hund",
+    "industrie", "informatik", "jahr", "kapitän", "karte", "kasse", "kind", "kinder", "kirche", "klasse",
+    "kopf", "kosten", "kraft", "kranken", "krieg", "küche", "kunst", "land", "lager", "leben",
+    "lehrer", "leistung", "leitung", "licht", "liebe", "luft", "macht", "mann", "markt", "maschine",
+    "meister", "mensch", "miete", "minister", "mittel", "monat", "musik", "mutter", "nacht", "name",
+    "netz", "nehmer", "ordnung", "papier", "park", "plan", "platz", "politik", "post", "preis",
+    "programm", "rad", "rat", "raum", "recht", "regierung", "rente", "sache", "schiff", "schloss",
+    "schlüssel", "schrank", "schrift", "schuh", "schule", "schutz", "see", "seite", "sicherheit", "sonne",
+    "spiel", "sprache", "staat", "stadt", "stelle", "steuer", "stoff", "straße", "strom", "stück",
+    "stunde", "system", "tag", "teil", "tier", "tisch", "tor", "turm", "tür", "uhr",
+    "umwelt", "unternehmen", "vater", "verband", "verkehr", "versicherung", "vertrag", "wagen", "wald", "wand",
+    "ware", "wasser", "weg", "wein", "welt", "werk", "wetter", "wind", "wirtschaft", "wissen",
+    "woche", "wohnung", "wort", "zeit", "zeitung", "zentrum", "zimmer", "zug",
+];
+
+/// Letters German inserts between the parts of a compound ("Arbeit-s-platz").
+const GERMAN_LINKING_ELEMENTS: &[&str] = &["s", "es", "n", "en", "e", "er"];
+
+/// Shortest word the compound splitter tries to break up, in characters.
+const MIN_COMPOUND_LEN: usize = 6;
+
+/// Languages supported by the Snowball stemmer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StemLanguage {
+    English,
+    German,
+    French,
+    Spanish,
+    Italian,
+    Portuguese,
+    Dutch,
+    Swedish,
+    Russian,
+}
+
+impl StemLanguage {
+    fn algorithm(self) -> Algorithm {
+        match self {
+            StemLanguage::English => Algorithm::English,
+            StemLanguage::German => Algorithm::German,
+            StemLanguage::French => Algorithm::French,
+            StemLanguage::Spanish => Algorithm::Spanish,
+            StemLanguage::Italian => Algorithm::Italian,
+            StemLanguage::Portuguese => Algorithm::Portuguese,
+            StemLanguage::Dutch => Algorithm::Dutch,
+            StemLanguage::Swedish => Algorithm::Swedish,
+            StemLanguage::Russian => Algorithm::Russian,
+        }
+    }
+
+    /// Returns the lowercase name used in analyzer specs.
+    pub fn name(self) -> &'static str {
+        match self {
+            StemLanguage::English => "english",
+            StemLanguage::German => "german",
+            StemLanguage::French => "french",
+            StemLanguage::Spanish => "spanish",
+            StemLanguage::Italian => "italian",
+            StemLanguage::Portuguese => "portuguese",
+            StemLanguage::Dutch => "dutch",
+            StemLanguage::Swedish => "swedish",
+            StemLanguage::Russian => "russian",
+        }
+    }
+
+    /// Looks up the stemmer for a two-letter language code ("en", "de", ...).
+    pub fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "en" => Some(StemLanguage::English),
+            "de" => Some(StemLanguage::German),
+            "fr" => Some(StemLanguage::French),
+            "es" => Some(StemLanguage::Spanish),
+            "it" => Some(StemLanguage::Italian),
+            "pt" => Some(StemLanguage::Portuguese),
+            "nl" => Some(StemLanguage::Dutch),
+            "sv" => Some(StemLanguage::Swedish),
+            "ru" => Some(StemLanguage::Russian),
+            _ => None,
+        }
+    }
+
+    /// Parses a name produced by `name`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "english" => Some(StemLanguage::English),
+            "german" => Some(StemLanguage::German),
+            "french" => Some(StemLanguage::French),
+            "spanish" => Some(StemLanguage::Spanish),
+            "italian" => Some(StemLanguage::Italian),
+            "portuguese" => Some(StemLanguage::Portuguese),
+            "dutch" => Some(StemLanguage::Dutch),
+            "swedish" => Some(StemLanguage::Swedish),
+            "russian" => Some(StemLanguage::Russian),
+            _ => None,
+        }
+    }
+}
+
+/// Programming languages whose keywords the code analyzer drops.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CodeLanguage {
+    Rust,
+    Python,
+    JavaScript,
+    /// C, C++, C# and Java.
+    CFamily,
+    Go,
+    Php,
+    Ruby,
+    Shell,
+    Sql,
+    Css,
+}
+
+impl CodeLanguage {
+    /// Picks the language for a (lowercase) file extension, if it is source code.
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension {
+            "rs" => Some(CodeLanguage::Rust),
+            "py" => Some(CodeLanguage::Python),
+            "js" | "mjs" | "jsx" | "ts" | "tsx" => Some(CodeLanguage::JavaScript),
+            "c" | "h" | "cc" | "cpp" | "hpp" | "cs" | "java" => Some(CodeLanguage::CFamily),
+            "go" => Some(CodeLanguage::Go),
+            "php" => Some(CodeLanguage::Php),
+            "rb" => Some(CodeLanguage::Ruby),
+            "sh" | "bash" | "bat" => Some(CodeLanguage::Shell),
+            "sql" => Some(CodeLanguage::Sql),
+            "css" => Some(CodeLanguage::Css),
+            _ => None,
+        }
+    }
+
+    /// Reserved words that say nothing about what the code does.
+    pub fn keywords(self) -> &'static [&'static str] {
+        match self {
+            CodeLanguage::Rust => &[
+                "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum",
+                "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match",
+                "mod", "move", "mut", "pub", "ref", "return", "self", "static", "struct", "super",
+                "trait", "true", "type", "unsafe", "use", "where", "while",
+            ],
+            CodeLanguage::Python => &[
+                "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del",
+                "elif", "else", "except", "false", "finally", "for", "from", "global", "if", "import",
+                "in", "is", "lambda", "none", "nonlocal", "not", "or", "pass", "raise", "return",
+                "self", "true", "try", "while", "with", "yield",
+            ],
+            CodeLanguage::JavaScript => &[
+                "async", "await", "break", "case", "catch", "class", "const", "continue", "default", "delete",
+                "do", "else", "export", "extends", "false", "finally", "for", "function", "if", "import",
+                "in", "instanceof", "let", "new", "null", "return", "super", "switch", "this", "throw",
+                "true", "try", "typeof", "undefined", "var", "void", "while", "yield",
+            ],
+            CodeLanguage::CFamily => &[
+                "auto", "bool", "break", "case", "catch", "char", "class", "const", "continue", "default",
+                "delete", "do", "double", "else", "enum", "extends", "extern", "false", "final", "float",
+                "for", "if", "implements", "import", "include", "int", "long", "namespace", "new", "null",
+                "nullptr", "package", "private", "protected", "public", "return", "short", "signed", "sizeof", "static",
+                "struct", "switch", "template", "this", "throw", "true", "try", "typedef", "union", "unsigned",
+                "using", "virtual", "void", "volatile", "while",
+            ],
+            CodeLanguage::Go => &[
+                "break", "case", "chan", "const", "continue", "default", "defer", "else", "fallthrough", "false",
+                "for", "func", "go", "goto", "if", "import", "interface", "map", "nil", "package",
+                "range", "return", "select", "struct", "switch", "true", "type", "var",
+            ],
+            CodeLanguage::Php => &[
+                "abstract", "array", "as", "break", "case", "catch", "class", "const", "continue", "default",
+                "echo", "else", "elseif", "extends", "false", "final", "for", "foreach", "function", "if",
+                "implements", "include", "instanceof", "interface", "namespace", "new", "null", "php", "private", "protected",
+                "public", "require", "return", "static", "switch", "this", "throw", "true", "try", "use",
+                "var", "while",
+            ],
+            CodeLanguage::Ruby => &[
+                "alias", "and", "begin", "break", "case", "class", "def", "do", "else", "elsif",
+                "end", "ensure", "false", "for", "if", "in", "module", "next", "nil", "not",
+                "or", "redo", "rescue", "retry", "return", "self", "super", "then", "true", "undef",
+                "unless", "until", "when", "while", "yield",
+            ],
+            CodeLanguage::Shell => &[
+                "case", "do", "done", "echo", "elif", "else", "esac", "exit", "export", "fi",
+                "for", "function", "if", "in", "local", "rem", "return", "set", "then", "until",
+                "while",
+            ],
+            CodeLanguage::Sql => &[
+                "and", "as", "asc", "by", "create", "delete", "desc", "distinct", "drop", "from",
+                "group", "having", "in", "insert", "into", "is", "join", "left", "limit", "not",
+                "null", "on", "or", "order", "select", "set", "table", "update", "values", "where",
+            ],
+            CodeLanguage::Css => &["important", "import", "media", "keyframes", "supports", "charset"],
+        }
+    }
+
+    /// Returns the lowercase name used in analyzer specs.
+    pub fn name(self) -> &'static str {
+        match self {
+            CodeLanguage::Rust => "rust",
+            CodeLanguage::Python => "python",
+            CodeLanguage::JavaScript => "javascript",
+            CodeLanguage::CFamily => "c-family",
+            CodeLanguage::Go => "go",
+            CodeLanguage::Php => "php",
+            CodeLanguage::Ruby => "ruby",
+            CodeLanguage::Shell => "shell",
+            CodeLanguage::Sql => "sql",
+            CodeLanguage::Css => "css",
+        }
+    }
+
+    /// Parses a name produced by `name`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "rust" => Some(CodeLanguage::Rust),
+            "python" => Some(CodeLanguage::Python),
+            "javascript" => Some(CodeLanguage::JavaScript),
+            "c-family" => Some(CodeLanguage::CFamily),
+            "go" => Some(CodeLanguage::Go),
+            "php" => Some(CodeLanguage::Php),
+            "ruby" => Some(CodeLanguage::Ruby),
+            "shell" => Some(CodeLanguage::Shell),
+            "sql" => Some(CodeLanguage::Sql),
+            "css" => Some(CodeLanguage::Css),
+            _ => None,
+        }
+    }
+}
+
+/// Which stop words the analyzer drops.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StopWords {
+    None,
+    English,
+    German,
+    Custom(Vec<String>),
+}
+
+/// Settings for the analysis chain that runs before prime assignment.
+///
+/// The default keeps the original behavior: lowercase and split on `\b\w+\b`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnalyzerConfig {
+    /// Apply NFKC normalization and strip diacritics, so "Café" matches "cafe".
+    pub fold_unicode: bool,
+    pub stop_words: StopWords,
+    pub stemmer: Option<StemLanguage>,
+    /// Tokens shorter than this (in characters) are dropped.
+    pub min_token_len: usize,
+    /// Tokens longer than this (in characters) are dropped.
+    pub max_token_len: usize,
+    /// Split camelCase, snake_case and kebab-case identifiers into their words,
+    /// keeping the whole identifier as a token too.
+    pub split_identifiers: bool,
+    /// Drop the keywords of this language (as whole identifiers).
+    pub code_keywords: Option<CodeLanguage>,
+    /// Index runs of Chinese, Japanese and Korean characters as overlapping
+    /// character bigrams, since those scripts do not separate words.
+    pub cjk_bigrams: bool,
+    /// Split German compounds into their parts, keeping the compound too.
+    pub split_compounds: bool,
+}
+
+impl Default for AnalyzerConfig {
+    fn default() -> Self {
+        AnalyzerConfig {
+            fold_unicode: false,
+            stop_words: StopWords::None,
+            stemmer: None,
+            min_token_len: 1,
+            max_token_len: usize::MAX,
+            split_identifiers: false,
+            code_keywords: None,
+            cjk_bigrams: false,
+            split_compounds: false,
+        }
+    }
+}
+
+impl AnalyzerConfig {
+    /// Folding, English stop words, Snowball (Porter2) stemming and 2..=40 character tokens.
+    pub fn english() -> Self {
+        AnalyzerConfig {
+            fold_unicode: true,
+            stop_words: StopWords::English,
+            stemmer: Some(StemLanguage::English),
+            min_token_len: 2,
+            max_token_len: 40,
+            split_identifiers: false,
+            code_keywords: None,
+            cjk_bigrams: false,
+            split_compounds: false,
+        }
+    }
+
+    /// The same settings for source code in `language`: identifiers are split
+    /// and the language's keywords dropped.
+    pub fn for_code(&self, language: CodeLanguage) -> Self {
+        AnalyzerConfig {
+            split_identifiers: true,
+            code_keywords: Some(language),
+            ..self.clone()
+        }
+    }
+
+    /// The same settings adapted to documents in `language` (a code from
+    /// `language::detect_language`).
+    ///
+    /// Every language gets the CJK bigram fallback. For a language with a
+    /// Snowball stemmer, settings that stem or drop stop words switch to that
+    /// language's (German also splits compounds); other languages keep the
+    /// settings as they are.
+    pub fn for_language(&self, language: &str) -> Self {
+        let mut config = AnalyzerConfig {
+            cjk_bigrams: true,
+            ..self.clone()
+        };
+
+        if matches!(language, "zh" | "ja" | "ko") {
+            config.stemmer = None;
+            config.stop_words = StopWords::None;
+            config.min_token_len = 1;
+        } else if let Some(stem_language) = StemLanguage::from_code(language) {
+            if self.stemmer.is_some() {
+                config.stemmer = Some(stem_language);
+            }
+            if matches!(self.stop_words, StopWords::English | StopWords::German) {
+                config.stop_words = match stem_language {
+                    StemLanguage::English => StopWords::English,
+                    StemLanguage::German => StopWords::German,
+                    _ => StopWords::None,
+                };
+            }
+            config.split_compounds = stem_language == StemLanguage::German;
+        }
+        config
+    }
+
+    /// Looks up a named preset ("plain" or "english").
+    pub fn preset(name: &str) -> Option<Self> {
+        match name {
+            "plain" => Some(Self::default()),
+            "english" => Some(Self::english()),
+            _ => None,
+        }
+    }
+
+    /// Serializes the settings as `key=value` pairs separated by `;`.
+    pub fn spec(&self) -> String {
+        let stop = match &self.stop_words {
+            StopWords::None => "none".to_string(),
+            StopWords::English => "english".to_string(),
+            StopWords::German => "german".to_string(),
+            StopWords::Custom(words) => format!("custom:{}", words.join(",")),
+        };
+        let stem = self.stemmer.map(|lang| lang.name()).unwrap_or("none");
+        let mut spec = format!(
+            "fold={};stop={};stem={};min={};max={}",
+            self.fold_unicode as u8, stop, stem, self.min_token_len, self.max_token_len
+        );
+        // Only written when set, so specs recorded before these options existed still match
+        if self.split_identifiers {
+            spec.push_str(";split=1");
+        }
+        if let Some(language) = self.code_keywords {
+            spec.push_str(&format!(";keywords={}", language.name()));
+        }
+        if self.cjk_bigrams {
+            spec.push_str(";cjk=1");
+        }
+        if self.split_compounds {
+            spec.push_str(";compounds=1");
+        }
+        spec
+    }
+
+    /// Parses a string produced by `spec`.
+    pub fn parse_spec(spec: &str) -> Option<Self> {
+        let mut config = AnalyzerConfig::default();
+        for pair in spec.split(';').filter(|p| !p.is_empty()) {
+            let (key, value) = pair.split_once('=')?;
+            match key {
+                "fold" => config.fold_unicode = value == "1",
+                "stop" => {
+                    config.stop_words = match value {
+                        "none" => StopWords::None,
+                        "english" => StopWords::English,
+                        "german" => StopWords::German,
+                        _ => {
+                            let words = value.strip_prefix("custom:")?;
+                            StopWords::Custom(
+                                words.split(',').filter(|w| !w.is_empty()).map(str::to_string).collect()
+                            )
+                        }
+                    }
+                }
+                "stem" => {
+                    config.stemmer = match value {
+                        "none" => None,
+                        name => Some(StemLanguage::from_name(name)?),
+                    }
+                }
+                "min" => config.min_token_len = value.parse().ok()?,
+                "max" => config.max_token_len = value.parse().ok()?,
+                "split" => config.split_identifiers = value == "1",
+                "keywords" => config.code_keywords = Some(CodeLanguage::from_name(value)?),
+                "cjk" => config.cjk_bigrams = value == "1",
+                "compounds" => config.split_compounds = value == "1",
+                _ => return None,
+            }
+        }
+        Some(config)
+    }
+}
+
+/// An extra step appended to the analysis chain.
+pub trait TokenFilter: Send + Sync {
+    /// Short identifier recorded with the vocabulary so mismatched chains are detected.
+    fn name(&self) -> String;
+
+    /// Returns the transformed token, or `None` to drop it.
+    fn apply(&self, token: String) -> Option<String>;
+}
+
+/// Turns text into the tokens that get primes: normalize, split, filter, stem.
+///
+/// Documents and queries must go through the same analyzer, which is why
+/// it lives inside `PrimeTokenizer` and is recorded with the vocabulary.
+pub struct Analyzer {
+    config: AnalyzerConfig,
+    word_regex: Regex,
+    identifier_regex: Regex,
+    stop_words: HashSet<String>,
+    keywords: HashSet<&'static str>,
+    compound_parts: HashSet<String>,
+    stemmer: Option<Stemmer>,
+    filters: Vec<Box<dyn TokenFilter>>,
+}
+
+impl Default for Analyzer {
+    fn default() -> Self {
+        Self::new(AnalyzerConfig::default())
+    }
+}
+
+impl Analyzer {
+    /// Creates an analyzer from its settings.
+    pub fn new(config: AnalyzerConfig) -> Self {
+        let word_regex = Regex::new(r"\b\w+\b").expect("Failed to create word regex");
+        let identifier_regex = Regex::new(r"[\p{L}\p{N}_]+(?:-[\p{L}\p{N}_]+)*")
+            .expect("Failed to create identifier regex");
+        let stop_words = match &config.stop_words {
+            StopWords::None => HashSet::new(),
+            StopWords::English => ENGLISH_STOP_WORDS.iter().map(|w| w.to_string()).collect(),
+            StopWords::German => GERMAN_STOP_WORDS.iter().map(|w| w.to_string()).collect(),
+            StopWords::Custom(words) => words.iter().cloned().collect(),
+        };
+        let stemmer = config.stemmer.map(|lang| Stemmer::create(lang.algorithm()));
+        let keywords = config.code_keywords
+            .map(|language| language.keywords().iter().copied().collect())
+            .unwrap_or_default();
+
+        let mut analyzer = Analyzer {
+            config,
+            word_regex,
+            identifier_regex,
+            stop_words,
+            keywords,
+            compound_parts: HashSet::new(),
+            stemmer,
+            filters: Vec::new(),
+        };
+        // Stop words and parts are compared with normalized words, so fold
+        // them the same way
+        analyzer.stop_words = analyzer.stop_words.iter().map(|word| analyzer.normalize(word)).collect();
+        if analyzer.config.split_compounds {
+            analyzer.compound_parts = GERMAN_COMPOUND_PARTS.iter().map(|part| analyzer.normalize(part)).collect();
+        }
+        analyzer
+    }
+
+    /// Appends a custom filter that runs after stemming.
+    pub fn with_filter<F: TokenFilter + 'static>(mut self, filter: F) -> Self {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    /// Returns the settings this analyzer was built from.
+    pub fn config(&self) -> &AnalyzerConfig {
+        &self.config
+    }
+
+    /// Identifies the full chain, including custom filters.
+    pub fn signature(&self) -> String {
+        let mut signature = self.config.spec();
+        for filter in &self.filters {
+            signature.push('|');
+            signature.push_str(&filter.name());
+        }
+        signature
+    }
+
+    /// Lowercases the text and, if enabled, applies NFKC and strips diacritics.
+    pub fn normalize(&self, text: &str) -> String {
+        if self.config.fold_unicode {
+            self.strip_marks(&text.nfkc().collect::<String>().to_lowercase())
+        } else {
+            text.to_lowercase()
+        }
+    }
+
+    /// Removes diacritics by decomposing each character and dropping its combining marks.
+    fn strip_marks(&self, text: &str) -> String {
+        let mut stripped = String::with_capacity(text.len());
+        for c in text.chars() {
+            // Kana voicing marks and Hangul syllables are part of the letter
+            if self.config.cjk_bigrams && is_cjk(c) {
+                stripped.push(c);
+                continue;
+            }
+            decompose_canonical(c, |part| {
+                if !is_combining_mark(part) {
+                    stripped.push(part);
+                }
+            });
+        }
+        stripped
+    }
+
+    /// Runs one already-normalized word through the filters, returning `None` if it is dropped.
+    pub fn filter_token(&self, word: &str) -> Option<String> {
+        let len = word.chars().count();
+        if len < self.config.min_token_len || len > self.config.max_token_len {
+            return None;
+        }
+        if self.stop_words.contains(word) {
+            return None;
+        }
+
+        let mut token = match &self.stemmer {
+            Some(stemmer) => stemmer.stem(word).into_owned(),
+            None => word.to_string(),
+        };
+        for filter in &self.filters {
+            token = filter.apply(token)?;
+        }
+        Some(token)
+    }
+
+    /// Splits text into analyzed tokens, in order.
+    pub fn analyze(&self, text: &str) -> Vec<String> {
+        if self.config.split_identifiers {
+            return self.analyze_identifiers(text);
+        }
+
+        let normalized = self.normalize(text);
+        let mut tokens = Vec::new();
+        for mat in self.word_regex.find_iter(&normalized) {
+            self.push_word(mat.as_str(), &mut tokens);
+        }
+        tokens
+    }
+
+    /// Like `analyze`, but each token comes with the byte range in `text` of
+    /// the word it came from (tokens split from one word share its range).
+    ///
+    /// Words are found before normalization, one at a time, so characters
+    /// that only become word characters once folded (such as circled digits)
+    /// are skipped; otherwise the tokens are the same as `analyze` gives.
+    pub fn analyze_with_offsets(&self, text: &str) -> Vec<(String, Range<usize>)> {
+        let regex = if self.config.split_identifiers { &self.identifier_regex } else { &self.word_regex };
+        let mut tokens = Vec::new();
+        let mut word_tokens = Vec::new();
+        for mat in regex.find_iter(text) {
+            if self.config.split_identifiers {
+                self.push_identifier(&self.fold(mat.as_str()), &mut word_tokens);
+            } else {
+                self.push_word(&self.normalize(mat.as_str()), &mut word_tokens);
+            }
+            tokens.extend(word_tokens.drain(..).map(|token| (token, mat.range())));
+        }
+        tokens
+    }
+
+    /// Filters one normalized word into `tokens`, first breaking up CJK runs
+    /// and compounds if enabled.
+    fn push_word(&self, word: &str, tokens: &mut Vec<String>) {
+        if self.config.cjk_bigrams && word.chars().any(is_cjk) {
+            for (run, cjk) in script_runs(word) {
+                if cjk {
+                    tokens.extend(cjk_bigrams(run).iter().filter_map(|bigram| self.filter_token(bigram)));
+                } else {
+                    self.push_word(run, tokens);
+                }
+            }
+            return;
+        }
+
+        tokens.extend(self.filter_token(word));
+        if self.config.split_compounds && word.chars().count() >= MIN_COMPOUND_LEN {
+            if let Some(parts) = self.split_compound(word) {
+                tokens.extend(parts.iter().filter_map(|part| self.filter_token(part)));
+            }
+        }
+    }
+
+    /// Breaks a normalized word into compound parts, preferring the longest
+    /// first part. Returns `None` unless the whole word is covered.
+    fn split_compound<'a>(&self, word: &'a str) -> Option<Vec<&'a str>> {
+        let boundaries: Vec<usize> = word.char_indices().map(|(index, _)| index).skip(1).collect();
+        for &end in boundaries.iter().rev() {
+            let (head, rest) = word.split_at(end);
+            if !self.compound_parts.contains(head) {
+                continue;
+            }
+            // The next part may start after a linking element
+            let remainders = std::iter::once(rest).chain(
+                GERMAN_LINKING_ELEMENTS.iter().filter_map(|link| rest.strip_prefix(link)),
+            );
+            for remainder in remainders.filter(|r| !r.is_empty()) {
+                if self.compound_parts.contains(remainder) {
+                    return Some(vec![head, remainder]);
+                }
+                if let Some(mut parts) = self.split_compound(remainder) {
+                    parts.insert(0, head);
+                    return Some(parts);
+                }
+            }
+        }
+        None
+    }
+
+    /// Emits each identifier whole, followed by its words if it has several.
+    fn analyze_identifiers(&self, text: &str) -> Vec<String> {
+        let folded = self.fold(text);
+        let mut tokens = Vec::new();
+        for mat in self.identifier_regex.find_iter(&folded) {
+            self.push_identifier(mat.as_str(), &mut tokens);
+        }
+        tokens
+    }
+
+    // Splitting needs the original case, so only fold before matching
+    fn fold(&self, text: &str) -> String {
+        if self.config.fold_unicode {
+            self.strip_marks(&text.nfkc().collect::<String>())
+        } else {
+            text.to_string()
+        }
+    }
+
+    /// Filters one folded identifier into `tokens`, then its words if it has several.
+    fn push_identifier(&self, identifier: &str, tokens: &mut Vec<String>) {
+        let whole = self.normalize(identifier);
+        if self.keywords.contains(whole.as_str()) {
+            return;
+        }
+
+        self.push_word(&whole, tokens);
+        let parts = split_identifier(identifier);
+        if parts.len() > 1 {
+            for part in parts {
+                self.push_word(&self.normalize(part), tokens);
+            }
+        }
+    }
+}
+
+/// Checks whether a character belongs to a script written without spaces
+/// between words (Chinese, Japanese) or is Korean Hangul.
+pub fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30FF}'     // Hiragana and Katakana
+        | '\u{3400}'..='\u{4DBF}'   // CJK Extension A
+        | '\u{4E00}'..='\u{9FFF}'   // CJK Unified Ideographs
+        | '\u{F900}'..='\u{FAFF}'   // CJK Compatibility Ideographs
+        | '\u{1100}'..='\u{11FF}'   // Hangul Jamo
+        | '\u{3130}'..='\u{318F}'   // Hangul Compatibility Jamo
+        | '\u{AC00}'..='\u{D7AF}'   // Hangul Syllables
+        | '\u{20000}'..='\u{2A6DF}' // CJK Extension B
+    )
+}
+
+/// Splits a word into alternating CJK and non-CJK runs, flagging the CJK ones.
+fn script_runs(word: &str) -> Vec<(&str, bool)> {
+    let mut runs = Vec::new();
+    let mut start = 0;
+    let mut current = None;
+    for (index, c) in word.char_indices() {
+        let cjk = is_cjk(c);
+        if current.is_some_and(|previous| previous != cjk) {
+            runs.push((&word[start..index], !cjk));
+            start = index;
+        }
+        current = Some(cjk);
+    }
+    if let Some(cjk) = current {
+        runs.push((&word[start..], cjk));
+    }
+    runs
+}
+
+/// Overlapping character bigrams of a CJK run; a single character stands alone.
+///
+/// "東京都" gives "東京" and "京都".
+pub fn cjk_bigrams(run: &str) -> Vec<String> {
+    let chars: Vec<char> = run.chars().collect();
+    if chars.len() < 2 {
+        return vec![run.to_string()];
+    }
+    chars.windows(2).map(|pair| pair.iter().collect()).collect()
+}
+
+/// Splits an identifier into its words.
+///
+/// `build_biorthogonal_vector`, `buildBiorthogonalVector` and
+/// `build-biorthogonal-vector` all give `build`, `Biorthogonal`/`biorthogonal`
+/// and `Vector`/`vector`; an acronym stays together (`HTTPServer` gives
+/// `HTTP` and `Server`).
+pub fn split_identifier(identifier: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    for chunk in identifier.split(['_', '-']).filter(|chunk| !chunk.is_empty()) {
+        let chars: Vec<(usize, char)> = chunk.char_indices().collect();
+        let mut start = 0;
+        for (i, window) in chars.windows(2).enumerate() {
+            let (prev, (index, c)) = (window[0].1, window[1]);
+            let next_is_lower = chars.get(i + 2).is_some_and(|&(_, next)| next.is_lowercase());
+            let boundary = c.is_uppercase()
+                && (prev.is_lowercase() || prev.is_numeric() || (prev.is_uppercase() && next_is_lower));
+            if boundary {
+                parts.push(&chunk[start..index]);
+                start = index;
+            }
+        }
+        parts.push(&chunk[start..]);
+    }
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn analyze(config: AnalyzerConfig, text: &str) -> Vec<String> {
+        Analyzer::new(config).analyze(text)
+    }
+
+    #[test]
+    fn each_filter_shapes_the_tokens() {
+        let cases: Vec<(&str, AnalyzerConfig, &str, &[&str])> = vec![
+            (
+                "plain",
+                AnalyzerConfig::default(),
+                "Hello, World! hello",
+                &["hello", "world", "hello"],
+            ),
+            (
+                "no folding",
+                AnalyzerConfig::default(),
+                "Café Über",
+                &["café", "über"],
+            ),
+            (
+                "folding",
+                AnalyzerConfig {
+                    fold_unicode: true,
+                    ..AnalyzerConfig::default()
+                },
+                "Café Über ﬁne",
+                &["cafe", "uber", "fine"],
+            ),
+            (
+                "english stop words",
+                AnalyzerConfig {
+                    stop_words: StopWords::English,
+                    ..AnalyzerConfig::default()
+                },
+                "The cat and THE hat",
+                &["cat", "hat"],
+            ),
+            (
+                "german stop words",
+                AnalyzerConfig {
+                    stop_words: StopWords::German,
+                    ..AnalyzerConfig::default()
+                },
+                "der Hund und die Katze",
+                &["hund", "katze"],
+            ),
+            (
+                "custom stop words are folded like the text",
+                AnalyzerConfig {
+                    fold_unicode: true,
+                    stop_words: StopWords::Custom(vec!["Café".to_string()]),
+                    ..AnalyzerConfig::default()
+                },
+                "cafe CAFÉ latte",
+                &["latte"],
+            ),
+            (
+                "stemming",
+                AnalyzerConfig {
+                    stemmer: Some(StemLanguage::English),
+                    ..AnalyzerConfig::default()
+                },
+                "running jumps connected",
+                &["run", "jump", "connect"],
+            ),
+            (
+                "token length",
+                AnalyzerConfig {
+                    min_token_len: 3,
+                    max_token_len: 5,
+                    ..AnalyzerConfig::default()
+                },
+                "a an ant ants antelope",
+                &["ant", "ants"],
+            ),
+            (
+                "english preset",
+                AnalyzerConfig::english(),
+                "The Cafés were running",
+                &["cafe", "run"],
+            ),
+        ];
+        for (name, config, text, expected) in cases {
+            assert_eq!(analyze(config, text), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn source_code_is_split_into_identifier_words() {
+        let cases: Vec<(&str, AnalyzerConfig, &str, &[&str])> = vec![
+            (
+                "camelCase and snake_case",
+                AnalyzerConfig {
+                    split_identifiers: true,
+                    ..AnalyzerConfig::default()
+                },
+                "parseHttpRequest snake_case",
+                &[
+                    "parsehttprequest",
+                    "parse",
+                    "http",
+                    "request",
+                    "snake_case",
+                    "snake",
+                    "case",
+                ],
+            ),
+            (
+                "kebab-case and acronyms",
+                AnalyzerConfig {
+                    split_identifiers: true,
+                    ..AnalyzerConfig::default()
+                },
+                "HTTPServer max-width",
+                &["httpserver", "http", "server", "max-width", "max", "width"],
+            ),
+            (
+                "keywords",
+                AnalyzerConfig::default().for_code(CodeLanguage::Rust),
+                "pub fn build_index(self) -> Index",
+                &["build_index", "build", "index", "index"],
+            ),
+        ];
+        for (name, config, text, expected) in cases {
+            assert_eq!(analyze(config, text), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn split_identifier_finds_word_boundaries() {
+        let cases: [(&str, &[&str]); 6] = [
+            (
+                "build_biorthogonal_vector",
+                &["build", "biorthogonal", "vector"],
+            ),
+            (
+                "buildBiorthogonalVector",
+                &["build", "Biorthogonal", "Vector"],
+            ),
+            (
+                "build-biorthogonal-vector",
+                &["build", "biorthogonal", "vector"],
+            ),
+            ("HTTPServer", &["HTTP", "Server"]),
+            ("utf8Decoder", &["utf8", "Decoder"]),
+            ("__init__", &["init"]),
+        ];
+        for (identifier, expected) in cases {
+            assert_eq!(split_identifier(identifier), expected, "{}", identifier);
+        }
+    }
+
+    #[test]
+    fn cjk_runs_become_bigrams() {
+        assert_eq!(cjk_bigrams("東京都"), ["東京", "京都"]);
+        assert_eq!(cjk_bigrams("東"), ["東"]);
+
+        let config = AnalyzerConfig {
+            cjk_bigrams: true,
+            ..AnalyzerConfig::default()
+        };
+        assert_eq!(analyze(config.clone(), "東京都"), ["東京", "京都"]);
+        // Latin letters inside the run are words of their own
+        assert_eq!(
+            analyze(config.clone(), "東京tower都庁"),
+            ["東京", "tower", "都庁"]
+        );
+        // Without the option the run is one token
+        assert_eq!(analyze(AnalyzerConfig::default(), "東京都"), ["東京都"]);
+    }
+
+    #[test]
+    fn german_compounds_are_split_into_known_parts() {
+        let config = AnalyzerConfig {
+            split_compounds: true,
+            ..AnalyzerConfig::default()
+        };
+        let cases: [(&str, &[&str]); 4] = [
+            ("Haustür", &["haustür", "haus", "tür"]),
+            ("Arbeitsplatz", &["arbeitsplatz", "arbeit", "platz"]),
+            ("Bahnhofsuhr", &["bahnhofsuhr", "bahn", "hof", "uhr"]),
+            // Only compounds made entirely of known parts are split
+            ("Hausmeisterei", &["hausmeisterei"]),
+        ];
+        for (word, expected) in cases {
+            assert_eq!(analyze(config.clone(), word), expected, "{}", word);
+        }
+
+        // German documents get the option from their language
+        let german = AnalyzerConfig::english().for_language("de");
+        assert!(german.split_compounds);
+        assert_eq!(german.stemmer, Some(StemLanguage::German));
+        assert_eq!(german.stop_words, StopWords::German);
+    }
+
+    #[test]
+    fn spec_round_trips() {
+        let configs = [
+            AnalyzerConfig::default(),
+            AnalyzerConfig::english(),
+            AnalyzerConfig::english().for_code(CodeLanguage::Python),
+            AnalyzerConfig::english().for_language("de"),
+            AnalyzerConfig::english().for_language("ja"),
+            AnalyzerConfig {
+                stop_words: StopWords::Custom(vec!["foo".to_string(), "bar".to_string()]),
+                stemmer: Some(StemLanguage::French),
+                min_token_len: 3,
+                max_token_len: 12,
+                ..AnalyzerConfig::default()
+            },
+        ];
+        for config in configs {
+            assert_eq!(
+                AnalyzerConfig::parse_spec(&config.spec()),
+                Some(config.clone()),
+                "{}",
+                config.spec()
+            );
+        }
+
+        // Specs recorded before the later options existed still parse
+        assert_eq!(
+            AnalyzerConfig::parse_spec("fold=1;stop=english;stem=english;min=2;max=40"),
+            Some(AnalyzerConfig::english())
+        );
+        assert_eq!(AnalyzerConfig::parse_spec("fold=1;color=blue"), None);
+        assert_eq!(AnalyzerConfig::parse_spec("stem=klingon"), None);
+    }
+
+    #[test]
+    fn offsets_give_the_same_tokens_as_analyze() {
+        let configs = [
+            AnalyzerConfig::default(),
+            AnalyzerConfig::english(),
+            AnalyzerConfig::english().for_code(CodeLanguage::Rust),
+            AnalyzerConfig::english().for_language("de"),
+            AnalyzerConfig::english().for_language("zh"),
+        ];
+        let text = "The Cafés near Bahnhofsuhr run parseHttpRequest, 東京tower都庁 naïvely";
+        for config in configs {
+            let analyzer = Analyzer::new(config);
+            let with_offsets = analyzer.analyze_with_offsets(text);
+            let tokens: Vec<String> = with_offsets
+                .iter()
+                .map(|(token, _)| token.clone())
+                .collect();
+            assert_eq!(
+                tokens,
+                analyzer.analyze(text),
+                "{}",
+                analyzer.config().spec()
+            );
+
+            // Each token points at the word it came from
+            for (token, range) in &with_offsets {
+                let word = &text[range.clone()];
+                let words = analyzer.analyze(word);
+                assert!(words.contains(token), "{} from {:?}", token, word);
+            }
+        }
+    }
+
+    #[test]
+    fn offsets_skip_characters_that_only_folding_makes_words() {
+        // A circled digit only becomes the word "1" once NFKC-folded
+        let analyzer = Analyzer::new(AnalyzerConfig {
+            fold_unicode: true,
+            ..AnalyzerConfig::default()
+        });
+        assert_eq!(analyzer.analyze("step ① done"), ["step", "1", "done"]);
+        let with_offsets = analyzer.analyze_with_offsets("step ① done");
+        assert_eq!(
+            with_offsets,
+            [("step".to_string(), 0..4), ("done".to_string(), 9..13)]
+        );
+    }
+}
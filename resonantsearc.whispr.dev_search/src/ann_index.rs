@@ -0,0 +1,581 @@
+// src/ann_index.rs
+
+use crate::prime_hilbert::{mix_hash, project_dense, project_dense_sparse, PrimeVector};
+use crate::sparse_vector::SparseVector;
+use serde::{Deserialize, Serialize};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+/// Dimension of the document embeddings an `AnnIndex` is built over.
+pub const ANN_DIMENSION: usize = 256;
+
+// Seeds the hash that draws each node's top layer, so rebuilding an index
+// over the same documents gives the same graph
+const LEVEL_SEED: u64 = 0x6a09_e667_f3bc_c908;
+
+// No node is placed above this layer, however lucky its draw
+const MAX_LEVEL: usize = 16;
+
+/// Embeds a document's frequency vector for an `AnnIndex`.
+///
+/// The vector is feature-hashed onto `ANN_DIMENSION` dimensions with
+/// `project_dense`; the index normalizes it, so similarity is the cosine of
+/// the projected word distributions.
+pub fn embed(vector: &PrimeVector) -> Vec<f32> {
+    project_dense(vector, ANN_DIMENSION).into_iter().map(|value| value as f32).collect()
+}
+
+/// Like `embed`, for a `SparseVector`.
+pub fn embed_sparse(vector: &SparseVector) -> Vec<f32> {
+    project_dense_sparse(vector, ANN_DIMENSION).into_iter().map(|value| value as f32).collect()
+}
+
+/// Settings of an `AnnIndex`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AnnConfig {
+    /// Links kept per node on the upper layers; layer 0 keeps twice as many.
+    pub m: usize,
+    /// Candidates considered while linking a new node.
+    pub ef_construction: usize,
+    /// Candidates considered per search. Higher values find more of the true
+    /// nearest neighbours and take longer.
+    pub ef_search: usize,
+}
+
+impl Default for AnnConfig {
+    fn default() -> Self {
+        AnnConfig {
+            m: 16,
+            ef_construction: 100,
+            ef_search: 64,
+        }
+    }
+}
+
+/// One document in an `AnnIndex`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnnNode {
+    /// Unit-length embedding.
+    pub embedding: Vec<f32>,
+    /// Keys of the linked nodes on each layer, from layer 0 up to the node's top layer.
+    pub links: Vec<Vec<usize>>,
+}
+
+/// Approximate nearest-neighbour index over document embeddings (HNSW).
+///
+/// Nodes are linked into a hierarchy of proximity graphs: every node is on
+/// layer 0 and exponentially fewer on each layer above. A search walks
+/// greedily down from the top layer and then explores layer 0 with a
+/// candidate list of `ef_search` nodes, trading recall for latency.
+///
+/// Nodes are keyed by the caller's document ids, so the links can be stored
+/// as they are and the graph rebuilt with `from_nodes`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AnnIndex {
+    config: AnnConfig,
+    nodes: HashMap<usize, AnnNode>,
+    entry: Option<usize>,
+    // Keys inserted, relinked or removed since the last `take_changed`
+    #[serde(skip)]
+    changed: HashSet<usize>,
+}
+
+impl AnnIndex {
+    pub fn new(config: AnnConfig) -> Self {
+        AnnIndex {
+            config,
+            ..Self::default()
+        }
+    }
+
+    /// Rebuilds an index from stored nodes, as returned by `node`.
+    pub fn from_nodes(config: AnnConfig, nodes: impl IntoIterator<Item = (usize, AnnNode)>) -> Self {
+        let nodes: HashMap<usize, AnnNode> = nodes.into_iter().collect();
+        let entry = Self::highest_node(&nodes);
+        AnnIndex {
+            config,
+            nodes,
+            entry,
+            changed: HashSet::new(),
+        }
+    }
+
+    pub fn config(&self) -> AnnConfig {
+        self.config
+    }
+
+    /// Sets how many candidates searches consider, the recall versus latency knob.
+    pub fn set_ef_search(&mut self, ef_search: usize) {
+        self.config.ef_search = ef_search.max(1);
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn contains(&self, key: usize) -> bool {
+        self.nodes.contains_key(&key)
+    }
+
+    pub fn node(&self, key: usize) -> Option<&AnnNode> {
+        self.nodes.get(&key)
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = usize> + '_ {
+        self.nodes.keys().copied()
+    }
+
+    /// The keys inserted, relinked or removed since the last call, in key
+    /// order, so only those need to be written back to storage.
+    pub fn take_changed(&mut self) -> Vec<usize> {
+        let mut changed: Vec<usize> = self.changed.drain().collect();
+        changed.sort_unstable();
+        changed
+    }
+
+    /// Adds a document, replacing any embedding already stored for `key`.
+    /// Embeddings that are all zero cannot be compared and are left out.
+    pub fn insert(&mut self, key: usize, embedding: Vec<f32>) {
+        self.remove(key);
+        let Some(embedding) = normalize(embedding) else {
+            return;
+        };
+
+        let level = level(key, self.config.m);
+        self.nodes.insert(key, AnnNode { embedding: embedding.clone(), links: vec![Vec::new(); level + 1] });
+        self.changed.insert(key);
+        let Some(entry) = self.entry else {
+            self.entry = Some(key);
+            return;
+        };
+
+        // Descend greedily to the new node's top layer, then link it on every
+        // layer from there down to 0
+        let top = self.level_of(entry);
+        let mut entries = vec![entry];
+        for layer in (level + 1..=top).rev() {
+            entries = self.closest(&embedding, &entries, layer);
+        }
+        for layer in (0..=level.min(top)).rev() {
+            let mut found = self.search_layer(&embedding, &entries, self.config.ef_construction, layer);
+            found.retain(|neighbor| neighbor.key != key);
+            let neighbors = self.select_neighbors(&found, self.max_links(layer));
+            for &neighbor in &neighbors {
+                self.link(neighbor, key, layer);
+            }
+            if let Some(node) = self.nodes.get_mut(&key) {
+                node.links[layer] = neighbors;
+            }
+            if !found.is_empty() {
+                entries = found.iter().map(|neighbor| neighbor.key).collect();
+            }
+        }
+
+        if level > top {
+            self.entry = Some(key);
+        }
+    }
+
+    /// Embeds a document's frequency vector and adds it.
+    pub fn insert_vector(&mut self, key: usize, vector: &PrimeVector) {
+        self.insert(key, embed(vector));
+    }
+
+    /// Like `insert_vector`, for a `SparseVector`.
+    pub fn insert_vector_sparse(&mut self, key: usize, vector: &SparseVector) {
+        self.insert(key, embed_sparse(vector));
+    }
+
+    /// Removes a document, relinking the nodes it was linked to among its
+    /// other neighbours. Returns false if it was not in the index.
+    ///
+    /// Nodes that linked to it without a link back keep a dangling link,
+    /// which searches skip and the next relink of that node drops.
+    pub fn remove(&mut self, key: usize) -> bool {
+        let Some(removed) = self.nodes.remove(&key) else {
+            return false;
+        };
+        self.changed.insert(key);
+
+        for (layer, links) in removed.links.iter().enumerate() {
+            for &neighbor in links {
+                let Some(node) = self.nodes.get(&neighbor) else {
+                    continue;
+                };
+                if !node.links.get(layer).is_some_and(|keys| keys.contains(&key)) {
+                    continue;
+                }
+
+                let base = node.embedding.clone();
+                let keys: HashSet<usize> = node.links[layer]
+                    .iter()
+                    .chain(links)
+                    .copied()
+                    .filter(|&other| other != key && other != neighbor)
+                    .collect();
+                let candidates = self.neighbors_of(&base, keys);
+                let relinked = self.select_neighbors(&candidates, self.max_links(layer));
+                if let Some(node) = self.nodes.get_mut(&neighbor) {
+                    node.links[layer] = relinked;
+                }
+                self.changed.insert(neighbor);
+            }
+        }
+
+        if self.entry == Some(key) {
+            self.entry = Self::highest_node(&self.nodes);
+        }
+        true
+    }
+
+    /// Finds the `k` stored documents most similar to `query`, most similar
+    /// first, with their cosine similarity.
+    pub fn search(&self, query: &[f32], k: usize) -> Vec<(usize, f64)> {
+        self.search_with_ef(query, k, self.config.ef_search)
+    }
+
+    /// Like `search`, considering `ef` candidates instead of `ef_search`.
+    pub fn search_with_ef(&self, query: &[f32], k: usize, ef: usize) -> Vec<(usize, f64)> {
+        let (Some(entry), Some(query)) = (self.entry, normalize(query.to_vec())) else {
+            return Vec::new();
+        };
+        if k == 0 {
+            return Vec::new();
+        }
+
+        let mut entries = vec![entry];
+        for layer in (1..=self.level_of(entry)).rev() {
+            entries = self.closest(&query, &entries, layer);
+        }
+        self.search_layer(&query, &entries, ef.max(k), 0)
+            .into_iter()
+            .take(k)
+            .map(|neighbor| (neighbor.key, 1.0 - neighbor.distance as f64))
+            .collect()
+    }
+
+    /// The `k` documents most similar to a stored one, leaving it out.
+    pub fn nearest_to(&self, key: usize, k: usize) -> Vec<(usize, f64)> {
+        let Some(node) = self.nodes.get(&key) else {
+            return Vec::new();
+        };
+        let mut results = self.search(&node.embedding, k + 1);
+        results.retain(|&(other, _)| other != key);
+        results.truncate(k);
+        results
+    }
+
+    fn max_links(&self, layer: usize) -> usize {
+        if layer == 0 {
+            self.config.m * 2
+        } else {
+            self.config.m
+        }
+    }
+
+    fn level_of(&self, key: usize) -> usize {
+        self.nodes.get(&key).map_or(0, |node| node.links.len().saturating_sub(1))
+    }
+
+    // The node on the highest layer, the smallest key among equals
+    fn highest_node(nodes: &HashMap<usize, AnnNode>) -> Option<usize> {
+        nodes
+            .iter()
+            .max_by(|a, b| a.1.links.len().cmp(&b.1.links.len()).then(b.0.cmp(a.0)))
+            .map(|(&key, _)| key)
+    }
+
+    // The single closest node on a layer, for the greedy descent
+    fn closest(&self, query: &[f32], entries: &[usize], layer: usize) -> Vec<usize> {
+        match self.search_layer(query, entries, 1, layer).first() {
+            Some(neighbor) => vec![neighbor.key],
+            None => entries.to_vec(),
+        }
+    }
+
+    // Best-first search of one layer keeping the `ef` closest nodes found,
+    // closest first
+    fn search_layer(&self, query: &[f32], entries: &[usize], ef: usize, layer: usize) -> Vec<Neighbor> {
+        let mut visited: HashSet<usize> = entries.iter().copied().collect();
+        let mut candidates: BinaryHeap<Reverse<Neighbor>> = BinaryHeap::new();
+        let mut found: BinaryHeap<Neighbor> = BinaryHeap::new();
+        for &key in entries {
+            if let Some(node) = self.nodes.get(&key) {
+                let neighbor = Neighbor { distance: distance(query, &node.embedding), key };
+                candidates.push(Reverse(neighbor));
+                found.push(neighbor);
+            }
+        }
+
+        while let Some(Reverse(current)) = candidates.pop() {
+            if found.len() >= ef && found.peek().is_some_and(|worst| current.distance > worst.distance) {
+                break;
+            }
+            let Some(node) = self.nodes.get(&current.key) else {
+                continue;
+            };
+            for &key in node.links.get(layer).into_iter().flatten() {
+                if !visited.insert(key) {
+                    continue;
+                }
+                // Links to removed nodes are skipped
+                let Some(next) = self.nodes.get(&key) else {
+                    continue;
+                };
+                let neighbor = Neighbor { distance: distance(query, &next.embedding), key };
+                if found.len() < ef || found.peek().is_some_and(|worst| neighbor.distance < worst.distance) {
+                    candidates.push(Reverse(neighbor));
+                    found.push(neighbor);
+                    if found.len() > ef {
+                        found.pop();
+                    }
+                }
+            }
+        }
+
+        found.into_sorted_vec()
+    }
+
+    // The stored nodes among `keys` by distance to `base`, closest first
+    fn neighbors_of(&self, base: &[f32], keys: impl IntoIterator<Item = usize>) -> Vec<Neighbor> {
+        let mut neighbors: Vec<Neighbor> = keys
+            .into_iter()
+            .filter_map(|key| Some(Neighbor { distance: distance(base, &self.nodes.get(&key)?.embedding), key }))
+            .collect();
+        neighbors.sort_unstable();
+        neighbors
+    }
+
+    // Picks up to `m` of the candidates (closest first), preferring ones that
+    // are closer to the base than to any already picked so the links spread
+    // in different directions, then filling up with the closest of the rest
+    fn select_neighbors(&self, candidates: &[Neighbor], m: usize) -> Vec<usize> {
+        let mut selected: Vec<Neighbor> = Vec::with_capacity(m);
+        let mut skipped: Vec<Neighbor> = Vec::new();
+        for &candidate in candidates {
+            if selected.len() >= m {
+                break;
+            }
+            let Some(node) = self.nodes.get(&candidate.key) else {
+                continue;
+            };
+            let diverse = selected.iter().all(|picked| {
+                self.nodes
+                    .get(&picked.key)
+                    .is_none_or(|other| distance(&node.embedding, &other.embedding) > candidate.distance)
+            });
+            if diverse {
+                selected.push(candidate);
+            } else {
+                skipped.push(candidate);
+            }
+        }
+        let missing = m - selected.len();
+        selected.extend(skipped.into_iter().take(missing));
+        selected.into_iter().map(|neighbor| neighbor.key).collect()
+    }
+
+    // Adds a link from one node to another, pruning the node's links back to
+    // the layer's maximum
+    fn link(&mut self, from: usize, to: usize, layer: usize) {
+        let max_links = self.max_links(layer);
+        let Some(node) = self.nodes.get_mut(&from) else {
+            return;
+        };
+        let Some(links) = node.links.get_mut(layer) else {
+            return;
+        };
+        if !links.contains(&to) {
+            links.push(to);
+        }
+        self.changed.insert(from);
+
+        if links.len() > max_links {
+            let keys = links.clone();
+            let base = node.embedding.clone();
+            let candidates = self.neighbors_of(&base, keys);
+            let pruned = self.select_neighbors(&candidates, max_links);
+            if let Some(node) = self.nodes.get_mut(&from) {
+                node.links[layer] = pruned;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Neighbor {
+    distance: f32,
+    key: usize,
+}
+
+impl PartialEq for Neighbor {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Neighbor {}
+
+impl PartialOrd for Neighbor {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Neighbor {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance.total_cmp(&other.distance).then(self.key.cmp(&other.key))
+    }
+}
+
+// Cosine distance between unit-length embeddings. The dot product is summed
+// in eight lanes, which the compiler can vectorize
+fn distance(a: &[f32], b: &[f32]) -> f32 {
+    let mut lanes = [0.0f32; 8];
+    let (a_chunks, b_chunks) = (a.chunks_exact(8), b.chunks_exact(8));
+    let tail: f32 = a_chunks.remainder().iter().zip(b_chunks.remainder()).map(|(x, y)| x * y).sum();
+    for (x, y) in a_chunks.zip(b_chunks) {
+        for lane in 0..8 {
+            lanes[lane] += x[lane] * y[lane];
+        }
+    }
+    1.0 - (lanes.iter().sum::<f32>() + tail)
+}
+
+fn normalize(mut embedding: Vec<f32>) -> Option<Vec<f32>> {
+    let norm = embedding.iter().map(|value| value * value).sum::<f32>().sqrt();
+    if norm == 0.0 || !norm.is_finite() {
+        return None;
+    }
+    for value in &mut embedding {
+        *value /= norm;
+    }
+    Some(embedding)
+}
+
+// Top layer of a node: geometrically distributed with ratio 1/m, drawn from
+// a hash of the key
+fn level(key: usize, m: usize) -> usize {
+    let uniform = (mix_hash(key as u64 ^ LEVEL_SEED) >> 11) as f64 / (1u64 << 53) as f64;
+    let scale = 1.0 / (m.max(2) as f64).ln();
+    ((-(1.0 - uniform).ln() * scale) as usize).min(MAX_LEVEL)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    const DIMENSION: usize = 16;
+
+    fn embeddings(rng: &mut StdRng, count: usize) -> Vec<Vec<f32>> {
+        (0..count)
+            .map(|_| (0..DIMENSION).map(|_| rng.gen_range(-1.0..1.0)).collect())
+            .collect()
+    }
+
+    fn build(embeddings: &[Vec<f32>]) -> AnnIndex {
+        let mut index = AnnIndex::new(AnnConfig { m: 8, ef_construction: 40, ef_search: 32 });
+        for (key, embedding) in embeddings.iter().enumerate() {
+            index.insert(key, embedding.clone());
+        }
+        index
+    }
+
+    // The `k` stored keys most similar to the query, by comparing it with each
+    fn brute_force(index: &AnnIndex, query: &[f32], k: usize) -> Vec<usize> {
+        let query = normalize(query.to_vec()).unwrap();
+        let mut keys: Vec<(usize, f32)> = index
+            .nodes
+            .iter()
+            .map(|(&key, node)| (key, distance(&query, &node.embedding)))
+            .collect();
+        keys.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+        keys.into_iter().take(k).map(|(key, _)| key).collect()
+    }
+
+    // Share of the true nearest neighbours the index finds, over all queries
+    fn recall(index: &AnnIndex, queries: &[Vec<f32>], k: usize) -> f64 {
+        let found: usize = queries
+            .iter()
+            .map(|query| {
+                let expected = brute_force(index, query, k);
+                let results = index.search(query, k);
+                results.iter().filter(|(key, _)| expected.contains(key)).count()
+            })
+            .sum();
+        found as f64 / (queries.len() * k) as f64
+    }
+
+    #[test]
+    fn search_recalls_the_brute_force_neighbours() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let index = build(&embeddings(&mut rng, 500));
+        let queries = embeddings(&mut rng, 100);
+
+        let recall = recall(&index, &queries, 10);
+        assert!(recall >= 0.95, "recall {}", recall);
+
+        // Similarities are the cosines, most similar first
+        let results = index.search(&queries[0], 10);
+        assert_eq!(results.len(), 10);
+        assert!(results.windows(2).all(|pair| pair[0].1 >= pair[1].1));
+        let query = normalize(queries[0].clone()).unwrap();
+        for (key, similarity) in results {
+            let expected = 1.0 - distance(&query, &index.nodes[&key].embedding) as f64;
+            assert!((similarity - expected).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn search_after_removing_the_entry_node() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut index = build(&embeddings(&mut rng, 500));
+        let queries = embeddings(&mut rng, 50);
+        let first_entry = index.entry.unwrap();
+        let embedding = index.nodes[&first_entry].embedding.clone();
+
+        let mut removed = Vec::new();
+        for _ in 0..5 {
+            let entry = index.entry.unwrap();
+            assert!(index.remove(entry));
+            removed.push(entry);
+
+            let entry = index.entry.unwrap();
+            assert!(index.contains(entry));
+            assert!(!removed.contains(&entry));
+            assert_eq!(index.level_of(entry), index.nodes.values().map(|node| node.links.len() - 1).max().unwrap());
+        }
+        assert_eq!(index.len(), 495);
+
+        for query in &queries {
+            let results = index.search(query, 10);
+            assert_eq!(results.len(), 10);
+            assert!(results.iter().all(|(key, _)| !removed.contains(key)));
+        }
+        let recall = recall(&index, &queries, 10);
+        assert!(recall >= 0.95, "recall {}", recall);
+
+        // The first entry's own embedding now finds its neighbours instead
+        let results = index.search(&embedding, 5);
+        assert_eq!(results.len(), 5);
+        assert!(results.iter().all(|&(key, _)| key != first_entry));
+    }
+
+    #[test]
+    fn search_of_an_emptied_index_finds_nothing() {
+        let mut rng = StdRng::seed_from_u64(9);
+        let mut index = build(&embeddings(&mut rng, 20));
+        for key in 0..20 {
+            assert!(index.remove(key));
+        }
+        assert!(index.is_empty());
+        assert_eq!(index.entry, None);
+        assert!(index.search(&embeddings(&mut rng, 1)[0], 5).is_empty());
+    }
+}
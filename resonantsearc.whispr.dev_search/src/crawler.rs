@@ -11,6 +11,7 @@ use std::error::Error;
 use std::fmt;
 use futures::stream::{self, StreamExt};
 use rand::Rng;
+use crate::language::detect_language;
 
 /// A simple error type for crawling.
 #[derive(Debug)]
@@ -32,7 +33,11 @@ unsafe impl Sync for CrawlerError {}
 pub struct CrawledDocument {
     pub url: String,
     pub title: String,
+    /// Text of the page's h1-h3 headings, one per line.
+    pub headings: String,
     pub text: String,
+    /// Detected language code ("en", "de", "zh", ...), empty if unknown.
+    pub lang: String,
 }
 
 /// A web crawler that fetches and extracts content from URLs.
@@ -347,6 +352,20 @@ impl Crawler {
                            .map(|t| t.text().collect::<String>())
                            .unwrap_or_else(|| url.to_string());
 
+        // Extract section headings
+        let heading_selector = Selector::parse("h1, h2, h3").unwrap();
+        let headings = fragment.select(&heading_selector)
+                               .map(|heading| heading.text().collect::<String>().trim().to_string())
+                               .filter(|heading| !heading.is_empty())
+                               .collect::<Vec<_>>()
+                               .join("\n");
+
+        // Language declared by the page, used when detection is unsure
+        let html_selector = Selector::parse("html").unwrap();
+        let declared_lang = fragment.select(&html_selector)
+                                    .next()
+                                    .and_then(|html| html.value().attr("lang"));
+
         if text.trim().is_empty() {
             Ok(None)
         } else {
@@ -373,10 +392,13 @@ impl Crawler {
                 }
             }
 
+            let lang = detect_language(&text, declared_lang);
             Ok(Some(CrawledDocument {
                 url: url.to_string(),
                 title,
+                headings,
                 text,
+                lang,
             }))
         }
     }
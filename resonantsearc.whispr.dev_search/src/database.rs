@@ -1,13 +1,26 @@
 // src/database.rs
 
-use crate::prime_hilbert::{PrimeVector, BiorthogonalVector};
+use crate::prime_hilbert::{PrimeVector, BiorthogonalVector, BiorthogonalViews, remap_vector, remap_ngram_vector, remap_biorthogonal, token_primes};
+use crate::postings::{Postings, remap_postings};
+use crate::passages::DocumentPassages;
+use crate::density::DocumentDensity;
+use crate::sparse_vector::SparseVector;
+use crate::history::{content_change, content_hash, Fetch, UpdateStats};
+use crate::fingerprint::{DuplicateIndex, Fingerprint};
+use crate::analyzer::{Analyzer, AnalyzerConfig};
+use crate::language::language_analyzers;
+use crate::tokenizer::{PrimeTokenizer, VocabularyBuilder, VocabularyStamp};
+use crate::weighting::{document_length, CorpusStats};
+use crate::ann_index::{AnnConfig, AnnIndex, AnnNode};
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 use std::fs;
-use std::io;
+use std::io::{self, Read};
 use rusqlite::{params, Connection, Result as SqlResult, OptionalExtension};
 use serde::{Serialize, Deserialize};
 use serde_json;
 use std::time::{SystemTime, UNIX_EPOCH};
+use flate2::read::GzDecoder;
 
 /// Document representation for database storage
 #[derive(Debug, Serialize, Deserialize)]
@@ -15,10 +28,16 @@ pub struct StoredDocument {
     pub id: Option<i64>,
     pub url: String,
     pub title: String,
+    pub headings: String,            // Headings that went into the title view, one per line
     pub text_snippet: String,
     pub compressed_text: Vec<u8>,
     pub vector_data: String,         // Serialized PrimeVector
+    pub ngram_data: String,          // Serialized PrimeVector of composite n-gram keys
     pub biorthogonal_data: String,   // Serialized BiorthogonalVector
+    pub postings_data: String,       // Serialized Postings (token positions)
+    pub passages_data: String,       // Serialized DocumentPassages (entropy profile and passages)
+    pub density_data: String,        // Serialized DocumentDensity (the passages as a mixed state)
+    pub lang: String,                // Detected language code, empty if unknown
     pub entropy: f64,
     pub reversibility: f64,
     pub buffering: f64,
@@ -44,7 +63,13 @@ impl DocumentDatabase {
         // Create tables if they don't exist
         Self::initialize_database(&conn)?;
         
-        Ok(DocumentDatabase { conn })
+        // Databases from before term statistics (or collection frequencies) were kept get them computed once
+        let db = DocumentDatabase { conn };
+        if db.get_meta("corpus_documents")?.is_none() || db.missing_collection_frequencies()? {
+            db.rebuild_term_stats()?;
+        }
+        
+        Ok(db)
     }
     
     /// Initialize database schema
@@ -55,10 +80,16 @@ impl DocumentDatabase {
                 id INTEGER PRIMARY KEY,
                 url TEXT UNIQUE NOT NULL,
                 title TEXT NOT NULL,
+                headings TEXT NOT NULL DEFAULT '',
                 text_snippet TEXT NOT NULL,
                 compressed_text BLOB NOT NULL,
                 vector_data TEXT NOT NULL,
+                ngram_data TEXT NOT NULL DEFAULT '{}',
                 biorthogonal_data TEXT NOT NULL,
+                postings_data TEXT NOT NULL DEFAULT '{}',
+                passages_data TEXT NOT NULL DEFAULT '{}',
+                density_data TEXT NOT NULL DEFAULT '{}',
+                lang TEXT NOT NULL DEFAULT '',
                 entropy REAL NOT NULL,
                 reversibility REAL NOT NULL,
                 buffering REAL NOT NULL,
@@ -68,6 +99,79 @@ impl DocumentDatabase {
             [],
         )?;
         
+        // Columns added after the first release
+        Self::ensure_column(conn, "documents", "postings_data", "TEXT NOT NULL DEFAULT '{}'")?;
+        Self::ensure_column(conn, "documents", "lang", "TEXT NOT NULL DEFAULT ''")?;
+        Self::ensure_column(conn, "documents", "passages_data", "TEXT NOT NULL DEFAULT '{}'")?;
+        Self::ensure_column(conn, "documents", "density_data", "TEXT NOT NULL DEFAULT '{}'")?;
+        Self::ensure_column(conn, "documents", "headings", "TEXT NOT NULL DEFAULT ''")?;
+        
+        // Composite n-gram keys used to be stored in the document vectors
+        if Self::ensure_column(conn, "documents", "ngram_data", "TEXT NOT NULL DEFAULT '{}'")? {
+            Self::split_ngram_keys(conn)?;
+        }
+        
+        // Vocabulary table so query primes match the primes stored in vector_data
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS vocabulary (
+                prime INTEGER PRIMARY KEY,
+                token TEXT UNIQUE NOT NULL
+            )",
+            [],
+        )?;
+        
+        // Key/value metadata about the index (vocabulary stamp, etc.)
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS index_meta (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL
+            )",
+            [],
+        )?;
+        
+        // Number of documents containing each word prime, for IDF and BM25 weighting,
+        // and its occurrences over all documents, for the language-model collection model
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS term_stats (
+                prime INTEGER PRIMARY KEY,
+                document_frequency INTEGER NOT NULL,
+                collection_frequency INTEGER NOT NULL DEFAULT 0
+            )",
+            [],
+        )?;
+        Self::ensure_column(conn, "term_stats", "collection_frequency", "INTEGER NOT NULL DEFAULT 0")?;
+        
+        // Nearest-neighbour graph over document embeddings, one node per document
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS document_embeddings (
+                document_id INTEGER PRIMARY KEY,
+                embedding BLOB NOT NULL,
+                links TEXT NOT NULL
+            )",
+            [],
+        )?;
+        
+        // MinHash fingerprint of each document's tokens, to find near-duplicates
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS document_fingerprints (
+                document_id INTEGER PRIMARY KEY,
+                minhash BLOB NOT NULL
+            )",
+            [],
+        )?;
+        
+        // Every fetch of a URL, to tell how often and how much its content changes
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS fetch_history (
+                id INTEGER PRIMARY KEY,
+                url TEXT NOT NULL,
+                fetched_at INTEGER NOT NULL,
+                content_hash INTEGER NOT NULL,
+                change REAL NOT NULL
+            )",
+            [],
+        )?;
+        
         // Create full-text search index
         conn.execute(
             "CREATE VIRTUAL TABLE IF NOT EXISTS document_fts USING fts5(
@@ -111,30 +215,123 @@ impl DocumentDatabase {
         // Create indices for faster queries
         conn.execute("CREATE INDEX IF NOT EXISTS idx_documents_url ON documents(url)", [])?;
         conn.execute("CREATE INDEX IF NOT EXISTS idx_documents_timestamp ON documents(timestamp)", [])?;
+        conn.execute("CREATE INDEX IF NOT EXISTS idx_documents_lang ON documents(lang)", [])?;
+        conn.execute("CREATE INDEX IF NOT EXISTS idx_fetch_history_url ON fetch_history(url)", [])?;
+        
+        Ok(())
+    }
+    
+    /// Add a column to an existing table if an older database lacks it,
+    /// returning whether it was added
+    fn ensure_column(conn: &Connection, table: &str, column: &str, definition: &str) -> SqlResult<bool> {
+        let exists = conn.prepare(&format!("SELECT {} FROM {} LIMIT 0", column, table)).is_ok();
+        if !exists {
+            conn.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition), [])?;
+        }
+        
+        Ok(!exists)
+    }
+    
+    /// Move the composite n-gram keys of documents stored with them in their
+    /// vectors to their own column
+    fn split_ngram_keys(conn: &Connection) -> SqlResult<()> {
+        let documents = {
+            let mut stmt = conn.prepare("SELECT id, vector_data FROM documents")?;
+            let rows = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?;
+            rows.collect::<SqlResult<Vec<_>>>()?
+        };
+        
+        for (id, vector_json) in documents {
+            let (words, ngrams): (PrimeVector, PrimeVector) = vector_from_json(&vector_json)?
+                .into_iter()
+                .partition(|&(key, _)| primal::is_prime(key));
+            if ngrams.is_empty() {
+                continue;
+            }
+            
+            let words_json = serde_json::to_string(&words)
+                .map_err(|e| rusqlite::Error::InvalidParameterName(e.to_string()))?;
+            let ngrams_json = serde_json::to_string(&ngrams)
+                .map_err(|e| rusqlite::Error::InvalidParameterName(e.to_string()))?;
+            conn.execute(
+                "UPDATE documents SET vector_data = ?, ngram_data = ? WHERE id = ?",
+                params![words_json, ngrams_json, id],
+            )?;
+        }
         
         Ok(())
     }
     
-    /// Store a document in the database
+    /// Store a document in the database, keeping the term statistics, the
+    /// URL's fetch history and the document's fingerprint up to date
+    ///
+    /// All of it is one transaction, or part of the caller's if one is open
+    /// (as during an import).
     pub fn store_document(&self, document: &StoredDocument) -> SqlResult<i64> {
+        if !self.conn.is_autocommit() {
+            return self.store_document_in_transaction(document);
+        }
+        
+        self.begin_transaction()?;
+        
+        match self.store_document_in_transaction(document) {
+            Ok(id) => {
+                self.commit_transaction()?;
+                Ok(id)
+            }
+            Err(e) => {
+                self.rollback_transaction()?;
+                Err(e)
+            }
+        }
+    }
+    
+    fn store_document_in_transaction(&self, document: &StoredDocument) -> SqlResult<i64> {
         let now = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap_or_default()
             .as_secs();
-            
-        let result = self.conn.execute(
-            "INSERT OR REPLACE INTO documents (
-                url, title, text_snippet, compressed_text, 
-                vector_data, biorthogonal_data, entropy,
+        
+        // A replaced document no longer counts towards the statistics
+        let old: Option<(i64, String, String)> = self.conn.query_row(
+            "SELECT id, postings_data, vector_data FROM documents WHERE url = ?",
+            params![document.url],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        ).optional()?;
+        if let Some((old_id, old_postings, _)) = &old {
+            self.update_term_stats(&postings_from_json(old_postings)?, -1)?;
+            // Its embedding is of the old content; the caller or the next sync embeds it again
+            self.conn.execute("DELETE FROM document_embeddings WHERE document_id = ?", params![old_id])?;
+        }
+        let postings = postings_from_json(&document.postings_data)?;
+        
+        // A URL stored again keeps its id, so rows keyed by it stay its own
+        self.conn.execute(
+            "INSERT INTO documents (
+                url, title, headings, text_snippet, compressed_text, 
+                vector_data, ngram_data, biorthogonal_data, postings_data, passages_data, density_data, lang, entropy,
                 reversibility, buffering, timestamp, created_at
-            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT(url) DO UPDATE SET
+                title = excluded.title, headings = excluded.headings, text_snippet = excluded.text_snippet, compressed_text = excluded.compressed_text,
+                vector_data = excluded.vector_data, ngram_data = excluded.ngram_data,
+                biorthogonal_data = excluded.biorthogonal_data, postings_data = excluded.postings_data,
+                passages_data = excluded.passages_data, density_data = excluded.density_data, lang = excluded.lang,
+                entropy = excluded.entropy, reversibility = excluded.reversibility, buffering = excluded.buffering,
+                timestamp = excluded.timestamp",
             params![
                 document.url,
                 document.title,
+                document.headings,
                 document.text_snippet,
                 document.compressed_text,
                 document.vector_data,
+                document.ngram_data,
                 document.biorthogonal_data,
+                document.postings_data,
+                document.passages_data,
+                document.density_data,
+                document.lang,
                 document.entropy,
                 document.reversibility,
                 document.buffering,
@@ -142,8 +339,255 @@ impl DocumentDatabase {
                 now
             ],
         )?;
+        let id = match &old {
+            Some((old_id, _, _)) => *old_id,
+            None => self.conn.last_insert_rowid(),
+        };
+        
+        self.update_term_stats(&postings, 1)?;
+        self.record_fetch(document, old.as_ref().map(|(_, _, old_vector)| old_vector.as_str()))?;
+        self.store_fingerprint(id, &Fingerprint::from_postings(&postings))?;
         
-        Ok(self.conn.last_insert_rowid())
+        Ok(id)
+    }
+    
+    /// Add a fetch of the document's URL to its history, with how far its words moved
+    /// from the version stored before (`old_vector_data`) if the content changed
+    fn record_fetch(&self, document: &StoredDocument, old_vector_data: Option<&str>) -> SqlResult<()> {
+        let text = decompress_text(&document.compressed_text).unwrap_or_default();
+        let mut fetch = Fetch { timestamp: document.timestamp, content_hash: content_hash(&text), change: 0.0 };
+        
+        // Only a change from a recorded fetch counts; the first one has nothing to compare with
+        let last_hash: Option<i64> = self.conn.query_row(
+            "SELECT content_hash FROM fetch_history WHERE url = ? ORDER BY fetched_at DESC, id DESC LIMIT 1",
+            params![document.url],
+            |row| row.get(0),
+        ).optional()?;
+        if let (Some(last_hash), Some(old_vector_data)) = (last_hash, old_vector_data) {
+            if last_hash as u64 != fetch.content_hash {
+                fetch.change = content_change(&vector_from_json(old_vector_data)?, &vector_from_json(&document.vector_data)?);
+            }
+        }
+        
+        self.conn.execute(
+            "INSERT INTO fetch_history (url, fetched_at, content_hash, change) VALUES (?, ?, ?, ?)",
+            params![document.url, fetch.timestamp, fetch.content_hash as i64, fetch.change],
+        )?;
+        
+        Ok(())
+    }
+    
+    /// Every recorded fetch of a URL, oldest first
+    pub fn fetch_history(&self, url: &str) -> SqlResult<Vec<Fetch>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT fetched_at, content_hash, change FROM fetch_history WHERE url = ? ORDER BY fetched_at, id"
+        )?;
+        let rows = stmt.query_map(params![url], |row| {
+            Ok(Fetch {
+                timestamp: row.get(0)?,
+                content_hash: row.get::<_, i64>(1)? as u64,
+                change: row.get(2)?,
+            })
+        })?;
+        
+        let mut history = Vec::new();
+        for row in rows {
+            history.push(row?);
+        }
+        
+        Ok(history)
+    }
+    
+    /// How often and how much a URL's content changed across its recorded fetches
+    pub fn update_stats(&self, url: &str) -> SqlResult<UpdateStats> {
+        Ok(UpdateStats::from_history(&self.fetch_history(url)?))
+    }
+    
+    /// Store the fingerprint of the document with `id`, replacing any it had
+    fn store_fingerprint(&self, id: i64, fingerprint: &Fingerprint) -> SqlResult<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO document_fingerprints (document_id, minhash) VALUES (?, ?)",
+            params![id, fingerprint.to_bytes()],
+        )?;
+        
+        Ok(())
+    }
+    
+    /// Get the fingerprint stored for a document, if it has one
+    pub fn get_fingerprint(&self, id: i64) -> SqlResult<Option<Fingerprint>> {
+        self.conn.query_row(
+            "SELECT minhash FROM document_fingerprints WHERE document_id = ?",
+            params![id],
+            |row| Ok(Fingerprint::from_bytes(&row.get::<_, Vec<u8>>(0)?)),
+        ).optional()
+    }
+    
+    /// Load the stored fingerprints into an index that finds near-duplicates
+    pub fn load_duplicate_index(&self) -> SqlResult<DuplicateIndex> {
+        let mut stmt = self.conn.prepare("SELECT document_id, minhash FROM document_fingerprints")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, Vec<u8>>(1)?))
+        })?;
+        
+        let mut index = DuplicateIndex::new();
+        for row in rows {
+            let (id, minhash) = row?;
+            index.insert(id as usize, Fingerprint::from_bytes(&minhash));
+        }
+        
+        Ok(index)
+    }
+    
+    /// Bring a duplicate index in line with the stored documents
+    ///
+    /// Documents stored without a fingerprint get one from their postings,
+    /// and documents that are gone are removed. Returns how many were added or removed.
+    pub fn sync_duplicate_index(&self, index: &mut DuplicateIndex) -> SqlResult<usize> {
+        let stored: HashSet<usize> = {
+            let mut stmt = self.conn.prepare("SELECT id FROM documents")?;
+            let rows = stmt.query_map([], |row| row.get::<_, i64>(0))?;
+            rows.map(|id| id.map(|id| id as usize)).collect::<SqlResult<_>>()?
+        };
+        
+        let gone: Vec<usize> = index.ids().filter(|id| !stored.contains(id)).collect();
+        for &id in &gone {
+            index.remove(id);
+            self.conn.execute("DELETE FROM document_fingerprints WHERE document_id = ?", params![id as i64])?;
+        }
+        
+        let mut missing: Vec<usize> = stored.into_iter().filter(|&id| !index.contains(id)).collect();
+        missing.sort_unstable();
+        let mut stmt = self.conn.prepare_cached("SELECT postings_data FROM documents WHERE id = ?")?;
+        for &id in &missing {
+            let postings_json: String = stmt.query_row(params![id as i64], |row| row.get(0))?;
+            let fingerprint = Fingerprint::from_postings(&postings_from_json(&postings_json)?);
+            self.store_fingerprint(id as i64, &fingerprint)?;
+            index.insert(id, fingerprint);
+        }
+        
+        Ok(gone.len() + missing.len())
+    }
+    
+    /// Groups of stored documents that are near-duplicates of each other, by id
+    pub fn duplicate_clusters(&self) -> SqlResult<Vec<Vec<i64>>> {
+        let mut index = self.load_duplicate_index()?;
+        self.sync_duplicate_index(&mut index)?;
+        
+        Ok(index.clusters()
+            .into_iter()
+            .map(|cluster| cluster.into_iter().map(|id| id as i64).collect())
+            .collect())
+    }
+    
+    /// Add (`delta` 1) or remove (`delta` -1) one document's words in the term statistics
+    fn update_term_stats(&self, postings: &Postings, delta: i64) -> SqlResult<()> {
+        // Documents without positions are left out of the statistics
+        if postings.is_empty() {
+            return Ok(());
+        }
+        
+        let mut stmt = self.conn.prepare_cached(
+            "INSERT INTO term_stats (prime, document_frequency, collection_frequency) VALUES (?1, ?2, ?3)
+             ON CONFLICT(prime) DO UPDATE SET document_frequency = document_frequency + ?2,
+                                              collection_frequency = collection_frequency + ?3"
+        )?;
+        for (&prime, positions) in postings {
+            stmt.execute(params![prime as i64, delta, delta * positions.len() as i64])?;
+        }
+        if delta < 0 {
+            self.conn.execute("DELETE FROM term_stats WHERE document_frequency <= 0", [])?;
+        }
+        
+        let documents = self.get_meta_number("corpus_documents")? + delta;
+        let length = self.get_meta_number("corpus_length")? + delta * document_length(postings) as i64;
+        self.set_meta("corpus_documents", &documents.max(0).to_string())?;
+        self.set_meta("corpus_length", &length.max(0).to_string())?;
+        
+        Ok(())
+    }
+    
+    /// Corpus statistics with the document and collection frequencies of `primes`
+    /// (other primes are left out)
+    pub fn corpus_stats(&self, primes: &[u64]) -> SqlResult<CorpusStats> {
+        let mut stats = CorpusStats::new();
+        stats.document_count = self.get_meta_number("corpus_documents")? as usize;
+        stats.total_length = self.get_meta_number("corpus_length")? as u64;
+        
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT document_frequency, collection_frequency FROM term_stats WHERE prime = ?"
+        )?;
+        for &prime in primes {
+            let frequencies: Option<(i64, i64)> = stmt
+                .query_row(params![prime as i64], |row| Ok((row.get(0)?, row.get(1)?)))
+                .optional()?;
+            if let Some((document_frequency, collection_frequency)) = frequencies {
+                stats.document_frequency.insert(prime, document_frequency as usize);
+                stats.collection_frequency.insert(prime, collection_frequency as u64);
+            }
+        }
+        
+        Ok(stats)
+    }
+    
+    /// Whether the term statistics predate collection frequencies: every word
+    /// that is counted occurs at least once
+    fn missing_collection_frequencies(&self) -> SqlResult<bool> {
+        self.conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM term_stats WHERE collection_frequency <= 0)",
+            [],
+            |row| row.get(0),
+        )
+    }
+    
+    /// Recompute the term statistics from the postings of every stored document
+    ///
+    /// In one transaction, or as part of the caller's if one is open.
+    pub fn rebuild_term_stats(&self) -> SqlResult<()> {
+        if !self.conn.is_autocommit() {
+            return self.rebuild_term_stats_in_transaction();
+        }
+        
+        self.begin_transaction()?;
+        
+        match self.rebuild_term_stats_in_transaction() {
+            Ok(()) => self.commit_transaction(),
+            Err(e) => {
+                self.rollback_transaction()?;
+                Err(e)
+            }
+        }
+    }
+    
+    fn rebuild_term_stats_in_transaction(&self) -> SqlResult<()> {
+        let mut stats = CorpusStats::new();
+        {
+            let mut stmt = self.conn.prepare("SELECT postings_data FROM documents")?;
+            let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+            for row in rows {
+                stats.add_document(&postings_from_json(&row?)?);
+            }
+        }
+        
+        self.conn.execute("DELETE FROM term_stats", [])?;
+        let mut stmt = self.conn.prepare_cached(
+            "INSERT INTO term_stats (prime, document_frequency, collection_frequency) VALUES (?, ?, ?)"
+        )?;
+        for (&prime, &frequency) in &stats.document_frequency {
+            stmt.execute(params![prime as i64, frequency as i64, stats.collection_frequency(prime) as i64])?;
+        }
+        self.set_meta("corpus_documents", &stats.document_count.to_string())?;
+        self.set_meta("corpus_length", &stats.total_length.to_string())?;
+        
+        Ok(())
+    }
+    
+    /// Get the id of the document stored for a URL
+    pub fn get_document_id(&self, url: &str) -> SqlResult<Option<i64>> {
+        self.conn.query_row(
+            "SELECT id FROM documents WHERE url = ?",
+            params![url],
+            |row| row.get(0),
+        ).optional()
     }
     
     /// Retrieve a document by URL
@@ -151,7 +595,7 @@ impl DocumentDatabase {
         let mut stmt = self.conn.prepare(
             "SELECT id, url, title, text_snippet, compressed_text, 
                     vector_data, biorthogonal_data, entropy,
-                    reversibility, buffering, timestamp
+                    reversibility, buffering, timestamp, postings_data, lang, passages_data, density_data, ngram_data, headings
              FROM documents 
              WHERE url = ?"
         )?;
@@ -161,10 +605,16 @@ impl DocumentDatabase {
                 id: Some(row.get(0)?),
                 url: row.get(1)?,
                 title: row.get(2)?,
+                headings: row.get(16)?,
                 text_snippet: row.get(3)?,
                 compressed_text: row.get(4)?,
                 vector_data: row.get(5)?,
+                ngram_data: row.get(15)?,
                 biorthogonal_data: row.get(6)?,
+                postings_data: row.get(11)?,
+                passages_data: row.get(13)?,
+                density_data: row.get(14)?,
+                lang: row.get(12)?,
                 entropy: row.get(7)?,
                 reversibility: row.get(8)?,
                 buffering: row.get(9)?,
@@ -173,29 +623,35 @@ impl DocumentDatabase {
         }).optional()
     }
     
-    /// Text search using the FTS index
-    pub fn text_search(&self, query: &str, limit: usize) -> SqlResult<Vec<StoredDocument>> {
+    /// Text search using the FTS index, optionally limited to documents in one language
+    pub fn text_search(&self, query: &str, limit: usize, lang: Option<&str>) -> SqlResult<Vec<StoredDocument>> {
         let mut stmt = self.conn.prepare(
             "SELECT d.id, d.url, d.title, d.text_snippet, d.compressed_text, 
                     d.vector_data, d.biorthogonal_data, d.entropy,
                     d.reversibility, d.buffering, d.timestamp,
-                    rank
+                    d.postings_data, d.lang, d.passages_data, d.density_data, d.ngram_data, d.headings, rank
              FROM document_fts
              JOIN documents d ON document_fts.rowid = d.id
-             WHERE document_fts MATCH ?
+             WHERE document_fts MATCH ?1 AND (?3 IS NULL OR d.lang = ?3)
              ORDER BY rank
-             LIMIT ?"
+             LIMIT ?2"
         )?;
         
-        let rows = stmt.query_map(params![query, limit as i64], |row| {
+        let rows = stmt.query_map(params![query, limit as i64, lang], |row| {
             Ok(StoredDocument {
                 id: Some(row.get(0)?),
                 url: row.get(1)?,
                 title: row.get(2)?,
+                headings: row.get(16)?,
                 text_snippet: row.get(3)?,
                 compressed_text: row.get(4)?,
                 vector_data: row.get(5)?,
+                ngram_data: row.get(15)?,
                 biorthogonal_data: row.get(6)?,
+                postings_data: row.get(11)?,
+                passages_data: row.get(13)?,
+                density_data: row.get(14)?,
+                lang: row.get(12)?,
                 entropy: row.get(7)?,
                 reversibility: row.get(8)?,
                 buffering: row.get(9)?,
@@ -217,14 +673,21 @@ impl DocumentDatabase {
         stmt.query_row([], |row| row.get(0))
     }
     
+    /// The languages of the stored documents
+    pub fn languages(&self) -> SqlResult<Vec<String>> {
+        let mut stmt = self.conn.prepare("SELECT DISTINCT lang FROM documents ORDER BY lang")?;
+        let langs = stmt.query_map([], |row| row.get(0))?;
+        langs.collect()
+    }
+    
     /// Get all document vectors for batch operations
-    pub fn get_all_document_vectors(&self) -> SqlResult<Vec<(i64, PrimeVector)>> {
+    pub fn get_all_document_vectors(&self) -> SqlResult<Vec<(i64, SparseVector)>> {
         let mut stmt = self.conn.prepare("SELECT id, vector_data FROM documents")?;
         
         let rows = stmt.query_map([], |row| {
             let id: i64 = row.get(0)?;
             let vector_json: String = row.get(1)?;
-            let vector: PrimeVector = serde_json::from_str(&vector_json)
+            let vector: SparseVector = serde_json::from_str(&vector_json)
                 .map_err(|e| rusqlite::Error::InvalidParameterName(e.to_string()))?;
                 
             Ok((id, vector))
@@ -251,6 +714,19 @@ impl DocumentDatabase {
         Ok(())
     }
     
+    /// Update the composite n-gram keys of a document
+    pub fn update_document_ngrams(&self, id: i64, ngrams: &SparseVector) -> SqlResult<()> {
+        let ngram_json = serde_json::to_string(ngrams)
+            .map_err(|e| rusqlite::Error::InvalidParameterName(e.to_string()))?;
+            
+        self.conn.execute(
+            "UPDATE documents SET ngram_data = ? WHERE id = ?",
+            params![ngram_json, id],
+        )?;
+        
+        Ok(())
+    }
+    
     /// Update document biorthogonal vector data
     pub fn update_document_biorthogonal(&self, id: i64, bio: &BiorthogonalVector) -> SqlResult<()> {
         let bio_json = serde_json::to_string(bio)
@@ -264,6 +740,32 @@ impl DocumentDatabase {
         Ok(())
     }
     
+    /// Update document token positions
+    pub fn update_document_postings(&self, id: i64, postings: &Postings) -> SqlResult<()> {
+        let postings_json = serde_json::to_string(postings)
+            .map_err(|e| rusqlite::Error::InvalidParameterName(e.to_string()))?;
+            
+        self.conn.execute(
+            "UPDATE documents SET postings_data = ? WHERE id = ?",
+            params![postings_json, id],
+        )?;
+        
+        Ok(())
+    }
+    
+    /// Update the passages of a document as a mixed state
+    pub fn update_document_density(&self, id: i64, density: &DocumentDensity) -> SqlResult<()> {
+        let density_json = serde_json::to_string(density)
+            .map_err(|e| rusqlite::Error::InvalidParameterName(e.to_string()))?;
+            
+        self.conn.execute(
+            "UPDATE documents SET density_data = ? WHERE id = ?",
+            params![density_json, id],
+        )?;
+        
+        Ok(())
+    }
+    
     /// Update document persistence metrics
     pub fn update_document_persistence(
         &self, 
@@ -279,6 +781,402 @@ impl DocumentDatabase {
         Ok(())
     }
     
+    /// Load the tokenizer vocabulary and analyzer the stored documents were built with
+    ///
+    /// `default_analyzer` is only used for a fresh database; once documents are
+    /// stored, the analyzer recorded with them always wins. Fails if the stored
+    /// vocabulary has a different format version or no longer matches its
+    /// recorded stamp, or if documents exist without one.
+    pub fn load_tokenizer(&self, default_analyzer: AnalyzerConfig) -> SqlResult<PrimeTokenizer> {
+        let analyzer_config = match self.get_meta("analyzer")? {
+            Some(spec) => AnalyzerConfig::parse_spec(&spec).ok_or_else(|| {
+                rusqlite::Error::InvalidParameterName(format!("Invalid stored analyzer spec: {}", spec))
+            })?,
+            None => default_analyzer,
+        };
+        
+
+        let mut stmt = self.conn.prepare("SELECT token, prime FROM vocabulary ORDER BY prime")?;
+        
+        let rows = stmt.query_map([], |row| {
+            let token: String = row.get(0)?;
+            let prime: i64 = row.get(1)?;
+            Ok((token, prime as u64))
+        })?;
+        
+        let mut entries = Vec::new();
+        for row in rows {
+            entries.push(row?);
+        }
+        
+        let tokenizer = PrimeTokenizer::from_entries(Analyzer::new(analyzer_config), entries);
+        
+        match self.get_vocabulary_stamp()? {
+            Some(stamp) if !tokenizer.is_compatible_with(&stamp) => {
+                Err(rusqlite::Error::InvalidParameterName(format!(
+                    "Stored vocabulary does not match the index (format v{}, {} tokens)",
+                    stamp.format_version, stamp.size
+                )))
+            }
+            None if self.count_documents()? > 0 => {
+                Err(rusqlite::Error::InvalidParameterName(
+                    "Documents were indexed without a stored vocabulary; re-crawl to rebuild the index".to_string()
+                ))
+            }
+            _ => Ok(tokenizer),
+        }
+    }
+    
+    /// Persist vocabulary entries assigned after `since_prime` and record the new stamp
+    pub fn store_vocabulary(&self, tokenizer: &PrimeTokenizer, since_prime: u64) -> SqlResult<()> {
+        for (token, prime) in tokenizer.entries_after(since_prime) {
+            self.conn.execute(
+                "INSERT OR REPLACE INTO vocabulary (prime, token) VALUES (?, ?)",
+                params![prime as i64, token],
+            )?;
+        }
+        
+        let stamp = tokenizer.stamp();
+        self.set_meta("analyzer", &tokenizer.analyzer().config().spec())?;
+        self.set_meta("vocab_format_version", &stamp.format_version.to_string())?;
+        self.set_meta("vocab_size", &stamp.size.to_string())?;
+        self.set_meta("vocab_fingerprint", &stamp.fingerprint.to_string())?;
+        
+        Ok(())
+    }
+    
+    /// Reassign primes by token frequency over all stored documents and remap the index
+    ///
+    /// The most frequent tokens get the smallest primes, with ties broken by the
+    /// token itself, so the same corpus always ends up with the same primes no
+    /// matter the crawl order. Returns the number of documents remapped.
+    pub fn reindex(&self) -> SqlResult<usize> {
+        let mut tokenizer = self.load_tokenizer(AnalyzerConfig::default())?;
+        let analyzers = language_analyzers(tokenizer.analyzer().config());
+        
+        let mut counts = VocabularyBuilder::new();
+        {
+            let mut stmt = self.conn.prepare("SELECT compressed_text, lang, title, headings FROM documents")?;
+            let rows = stmt.query_map([], |row| {
+                Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?, row.get::<_, String>(3)?))
+            })?;
+            for row in rows {
+                let (compressed, lang, title, headings) = row?;
+                let text = decompress_text(&compressed)
+                    .map_err(|e| rusqlite::Error::InvalidParameterName(e.to_string()))?;
+                // Documents were tokenized with the analyzer for their language
+                let analyzer = analyzers.get(&lang).unwrap_or(&analyzers[""]);
+                // The title view holds the headings too, which need not be in the text
+                counts.add_tokens(analyzer.analyze(&format!("{}\n{}", title, headings)));
+                counts.add_tokens(analyzer.analyze(&text));
+            }
+        }
+        
+        let mapping = tokenizer.rebuild_by_frequency(&counts);
+        self.remap_documents(&tokenizer, &mapping)
+    }
+    
+    /// Drop vocabulary entries that no stored document uses and renumber the rest
+    /// onto the smallest primes, keeping their order
+    ///
+    /// Finding the used primes and rewriting the vectors and vocabulary happen in
+    /// one transaction. Returns the number of tokens dropped.
+    pub fn compact_vocabulary(&self) -> SqlResult<usize> {
+        self.begin_transaction()?;
+        
+        match self.compact_vocabulary_in_transaction() {
+            Ok(dropped) => {
+                self.commit_transaction()?;
+                Ok(dropped)
+            }
+            Err(e) => {
+                self.rollback_transaction()?;
+                Err(e)
+            }
+        }
+    }
+    
+    fn compact_vocabulary_in_transaction(&self) -> SqlResult<usize> {
+        let mut tokenizer = self.load_tokenizer(AnalyzerConfig::default())?;
+        
+        let mut used = HashSet::new();
+        {
+            let mut stmt = self.conn.prepare("SELECT vector_data, biorthogonal_data, postings_data FROM documents")?;
+            let rows = stmt.query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?))
+            })?;
+            for row in rows {
+                let (vector_json, bio_json, postings_json) = row?;
+                let vector: PrimeVector = serde_json::from_str(&vector_json)
+                    .map_err(|e| rusqlite::Error::InvalidParameterName(e.to_string()))?;
+                used.extend(token_primes(&vector));
+                
+                // Title words may only appear in the biorthogonal views
+                let bio: BiorthogonalVector = serde_json::from_str(&bio_json)
+                    .map_err(|e| rusqlite::Error::InvalidParameterName(e.to_string()))?;
+                used.extend(token_primes(&bio.left));
+                used.extend(token_primes(&bio.right));
+                
+                if !postings_json.is_empty() {
+                    let postings: Postings = serde_json::from_str(&postings_json)
+                        .map_err(|e| rusqlite::Error::InvalidParameterName(e.to_string()))?;
+                    used.extend(postings.keys().copied());
+                }
+            }
+        }
+        
+        let before = tokenizer.vocab_size();
+        let mapping = tokenizer.compact(&used);
+        self.remap_documents_in_transaction(&tokenizer, &mapping)?;
+        
+        Ok(before - tokenizer.vocab_size())
+    }
+    
+    /// Rewrite every stored vector through an old-prime to new-prime mapping and
+    /// replace the stored vocabulary with `tokenizer`, all in one transaction
+    /// or as part of the caller's if one is open
+    ///
+    /// Fails without changing anything if a stored prime is missing from the
+    /// mapping; inside the caller's transaction, rolling back is up to the caller.
+    pub fn remap_documents(&self, tokenizer: &PrimeTokenizer, mapping: &HashMap<u64, u64>) -> SqlResult<usize> {
+        if !self.conn.is_autocommit() {
+            return self.remap_documents_in_transaction(tokenizer, mapping);
+        }
+        
+        self.begin_transaction()?;
+        
+        match self.remap_documents_in_transaction(tokenizer, mapping) {
+            Ok(count) => {
+                self.commit_transaction()?;
+                Ok(count)
+            }
+            Err(e) => {
+                self.rollback_transaction()?;
+                Err(e)
+            }
+        }
+    }
+    
+    fn remap_documents_in_transaction(&self, tokenizer: &PrimeTokenizer, mapping: &HashMap<u64, u64>) -> SqlResult<usize> {
+        let documents = {
+            let mut stmt = self.conn.prepare("SELECT id, vector_data, ngram_data, biorthogonal_data, postings_data, density_data FROM documents")?;
+            let rows = stmt.query_map([], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, String>(4)?,
+                    row.get::<_, String>(5)?,
+                ))
+            })?;
+            rows.collect::<SqlResult<Vec<_>>>()?
+        };
+        
+        for (id, vector_json, ngram_json, bio_json, postings_json, density_json) in &documents {
+            let vector: PrimeVector = serde_json::from_str(vector_json)
+                .map_err(|e| rusqlite::Error::InvalidParameterName(e.to_string()))?;
+            let ngrams: SparseVector = serde_json::from_str(ngram_json)
+                .map_err(|e| rusqlite::Error::InvalidParameterName(e.to_string()))?;
+            let bio: BiorthogonalVector = serde_json::from_str(bio_json)
+                .map_err(|e| rusqlite::Error::InvalidParameterName(e.to_string()))?;
+            let postings: Postings = serde_json::from_str(postings_json)
+                .map_err(|e| rusqlite::Error::InvalidParameterName(e.to_string()))?;
+            let density: DocumentDensity = serde_json::from_str(density_json)
+                .map_err(|e| rusqlite::Error::InvalidParameterName(e.to_string()))?;
+            
+            let new_vector = remap_vector(&vector, mapping);
+            let new_ngrams = remap_ngram_vector(&ngrams, &SparseVector::from(&vector), mapping);
+            let new_bio = remap_biorthogonal(&bio, mapping);
+            let new_postings = remap_postings(&postings, mapping);
+            
+            // Composite keys whose new product overflows are dropped, as
+            // `remap_vector` does; only a missing word is an error
+            let words = |vector: &PrimeVector| token_primes(vector).count();
+            if words(&new_vector) != words(&vector)
+                || words(&new_bio.left) != words(&bio.left)
+                || words(&new_bio.right) != words(&bio.right)
+                || new_postings.len() != postings.len()
+            {
+                return Err(rusqlite::Error::InvalidParameterName(format!(
+                    "Document {} uses primes missing from the new vocabulary", id
+                )));
+            }
+            
+            self.update_document_vector(*id, &new_vector)?;
+            self.update_document_ngrams(*id, &new_ngrams)?;
+            self.update_document_biorthogonal(*id, &new_bio)?;
+            self.update_document_postings(*id, &new_postings)?;
+            self.update_document_density(*id, &density.remap(mapping))?;
+            self.store_fingerprint(*id, &Fingerprint::from_postings(&new_postings))?;
+        }
+        
+        self.conn.execute("DELETE FROM vocabulary", [])?;
+        self.store_vocabulary(tokenizer, 0)?;
+        self.rebuild_term_stats_in_transaction()?;
+        
+        // Embeddings hash the primes, so the next sync embeds every document again
+        self.conn.execute("DELETE FROM document_embeddings", [])?;
+        
+        Ok(documents.len())
+    }
+    
+    /// Load the nearest-neighbour index stored next to the documents
+    pub fn load_ann_index(&self, config: AnnConfig) -> SqlResult<AnnIndex> {
+        let mut stmt = self.conn.prepare("SELECT document_id, embedding, links FROM document_embeddings")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, Vec<u8>>(1)?, row.get::<_, String>(2)?))
+        })?;
+        
+        let mut nodes = Vec::new();
+        for row in rows {
+            let (id, embedding, links_json) = row?;
+            let links = serde_json::from_str(&links_json)
+                .map_err(|e| rusqlite::Error::InvalidParameterName(e.to_string()))?;
+            nodes.push((id as usize, AnnNode { embedding: embedding_from_bytes(&embedding), links }));
+        }
+        
+        Ok(AnnIndex::from_nodes(config, nodes))
+    }
+    
+    /// Bring a nearest-neighbour index in line with the stored documents and save it
+    ///
+    /// Documents without an embedding are inserted and the embeddings of
+    /// documents that are gone are removed. Returns how many were inserted or removed.
+    pub fn sync_ann_index(&self, index: &mut AnnIndex) -> SqlResult<usize> {
+        let stored: HashSet<usize> = {
+            let mut stmt = self.conn.prepare("SELECT id FROM documents")?;
+            let rows = stmt.query_map([], |row| row.get::<_, i64>(0))?;
+            rows.map(|id| id.map(|id| id as usize)).collect::<SqlResult<_>>()?
+        };
+        
+        let gone: Vec<usize> = index.keys().filter(|id| !stored.contains(id)).collect();
+        for &id in &gone {
+            index.remove(id);
+        }
+        
+        let mut missing: Vec<usize> = stored.into_iter().filter(|&id| !index.contains(id)).collect();
+        missing.sort_unstable();
+        let mut stmt = self.conn.prepare_cached("SELECT vector_data FROM documents WHERE id = ?")?;
+        for &id in &missing {
+            let vector_json: String = stmt.query_row(params![id as i64], |row| row.get(0))?;
+            let vector: PrimeVector = serde_json::from_str(&vector_json)
+                .map_err(|e| rusqlite::Error::InvalidParameterName(e.to_string()))?;
+            index.insert_vector(id, &vector);
+        }
+        
+        self.save_ann_index(index)?;
+        Ok(gone.len() + missing.len())
+    }
+    
+    /// Write the nodes of a nearest-neighbour index that changed since it was last saved
+    ///
+    /// In one transaction, or as part of the caller's if one is open.
+    pub fn save_ann_index(&self, index: &mut AnnIndex) -> SqlResult<usize> {
+        let changed = index.take_changed();
+        if changed.is_empty() {
+            return Ok(0);
+        }
+        
+        if !self.conn.is_autocommit() {
+            self.save_ann_nodes_in_transaction(index, &changed)?;
+            return Ok(changed.len());
+        }
+        
+        self.begin_transaction()?;
+        
+        match self.save_ann_nodes_in_transaction(index, &changed) {
+            Ok(()) => {
+                self.commit_transaction()?;
+                Ok(changed.len())
+            }
+            Err(e) => {
+                self.rollback_transaction()?;
+                Err(e)
+            }
+        }
+    }
+    
+    fn save_ann_nodes_in_transaction(&self, index: &AnnIndex, changed: &[usize]) -> SqlResult<()> {
+        let mut upsert = self.conn.prepare_cached(
+            "INSERT OR REPLACE INTO document_embeddings (document_id, embedding, links) VALUES (?, ?, ?)"
+        )?;
+        let mut delete = self.conn.prepare_cached("DELETE FROM document_embeddings WHERE document_id = ?")?;
+        for &id in changed {
+            match index.node(id) {
+                Some(node) => {
+                    let links_json = serde_json::to_string(&node.links)
+                        .map_err(|e| rusqlite::Error::InvalidParameterName(e.to_string()))?;
+                    upsert.execute(params![id as i64, embedding_to_bytes(&node.embedding), links_json])?;
+                }
+                None => {
+                    delete.execute(params![id as i64])?;
+                }
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// Get the stamp of the vocabulary the index was built with, if one was recorded
+    pub fn get_vocabulary_stamp(&self) -> SqlResult<Option<VocabularyStamp>> {
+        let format_version = match self.get_meta("vocab_format_version")? {
+            Some(value) => value.parse().unwrap_or(0),
+            None => return Ok(None),
+        };
+        let size = self.get_meta("vocab_size")?.and_then(|v| v.parse().ok()).unwrap_or(0);
+        let fingerprint = self.get_meta("vocab_fingerprint")?.and_then(|v| v.parse().ok()).unwrap_or(0);
+        
+        Ok(Some(VocabularyStamp { format_version, size, fingerprint }))
+    }
+    
+    /// SQLite's data version, which changes whenever another connection commits
+    ///
+    /// Cheaper than reading any table, so readers can poll it to notice writes
+    /// from the crawler.
+    pub fn data_version(&self) -> SqlResult<i64> {
+        self.conn.query_row("PRAGMA data_version", [], |row| row.get(0))
+    }
+    
+    /// Get the document views the stored biorthogonal vectors were built from, if recorded
+    pub fn get_biorthogonal_views(&self) -> SqlResult<Option<BiorthogonalViews>> {
+        match self.get_meta("biorthogonal_views")? {
+            Some(spec) => BiorthogonalViews::parse(&spec).map(Some).ok_or_else(|| {
+                rusqlite::Error::InvalidParameterName(format!("Invalid stored biorthogonal views: {}", spec))
+            }),
+            None => Ok(None),
+        }
+    }
+    
+    /// Record the document views biorthogonal vectors are built from
+    pub fn set_biorthogonal_views(&self, views: BiorthogonalViews) -> SqlResult<()> {
+        self.set_meta("biorthogonal_views", &views.spec())
+    }
+    
+    /// Read a value from the index metadata table
+    fn get_meta(&self, key: &str) -> SqlResult<Option<String>> {
+        self.conn.query_row(
+            "SELECT value FROM index_meta WHERE key = ?",
+            params![key],
+            |row| row.get(0),
+        ).optional()
+    }
+    
+    /// Read a numeric value from the index metadata table, 0 if it is missing
+    fn get_meta_number(&self, key: &str) -> SqlResult<i64> {
+        Ok(self.get_meta(key)?.and_then(|value| value.parse().ok()).unwrap_or(0))
+    }
+    
+    /// Write a value to the index metadata table
+    fn set_meta(&self, key: &str, value: &str) -> SqlResult<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO index_meta (key, value) VALUES (?, ?)",
+            params![key, value],
+        )?;
+        
+        Ok(())
+    }
+    
     /// Begin a database transaction
     pub fn begin_transaction(&self) -> SqlResult<()> {
         self.conn.execute("BEGIN TRANSACTION", [])?;
@@ -318,10 +1216,16 @@ impl DocumentDatabase {
 pub fn prime_vector_to_document(
     url: String,
     title: String,
+    headings: String,
     text: String,
     compressed_text: Vec<u8>,
     vector: PrimeVector,
+    ngrams: PrimeVector,
     biorthogonal: BiorthogonalVector,
+    postings: Postings,
+    passages: DocumentPassages,
+    density: DocumentDensity,
+    lang: String,
     entropy: f64,
     reversibility: f64,
     buffering: f64,
@@ -333,9 +1237,21 @@ pub fn prime_vector_to_document(
     let vector_data = serde_json::to_string(&vector)
         .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
         
+    let ngram_data = serde_json::to_string(&ngrams)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+        
     let biorthogonal_data = serde_json::to_string(&biorthogonal)
         .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
         
+    let postings_data = serde_json::to_string(&postings)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+        
+    let passages_data = serde_json::to_string(&passages)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+        
+    let density_data = serde_json::to_string(&density)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+        
     // Get current timestamp
     let timestamp = SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -346,10 +1262,16 @@ pub fn prime_vector_to_document(
         id: None,
         url,
         title,
+        headings,
         text_snippet: snippet,
         compressed_text,
         vector_data,
+        ngram_data,
         biorthogonal_data,
+        postings_data,
+        passages_data,
+        density_data,
+        lang,
         entropy,
         reversibility,
         buffering,
@@ -358,9 +1280,9 @@ pub fn prime_vector_to_document(
 }
 
 /// Parse a stored document back to usable types
-pub fn parse_stored_document(doc: &StoredDocument) -> io::Result<(PrimeVector, BiorthogonalVector)> {
-    // Parse vector data
-    let vector: PrimeVector = serde_json::from_str(&doc.vector_data)
+pub fn parse_stored_document(doc: &StoredDocument) -> io::Result<(SparseVector, BiorthogonalVector)> {
+    // Parse vector data, stored as a PrimeVector
+    let vector: SparseVector = serde_json::from_str(&doc.vector_data)
         .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
         
     // Parse biorthogonal data
@@ -370,6 +1292,91 @@ pub fn parse_stored_document(doc: &StoredDocument) -> io::Result<(PrimeVector, B
     Ok((vector, biorthogonal))
 }
 
+/// Parse the composite n-gram keys stored with a document
+///
+/// Documents stored without them (e.g. imported ones) yield no keys.
+pub fn parse_ngrams(doc: &StoredDocument) -> io::Result<SparseVector> {
+    if doc.ngram_data.is_empty() {
+        return Ok(SparseVector::new());
+    }
+    serde_json::from_str(&doc.ngram_data)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))
+}
+
+/// Parse the token positions stored with a document
+///
+/// Documents stored without positions (e.g. imported ones) yield empty postings.
+pub fn parse_postings(doc: &StoredDocument) -> io::Result<Postings> {
+    if doc.postings_data.is_empty() {
+        return Ok(Postings::new());
+    }
+    serde_json::from_str(&doc.postings_data)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))
+}
+
+/// Parse the entropy profile and passages stored with a document
+///
+/// Documents stored without them (e.g. imported ones) yield no passages.
+pub fn parse_passages(doc: &StoredDocument) -> io::Result<DocumentPassages> {
+    if doc.passages_data.is_empty() {
+        return Ok(DocumentPassages::default());
+    }
+    serde_json::from_str(&doc.passages_data)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))
+}
+
+/// Parse the passages of a document as a mixed state
+///
+/// Documents stored without one (e.g. before densities were kept) get it built
+/// from their postings and passages.
+pub fn parse_density(doc: &StoredDocument, postings: &Postings, passages: &DocumentPassages) -> io::Result<DocumentDensity> {
+    let density: DocumentDensity = if doc.density_data.is_empty() {
+        DocumentDensity::default()
+    } else {
+        serde_json::from_str(&doc.density_data)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?
+    };
+    if density.is_empty() && !postings.is_empty() {
+        return Ok(DocumentDensity::new(postings, passages));
+    }
+    Ok(density)
+}
+
+/// Parse a stored PrimeVector JSON
+fn vector_from_json(vector_json: &str) -> SqlResult<PrimeVector> {
+    serde_json::from_str(vector_json)
+        .map_err(|e| rusqlite::Error::InvalidParameterName(e.to_string()))
+}
+
+/// Parse stored postings JSON, treating an empty column as no postings
+fn postings_from_json(postings_json: &str) -> SqlResult<Postings> {
+    if postings_json.is_empty() {
+        return Ok(Postings::new());
+    }
+    serde_json::from_str(postings_json)
+        .map_err(|e| rusqlite::Error::InvalidParameterName(e.to_string()))
+}
+
+/// Store an embedding as little-endian f32 values
+fn embedding_to_bytes(embedding: &[f32]) -> Vec<u8> {
+    embedding.iter().flat_map(|value| value.to_le_bytes()).collect()
+}
+
+/// Read an embedding stored by `embedding_to_bytes`
+fn embedding_from_bytes(bytes: &[u8]) -> Vec<f32> {
+    bytes.chunks_exact(4)
+        .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect()
+}
+
+/// Decompress the gzip-compressed text stored with a document
+pub fn decompress_text(compressed: &[u8]) -> io::Result<String> {
+    let mut decoder = GzDecoder::new(compressed);
+    let mut text = String::new();
+    decoder.read_to_string(&mut text)?;
+    Ok(text)
+}
+
 /// Create a snippet from text
 fn create_snippet(text: &str, max_length: usize) -> String {
     // Remove extra whitespace
@@ -0,0 +1,323 @@
+// src/density.rs
+
+use crate::passages::DocumentPassages;
+use crate::postings::Postings;
+use crate::prime_hilbert::PrimeVector;
+use crate::sparse_vector::SparseVector;
+use crate::weighting::document_length;
+use nalgebra::DMatrix;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Largest rank kept; a mixture of more passages is cut down to its
+/// strongest eigenstates.
+pub const DENSITY_RANK: usize = 8;
+
+/// Most words the state is spread over: the document's most frequent ones.
+pub const DENSITY_TERMS: usize = 64;
+
+/// Most passages mixed; adjacent passages of a longer document are merged
+/// until this many are left.
+pub const DENSITY_PASSAGES: usize = 32;
+
+/// A document as a mixed state over its most frequent words.
+///
+/// Each passage is a pure state with amplitudes whose squares are the words'
+/// shares of the passage, counting only the `DENSITY_TERMS` most frequent
+/// words of the document, and the passages are mixed in proportion to their
+/// length. Documents with more than `DENSITY_PASSAGES` passages have adjacent
+/// ones merged first. The state is kept factored as ρ = Σ wₖ|uₖ⟩⟨uₖ| with at
+/// most `DENSITY_RANK` components, each component a combination of passage
+/// states, so it is built once when the document is indexed and stores at
+/// most `DENSITY_PASSAGES` × `DENSITY_TERMS` amplitudes.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DocumentDensity {
+    /// Each passage as a unit vector over the word primes it contains.
+    pub passages: Vec<SparseVector>,
+    /// Weight of each component, summing to 1.
+    pub weights: Vec<f64>,
+    /// Each component as coefficients over `passages`, one per weight.
+    pub states: Vec<Vec<f64>>,
+}
+
+impl DocumentDensity {
+    /// Builds the mixed state of a document from its postings and passages.
+    /// A document without passages is one pure state.
+    pub fn new(postings: &Postings, passages: &DocumentPassages) -> Self {
+        let ranges: Vec<(u32, u32)> = if passages.is_empty() {
+            vec![(0, document_length(postings) as u32)]
+        } else {
+            passages.passages.iter().map(|passage| (passage.first_token, passage.end_token)).collect()
+        };
+        let ranges = merge_adjacent(&ranges, DENSITY_PASSAGES);
+
+        // Word counts per passage, each position falling in at most one range
+        let mut counts = vec![PrimeVector::new(); ranges.len()];
+        for prime in frequent_words(postings, DENSITY_TERMS) {
+            for &position in &postings[&prime] {
+                let index = ranges.partition_point(|&(_, end)| end <= position);
+                if ranges.get(index).is_some_and(|&(first, _)| first <= position) {
+                    *counts[index].entry(prime).or_insert(0.0) += 1.0;
+                }
+            }
+        }
+
+        let mut weights = Vec::new();
+        let mut states = Vec::new();
+        for ((first, end), counts) in ranges.into_iter().zip(counts) {
+            let total: f64 = counts.values().sum();
+            if total > 0.0 {
+                weights.push(end.saturating_sub(first).max(1) as f64);
+                states.push(counts.into_iter().map(|(prime, count)| (prime, (count / total).sqrt())).collect());
+            }
+        }
+        if states.is_empty() {
+            return DocumentDensity::default();
+        }
+
+        let weight_total: f64 = weights.iter().sum();
+        let weights: Vec<f64> = weights.iter().map(|weight| weight / weight_total).collect();
+        Self::diagonalized(states, &weights, DENSITY_RANK)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.weights.is_empty()
+    }
+
+    /// Number of components the state is kept as.
+    pub fn rank(&self) -> usize {
+        self.weights.len()
+    }
+
+    /// Fidelity of the document's state ρ with the query as a pure state
+    /// σ = |q⟩⟨q|, from 0 to 1.
+    ///
+    /// |q⟩ has amplitudes whose squares are the query words' shares of the
+    /// query's total weight; composite n-gram keys and negative weights are
+    /// ignored, and words outside the document's basis have no amplitude in
+    /// ρ. With σ pure, the Uhlmann fidelity (Tr √(√ρ σ √ρ))² is
+    /// Tr(ρσ) = ⟨q|ρ|q⟩, the weighted mean of the squared overlaps of the query
+    /// with each component, so it needs no matrix at all.
+    pub fn fidelity(&self, query: &SparseVector) -> f64 {
+        let words: Vec<(u64, f64)> = query
+            .iter()
+            .filter(|&(prime, weight)| weight > 0.0 && primal::is_prime(prime))
+            .collect();
+        let total: f64 = words.iter().map(|&(_, weight)| weight).sum();
+        if total <= 0.0 {
+            return 0.0;
+        }
+
+        // Overlap of the query with each passage, then with each component
+        let overlaps: Vec<f64> = self
+            .passages
+            .iter()
+            .map(|passage| {
+                words
+                    .iter()
+                    .map(|&(prime, weight)| passage.get(prime).unwrap_or(0.0) * (weight / total).sqrt())
+                    .sum()
+            })
+            .collect();
+        self.weights
+            .iter()
+            .zip(&self.states)
+            .map(|(weight, coefficients)| {
+                let overlap: f64 = coefficients.iter().zip(&overlaps).map(|(c, overlap)| c * overlap).sum();
+                weight * overlap * overlap
+            })
+            .sum::<f64>()
+            .clamp(0.0, 1.0)
+    }
+
+    /// Relabels the words through an old-prime to new-prime mapping, dropping
+    /// words the mapping lacks.
+    pub fn remap(&self, mapping: &HashMap<u64, u64>) -> Self {
+        DocumentDensity {
+            passages: self
+                .passages
+                .iter()
+                .map(|passage| {
+                    passage
+                        .iter()
+                        .filter_map(|(prime, amplitude)| mapping.get(&prime).map(|&new_prime| (new_prime, amplitude)))
+                        .collect()
+                })
+                .collect(),
+            weights: self.weights.clone(),
+            states: self.states.clone(),
+        }
+    }
+
+    /// Keeps the `rank` strongest eigenstates of ρ = Σ wᵢ|ψᵢ⟩⟨ψᵢ|, reweighted
+    /// to sum to 1.
+    ///
+    /// With A the matrix of columns √wᵢ ψᵢ, ρ = AAᵀ shares its nonzero
+    /// eigenvalues with the Gram matrix G = AᵀA, and an eigenvector v of G
+    /// gives the eigenstate Av/√λ of ρ, so only a matrix the size of the
+    /// number of passages, at most `DENSITY_PASSAGES`, is diagonalized.
+    fn diagonalized(passages: Vec<SparseVector>, weights: &[f64], rank: usize) -> Self {
+        let size = passages.len();
+        let gram = DMatrix::<f64>::from_fn(size, size, |i, j| {
+            (weights[i] * weights[j]).sqrt() * passages[i].dot(&passages[j])
+        });
+
+        let eigen = gram.symmetric_eigen();
+        let mut order: Vec<usize> = (0..size).filter(|&k| eigen.eigenvalues[k] > f64::EPSILON).collect();
+        order.sort_unstable_by(|&a, &b| eigen.eigenvalues[b].total_cmp(&eigen.eigenvalues[a]));
+        order.truncate(rank);
+
+        let total: f64 = order.iter().map(|&k| eigen.eigenvalues[k]).sum();
+        if total <= 0.0 {
+            return DocumentDensity::default();
+        }
+        let states = order
+            .iter()
+            .map(|&k| {
+                let scale = eigen.eigenvalues[k].sqrt();
+                (0..size).map(|i| weights[i].sqrt() * eigen.eigenvectors[(i, k)] / scale).collect()
+            })
+            .collect();
+        DocumentDensity {
+            passages,
+            weights: order.iter().map(|&k| eigen.eigenvalues[k] / total).collect(),
+            states,
+        }
+    }
+}
+
+/// Merges runs of adjacent ranges so at most `limit` are left, as evenly as
+/// the count allows.
+fn merge_adjacent(ranges: &[(u32, u32)], limit: usize) -> Vec<(u32, u32)> {
+    if ranges.len() <= limit {
+        return ranges.to_vec();
+    }
+    (0..limit)
+        .map(|group| {
+            let first = ranges.len() * group / limit;
+            let last = ranges.len() * (group + 1) / limit - 1;
+            (ranges[first].0, ranges[last].1)
+        })
+        .collect()
+}
+
+/// The `limit` words occurring most often, ties going to the smaller prime.
+fn frequent_words(postings: &Postings, limit: usize) -> Vec<u64> {
+    let mut words: Vec<(usize, u64)> = postings.iter().map(|(&prime, positions)| (positions.len(), prime)).collect();
+    words.sort_unstable_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    words.into_iter().take(limit).map(|(_, prime)| prime).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::passages::Passage;
+    use crate::postings::build_postings;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn vocabulary(size: usize) -> Vec<u64> {
+        primal::Primes::all().take(size).map(|prime| prime as u64).collect()
+    }
+
+    // Passages of `length` tokens each, cut back to back
+    fn passages(lengths: &[u32]) -> DocumentPassages {
+        let mut first = 0;
+        let passages = lengths
+            .iter()
+            .map(|&length| {
+                let passage = Passage { first_token: first, end_token: first + length, start: 0, end: 0 };
+                first += length;
+                passage
+            })
+            .collect();
+        DocumentPassages { entropy_profile: Vec::new(), passages }
+    }
+
+    // ρ written out over `primes`, row and column i for primes[i]
+    fn dense_rho(density: &DocumentDensity, primes: &[u64]) -> DMatrix<f64> {
+        let mut rho = DMatrix::<f64>::zeros(primes.len(), primes.len());
+        for (weight, coefficients) in density.weights.iter().zip(&density.states) {
+            let component: Vec<f64> = primes
+                .iter()
+                .map(|&prime| {
+                    coefficients
+                        .iter()
+                        .zip(&density.passages)
+                        .map(|(c, passage)| c * passage.get(prime).unwrap_or(0.0))
+                        .sum()
+                })
+                .collect();
+            for i in 0..primes.len() {
+                for j in 0..primes.len() {
+                    rho[(i, j)] += weight * component[i] * component[j];
+                }
+            }
+        }
+        rho
+    }
+
+    #[test]
+    fn density_has_unit_trace() {
+        let vocabulary = vocabulary(40);
+        let mut rng = StdRng::seed_from_u64(1);
+        let tokens: Vec<u64> = (0..120).map(|_| vocabulary[rng.gen_range(0..vocabulary.len())]).collect();
+        let density = DocumentDensity::new(&build_postings(&tokens), &passages(&[30, 50, 40]));
+
+        let rho = dense_rho(&density, &vocabulary);
+        assert!((rho.trace() - 1.0).abs() < 1e-9, "trace {}", rho.trace());
+        assert!((density.weights.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn pure_state_has_fidelity_one_with_itself() {
+        let tokens = [2, 3, 3, 5, 5, 5, 7];
+        let density = DocumentDensity::new(&build_postings(&tokens), &DocumentPassages::default());
+        assert_eq!(density.rank(), 1);
+
+        let query: SparseVector = [(2, 1.0), (3, 2.0), (5, 3.0), (7, 1.0)].into_iter().collect();
+        assert!((density.fidelity(&query) - 1.0).abs() < 1e-9, "fidelity {}", density.fidelity(&query));
+    }
+
+    #[test]
+    fn fidelity_matches_trace_of_rho_sigma() {
+        let vocabulary = vocabulary(12);
+        let mut rng = StdRng::seed_from_u64(2);
+        let tokens: Vec<u64> = (0..60).map(|_| vocabulary[rng.gen_range(0..vocabulary.len())]).collect();
+        let density = DocumentDensity::new(&build_postings(&tokens), &passages(&[15, 25, 20]));
+        let rho = dense_rho(&density, &vocabulary);
+
+        for _ in 0..10 {
+            let mut query = PrimeVector::new();
+            for &prime in &vocabulary {
+                if rng.gen_bool(0.4) {
+                    query.insert(prime, rng.gen_range(0.1..2.0));
+                }
+            }
+            let query = SparseVector::from(query);
+            let total: f64 = query.iter().map(|(_, weight)| weight).sum();
+            let q = DMatrix::from_iterator(
+                vocabulary.len(),
+                1,
+                vocabulary.iter().map(|&prime| (query.get(prime).unwrap_or(0.0) / total).sqrt()),
+            );
+            let sigma = &q * q.transpose();
+
+            let expected = (&rho * sigma).trace();
+            assert!((density.fidelity(&query) - expected).abs() < 1e-9, "{} vs {}", density.fidelity(&query), expected);
+        }
+    }
+
+    #[test]
+    fn long_documents_are_bounded() {
+        let vocabulary = vocabulary(500);
+        let mut rng = StdRng::seed_from_u64(3);
+        let tokens: Vec<u64> = (0..20_000).map(|_| vocabulary[rng.gen_range(0..vocabulary.len())]).collect();
+        let density = DocumentDensity::new(&build_postings(&tokens), &passages(&[20; 1000]));
+
+        assert!(density.passages.len() <= DENSITY_PASSAGES);
+        assert!(density.passages.iter().all(|passage| passage.len() <= DENSITY_TERMS));
+        assert!(density.rank() <= DENSITY_RANK);
+    }
+}
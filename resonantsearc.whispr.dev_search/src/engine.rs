@@ -1,35 +1,74 @@
 // src/engine.rs
 
-use crate::tokenizer::PrimeTokenizer;
-use crate::prime_hilbert::{build_vector, dot_product, PrimeVector, build_biorthogonal_vector, BiorthogonalVector, to_dense_vector, resonance_complex, biorthogonal_score};
-use crate::entropy::{shannon_entropy, calculate_reversibility, entropy_pressure, buffering_capacity, persistence_score};
+use crate::analyzer::Analyzer;
+use crate::language::{detect_language, language_analyzers, language_tag};
+use crate::tokenizer::{phrase_keys, PrimeTokenizer, QueryTokens, VocabularyStamp, MAX_NGRAM};
+use crate::prime_hilbert::{build_vector, build_ngram_vector, phrase_match, build_biorthogonal_vector, build_query_biorthogonal, BiorthogonalVector, BiorthogonalViews, project_dense, project_dense_sparse, DENSE_DIMENSION, biorthogonal_score, QuantumScoring, PrimeVector, rocchio, top_terms};
+use crate::sparse_vector::SparseVector;
+use crate::weighting::{CorpusStats, Weighting};
+use crate::inverted_index::InvertedIndex;
+use crate::ann_index::{AnnConfig, AnnIndex};
+use crate::postings::{build_postings, contains_phrase, proximity_score, Postings};
+use crate::passages::{DocumentPassages, PassageMatch};
+use crate::density::DocumentDensity;
+use crate::entropy::{shannon_entropy, calculate_reversibility, buffering_capacity, persistence_score, DecayKernel};
+use crate::history::{content_change_sparse, content_hash, Fetch, UpdateStats};
+use crate::fingerprint::{collapse_near_duplicates, DuplicateIndex, Fingerprint};
 use crate::crawler::CrawledDocument;
 
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::io::{self, Write, Read};
 use std::time::{SystemTime, UNIX_EPOCH};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use scraper::Html;
 use flate2::write::GzEncoder;
 use flate2::read::GzDecoder;
 use flate2::Compression;
 use num_complex::Complex;
 
+/// Weight of the original query in `search_with_feedback`
+pub const ROCCHIO_ALPHA: f64 = 1.0;
+
+/// Weight of the liked documents' centroid in `search_with_feedback`
+pub const ROCCHIO_BETA: f64 = 0.75;
+
+/// Weight of the disliked documents' centroid in `search_with_feedback`
+pub const ROCCHIO_GAMMA: f64 = 0.15;
+
+/// Most words `search_with_feedback` adds to a query
+pub const FEEDBACK_TERMS: usize = 10;
+
+/// Candidates fully scored per result asked for; documents outside the candidate set are not returned
+pub const CANDIDATES_PER_RESULT: usize = 10;
+
+/// Fewest candidates fully scored, however few results are asked for
+pub const MIN_CANDIDATES: usize = 100;
+
 /// Represents a processed document in the engine's index.
 struct IndexedDocument {
     title: String,
     text: String,
     compressed_text: Option<Vec<u8>>, // New field for compressed text
-    vector: PrimeVector,
+    vector: SparseVector,
+    // Composite keys for runs of words, for phrases when there are no postings
+    ngrams: SparseVector,
     biorthogonal: BiorthogonalVector,
+    postings: Postings,
+    passages: DocumentPassages,
+    // The passages as a mixed state, built only for `QuantumScoring::Fidelity`
+    density: DocumentDensity,
+    fingerprint: Fingerprint,
     entropy: f64,
     path: PathBuf,
+    lang: String,
     timestamp: u64,
     // Persistence theory metrics
     reversibility: f64,
     buffering: f64,
     historical_vectors: Vec<Vec<f64>>,
+    // Every fetch of the document's URL, oldest first
+    history: Vec<Fetch>,
 }
 
 // Add these methods to the IndexedDocument implementation
@@ -48,23 +87,22 @@ impl IndexedDocument {
         }
     }
     
-    /// Decompress the text when needed
-    fn decompress_text(&mut self) -> &str {
-        if self.text.is_empty() && self.compressed_text.is_some() {
-            let compressed = self.compressed_text.as_ref().unwrap();
+    /// Decompress the text when needed, leaving the document compressed
+    fn decompress_text(&self) -> String {
+        if let Some(compressed) = &self.compressed_text {
             let mut decoder = GzDecoder::new(&compressed[..]);
             let mut text = String::new();
             
             if decoder.read_to_string(&mut text).is_ok() {
-                self.text = text;
+                return text;
             }
         }
         
-        &self.text
+        self.text.clone()
     }
     
     /// Get a snippet of the document text
-    fn get_snippet(&mut self, max_len: usize) -> String {
+    fn get_snippet(&self, max_len: usize) -> String {
         let text = self.decompress_text();
         let snippet_chars: String = text.chars().take(max_len).collect();
         snippet_chars.trim().replace('\n', " ") + "..."
@@ -73,26 +111,69 @@ impl IndexedDocument {
 
 /// Represents a search result with scoring details and a snippet.
 pub struct SearchResult {
+    /// Position of the document in the engine, or its row id when it comes from the database
+    pub id: usize,
     pub title: String,
     pub resonance: f64,
     pub delta_entropy: f64,
     pub score: f64,
     pub quantum_score: f64,
     pub persistence_score: f64,
+    /// Fraction of the quoted query phrases found in the document (0 to 1)
+    pub phrase_score: f64,
+    /// How close together the query words appear in the document (0 to 1)
+    pub proximity: f64,
     pub snippet: String,
     pub path: String,
+    /// Detected language of the document, empty if unknown
+    pub lang: String,
+    /// The passage with the most of the query's words, if the document was indexed with passages
+    pub passage: Option<PassageMatch>,
+    /// Near-duplicates of this document ranked below it and left out of the results
+    pub duplicates: usize,
+}
+
+/// Results of `ResonantEngine::search_with_feedback`
+pub struct FeedbackResults {
+    pub results: Vec<SearchResult>,
+    /// Words the feedback added to the query with their weights, strongest first
+    pub expansion_terms: Vec<(String, f64)>,
 }
 
 /// The main search engine struct that manages documents and performs searches.
 pub struct ResonantEngine {
     tokenizer: PrimeTokenizer,
+    // Documents and queries are analyzed per language
+    language_analyzers: HashMap<String, Analyzer>,
+    // How many documents are in each language, so unfiltered queries go through every analyzer in use
+    languages: BTreeMap<String, usize>,
     docs: Vec<IndexedDocument>,
+    // The document added from each URL, which a refetch replaces in place and whose fetch history it continues
+    latest: HashMap<PathBuf, usize>,
+    // Word primes to the documents containing them, for top-k candidates;
+    // documents without postings are listed separately and always scored
+    inverted_index: InvertedIndex,
+    unindexed: Vec<usize>,
+    // Embedding graph for `similar_documents`, built on first use and kept up to date after
+    ann_index: Option<AnnIndex>,
+    ann_config: AnnConfig,
+    // MinHash fingerprints of every document, to find near-duplicates
+    duplicate_index: DuplicateIndex,
+    collapse_duplicates: bool,
+    // Document frequencies and lengths of everything indexed
+    corpus_stats: CorpusStats,
+    weighting: Weighting,
+    biorthogonal_views: BiorthogonalViews,
     entropy_weight: f64,
     // Quantum and persistence parameters
     fragility: f64,
     trend_decay: f64,
+    decay_kernel: DecayKernel,
     use_quantum_score: bool,
+    quantum_scoring: QuantumScoring,
     use_persistence_score: bool,
+    phrase_weight: f64,
+    proximity_weight: f64,
 }
 
 impl ResonantEngine {
@@ -109,6 +190,11 @@ impl ResonantEngine {
                 .unwrap_or_default()
                 .as_secs()
         )?;
+        let stamp = self.tokenizer.stamp();
+        writeln!(file, "# Vocabulary: {}\t{}\t{}", stamp.format_version, stamp.size, stamp.fingerprint)?;
+        
+        // Save the vocabulary next to the checkpoint so it can be reloaded with it
+        self.save_vocabulary(&Self::vocabulary_path(path))?;
         
         // Write document entries
         for doc in &self.docs {
@@ -130,8 +216,28 @@ impl ResonantEngine {
         let content = fs::read_to_string(path)?;
         let mut lines = content.lines();
         
-        // Skip header lines
+        // Restore the vocabulary saved alongside the checkpoint, if any
+        let vocab_path = Self::vocabulary_path(path);
+        if Path::new(&vocab_path).exists() {
+            self.load_vocabulary(&vocab_path)?;
+        }
+        
+        // Skip header lines, refusing checkpoints built with another vocabulary
         while let Some(line) = lines.next() {
+            if let Some(stamp_line) = line.strip_prefix("# Vocabulary: ") {
+                let fields: Vec<&str> = stamp_line.split('\t').collect();
+                let stamp = VocabularyStamp {
+                    format_version: fields.first().and_then(|v| v.parse().ok()).unwrap_or(0),
+                    size: fields.get(1).and_then(|v| v.parse().ok()).unwrap_or(0),
+                    fingerprint: fields.get(2).and_then(|v| v.parse().ok()).unwrap_or(0),
+                };
+                if !self.tokenizer.is_compatible_with(&stamp) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Checkpoint was built with a different vocabulary ({} tokens)", stamp.size)
+                    ));
+                }
+            }
             if !line.starts_with('#') {
                 // Process the first non-header line
                 self.process_checkpoint_line(line)?;
@@ -148,6 +254,33 @@ impl ResonantEngine {
         Ok(())
     }
     
+    /// Path of the vocabulary file saved alongside a checkpoint
+    fn vocabulary_path(checkpoint_path: &str) -> String {
+        format!("{}.vocab", checkpoint_path)
+    }
+    
+    /// Save the tokenizer vocabulary so a later run assigns the same primes
+    pub fn save_vocabulary(&self, path: &str) -> io::Result<()> {
+        self.tokenizer.save_vocabulary(path)
+    }
+    
+    /// Replace the tokenizer vocabulary with one saved by `save_vocabulary`
+    ///
+    /// Fails if documents are already indexed and the new vocabulary would
+    /// map their primes to different words.
+    pub fn load_vocabulary(&mut self, path: &str) -> io::Result<()> {
+        let tokenizer = PrimeTokenizer::load_vocabulary(path)?;
+        if !self.docs.is_empty() && !tokenizer.is_compatible_with(&self.tokenizer.stamp()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Vocabulary does not match the documents already indexed"
+            ));
+        }
+        self.language_analyzers = language_analyzers(tokenizer.analyzer().config());
+        self.tokenizer = tokenizer;
+        Ok(())
+    }
+    
     /// Process a single line from the checkpoint file
     fn process_checkpoint_line(&mut self, line: &str) -> io::Result<()> {
         let parts: Vec<&str> = line.split('\t').collect();
@@ -161,28 +294,36 @@ impl ResonantEngine {
         let url = parts[0];
         let title = parts[1];
         let entropy: f64 = parts[2].parse().unwrap_or(0.0);
-        let reversibility: f64 = parts[3].parse().unwrap_or(1.0);
+        // Older checkpoints could hold reversibility above 1
+        let reversibility: f64 = parts[3].parse::<f64>().unwrap_or(1.0).clamp(0.0, 1.0);
         let timestamp: u64 = parts[4].parse().unwrap_or(0);
         
         // Create a placeholder document to be filled with real content later
         let path = PathBuf::from(url);
         let tokens = self.tokenizer.tokenize("placeholder");
         let vector = build_vector(&tokens);
-        let biorthogonal = build_biorthogonal_vector(&tokens);
-        let dense_vec = to_dense_vector(&vector, 1000);
+        let biorthogonal = build_biorthogonal_vector(&tokens, &tokens, self.biorthogonal_views);
+        let dense_vec = project_dense(&vector, DENSE_DIMENSION);
         
-        self.docs.push(IndexedDocument {
+        self.push_document(IndexedDocument {
             title: title.to_string(),
             text: String::new(),
             compressed_text: None,
-            vector,
+            vector: vector.into(),
+            ngrams: SparseVector::new(),
             biorthogonal,
+            postings: Postings::new(), // Positions are unknown until the content is re-crawled
+            passages: DocumentPassages::default(),
+            density: DocumentDensity::default(),
+            fingerprint: Fingerprint::default(),
             entropy,
             path,
+            lang: String::new(),
             timestamp,
             reversibility,
             buffering: 0.5, // Default value
             historical_vectors: vec![dense_vec],
+            history: Vec::new(), // Nothing is known about its fetches
         });
         
         Ok(())
@@ -220,14 +361,41 @@ impl ResonantEngine {
     
     /// Creates a new `ResonantEngine`.
     pub fn new() -> Self {
+        let tokenizer = PrimeTokenizer::new();
         ResonantEngine {
-            tokenizer: PrimeTokenizer::new(),
+            language_analyzers: language_analyzers(tokenizer.analyzer().config()),
+            tokenizer,
+            languages: BTreeMap::new(),
             docs: Vec::new(),
+            latest: HashMap::new(),
+            inverted_index: InvertedIndex::new(),
+            unindexed: Vec::new(),
+            ann_index: None,
+            ann_config: AnnConfig::default(),
+            duplicate_index: DuplicateIndex::new(),
+            collapse_duplicates: true,
+            corpus_stats: CorpusStats::new(),
+            weighting: Weighting::default(),
+            biorthogonal_views: BiorthogonalViews::default(),
             entropy_weight: 0.1,
             fragility: 0.2,
             trend_decay: 0.05,
+            decay_kernel: DecayKernel::default(),
             use_quantum_score: true,
+            quantum_scoring: QuantumScoring::default(),
             use_persistence_score: true,
+            phrase_weight: 1.0,
+            proximity_weight: 0.5,
+        }
+    }
+
+    /// Creates a new `ResonantEngine` whose documents and queries both go through `analyzer`,
+    /// adapted to each document's language.
+    pub fn with_analyzer(analyzer: Analyzer) -> Self {
+        ResonantEngine {
+            language_analyzers: language_analyzers(analyzer.config()),
+            tokenizer: PrimeTokenizer::with_analyzer(analyzer),
+            ..Self::new()
         }
     }
 
@@ -236,26 +404,69 @@ impl ResonantEngine {
         self.docs.len()
     }
 
+    /// Returns the entropy profile and passages of the document with `id`.
+    pub fn passages(&self, id: usize) -> Option<&DocumentPassages> {
+        self.docs.get(id).map(|doc| &doc.passages)
+    }
+
+    /// Returns the groups of documents that are near-duplicates of each other, by id
+    pub fn duplicate_clusters(&self) -> Vec<Vec<usize>> {
+        self.duplicate_index.clusters()
+    }
+
+    /// Returns how often and how much the document with `id` changed across its fetches
+    pub fn update_stats(&self, id: usize) -> Option<UpdateStats> {
+        self.docs.get(id).map(|doc| UpdateStats::from_history(&doc.history))
+    }
+
     /// Enable or disable quantum scoring
     pub fn set_use_quantum_score(&mut self, enable: bool) {
         self.use_quantum_score = enable;
     }
+    
+    /// Choose how the quantum score is computed
+    ///
+    /// Switching to `Fidelity` builds the density of every document added without one.
+    pub fn set_quantum_scoring(&mut self, scoring: QuantumScoring) {
+        self.quantum_scoring = scoring;
+        if scoring == QuantumScoring::Fidelity {
+            for doc in &mut self.docs {
+                if doc.density.is_empty() {
+                    doc.density = DocumentDensity::new(&doc.postings, &doc.passages);
+                }
+            }
+        }
+    }
+
+    /// Choose how entropy pressure grows with document age
+    pub fn set_decay_kernel(&mut self, kernel: DecayKernel) {
+        self.decay_kernel = kernel;
+    }
 
     /// Enable or disable persistence scoring
     pub fn set_use_persistence_score(&mut self, enable: bool) {
         self.use_persistence_score = enable;
     }
 
+    /// Leave near-duplicates of a better-ranked result out of search results (on by default)
+    pub fn set_collapse_duplicates(&mut self, enable: bool) {
+        self.collapse_duplicates = enable;
+    }
+
     /// Adds a single local file document to the engine's index.
     #[allow(dead_code)]
     fn add_local_document(&mut self, title: String, text: String, path: PathBuf) {
-        let tokens = self.tokenizer.tokenize(&text);
-        let vec = build_vector(&tokens);
-        let biorthogonal = build_biorthogonal_vector(&tokens);
+        let lang = detect_language(&text, None);
+        let analyzer = Self::document_analyzer(&self.language_analyzers, &lang);
+        let (tokens, offsets) = self.tokenizer.tokenize_with_offsets(analyzer, &text);
+        let title_tokens = self.tokenizer.tokenize_with(analyzer, &title);
+        let vec = SparseVector::from(build_vector(&tokens));
+        let ngrams = SparseVector::from(build_ngram_vector(&tokens, MAX_NGRAM));
+        let biorthogonal = build_biorthogonal_vector(&title_tokens, &tokens, self.biorthogonal_views);
         let entropy = shannon_entropy(&tokens);
         
         // Convert to dense vector for historical comparisons
-        let dense_vec = to_dense_vector(&vec, 1000); // Arbitrary dimension
+        let dense_vec = project_dense_sparse(&vec, DENSE_DIMENSION);
         
         // Get current timestamp
         let timestamp = SystemTime::now()
@@ -267,34 +478,50 @@ impl ResonantEngine {
         let reversibility = 1.0; // New document is fully reversible with itself
         let buffering = buffering_capacity(&dense_vec);
         
-        self.docs.push(IndexedDocument {
+        let postings = build_postings(&tokens);
+        let passages = DocumentPassages::new(&tokens, &offsets);
+        let fingerprint = Fingerprint::new(&tokens);
+        let history = self.fetch_history(&path, &text, &vec, timestamp);
+        
+        self.push_document(IndexedDocument {
             title,
             text,
             compressed_text: None,
             vector: vec,
+            ngrams,
             biorthogonal,
+            postings,
+            passages,
+            density: DocumentDensity::default(),
+            fingerprint,
             entropy,
             path,
+            lang,
             timestamp,
             reversibility,
             buffering,
             historical_vectors: vec![dense_vec.clone()], // Initialize with current vector
+            history,
         });
     }
 
     /// Adds a crawled web document to the engine's index.
     pub fn add_crawled_document(&mut self, doc: CrawledDocument) {
-        let tokens = self.tokenizer.tokenize(&doc.text);
+        let analyzer = Self::document_analyzer(&self.language_analyzers, &doc.lang);
+        let (tokens, offsets) = self.tokenizer.tokenize_with_offsets(analyzer, &doc.text);
         if tokens.is_empty() {
             return;
         }
         
-        let vec = build_vector(&tokens);
-        let biorthogonal = build_biorthogonal_vector(&tokens);
+        // The title and headings are the document's second view
+        let heading_tokens = self.tokenizer.tokenize_with(analyzer, &format!("{}\n{}", doc.title, doc.headings));
+        let vec = SparseVector::from(build_vector(&tokens));
+        let ngrams = SparseVector::from(build_ngram_vector(&tokens, MAX_NGRAM));
+        let biorthogonal = build_biorthogonal_vector(&heading_tokens, &tokens, self.biorthogonal_views);
         let entropy = shannon_entropy(&tokens);
         
         // Convert to dense vector for historical comparisons
-        let dense_vec = to_dense_vector(&vec, 1000); // Arbitrary dimension
+        let dense_vec = project_dense_sparse(&vec, DENSE_DIMENSION);
         
         // Get current timestamp
         let timestamp = SystemTime::now()
@@ -309,18 +536,30 @@ impl ResonantEngine {
         // Store the URL string in the path field
         let doc_path = PathBuf::from(doc.url);
 
-        self.docs.push(IndexedDocument {
+        let postings = build_postings(&tokens);
+        let passages = DocumentPassages::new(&tokens, &offsets);
+        let fingerprint = Fingerprint::new(&tokens);
+        let history = self.fetch_history(&doc_path, &doc.text, &vec, timestamp);
+
+        self.push_document(IndexedDocument {
             title: doc.title,
             text: doc.text,
             compressed_text: None,
             vector: vec,
+            ngrams,
             biorthogonal,
+            postings,
+            passages,
+            density: DocumentDensity::default(),
+            fingerprint,
             entropy,
             path: doc_path,
+            lang: doc.lang,
             timestamp,
             reversibility,
             buffering,
             historical_vectors: vec![dense_vec.clone()], // Initialize with current vector
+            history,
         });
     }
 
@@ -390,41 +629,121 @@ impl ResonantEngine {
         Ok(())
     }
 
-    /// Update document relationships and calculate reversibility
-    fn update_document_relationships(&mut self) {
-        // Create a copy of all document vectors
-        let all_vectors: Vec<Vec<f64>> = self.docs.iter()
-            .map(|doc| {
-                to_dense_vector(&doc.vector, 1000) // Convert to same-sized dense vectors
-            })
-            .collect();
+    /// Add a document to the index, its corpus statistics and its posting lists
+    ///
+    /// A document from a URL already indexed replaces the previous one under its id.
+    fn push_document(&mut self, mut doc: IndexedDocument) {
+        // Only fidelity scoring reads the density, so only then is it built
+        if self.quantum_scoring == QuantumScoring::Fidelity && doc.density.is_empty() {
+            doc.density = DocumentDensity::new(&doc.postings, &doc.passages);
+        }
+
+        let id = match self.latest.get(&doc.path) {
+            Some(&id) => {
+                self.retire_document(id);
+                id
+            }
+            None => self.docs.len(),
+        };
+
+        self.update_document_relationships(id, &mut doc);
+        self.corpus_stats.add_document(&doc.postings);
+        if doc.postings.is_empty() {
+            self.unindexed.push(id);
+        } else {
+            self.inverted_index.add_document(id, &doc.postings, &doc.vector);
+        }
+        if let Some(ann_index) = &mut self.ann_index {
+            ann_index.insert_vector_sparse(id, &doc.vector);
+        }
+        self.duplicate_index.insert(id, doc.fingerprint.clone());
+        *self.languages.entry(doc.lang.clone()).or_insert(0) += 1;
+        self.latest.insert(doc.path.clone(), id);
+        if id == self.docs.len() {
+            self.docs.push(doc);
+        } else {
+            self.docs[id] = doc;
+        }
+    }
+    
+    /// Take a document out of the corpus statistics and every index before a newer version replaces it
+    fn retire_document(&mut self, id: usize) {
+        let doc = &self.docs[id];
+        self.corpus_stats.remove_document(&doc.postings);
+        self.inverted_index.remove_document(id, &doc.postings);
+        self.unindexed.retain(|&other| other != id);
+        if let Some(ann_index) = &mut self.ann_index {
+            ann_index.remove(id);
+        }
+        self.duplicate_index.remove(id);
+        if let Some(count) = self.languages.get_mut(&doc.lang) {
+            *count -= 1;
+            if *count == 0 {
+                self.languages.remove(&doc.lang);
+            }
+        }
+    }
+    
+    /// The fetch history of `path` with one more fetch of `text`, its change measured
+    /// against the latest document added from the same URL
+    fn fetch_history(&self, path: &Path, text: &str, vector: &SparseVector, timestamp: u64) -> Vec<Fetch> {
+        let mut fetch = Fetch { timestamp, content_hash: content_hash(text), change: 0.0 };
+        let previous = self.latest.get(path).map(|&id| &self.docs[id]);
+        let mut history = previous.map(|doc| doc.history.clone()).unwrap_or_default();
+        if let (Some(previous), Some(last)) = (previous, history.last()) {
+            if last.content_hash != fetch.content_hash {
+                fetch.change = content_change_sparse(&previous.vector, vector);
+            }
+        }
+        history.push(fetch);
+        history
+    }
+    
+    /// Analyzer for documents in `lang`, falling back to the one for unknown languages
+    fn document_analyzer<'a>(language_analyzers: &'a HashMap<String, Analyzer>, lang: &str) -> &'a Analyzer {
+        language_analyzers.get(lang).unwrap_or(&language_analyzers[""])
+    }
+
+    /// Fold `doc`, about to be stored under `id`, into the reversibility of every document
+    ///
+    /// A document's reversibility is its mean similarity (1 - Jensen-Shannon
+    /// divergence) to the other documents. Each addition or replacement only
+    /// adjusts the means by its own similarities, so searches never recompute them.
+    fn update_document_relationships(&mut self, id: usize, doc: &mut IndexedDocument) {
+        let current_vec = project_dense_sparse(&doc.vector, DENSE_DIMENSION);
+        let previous_vec = self.docs.get(id).map(|previous| project_dense_sparse(&previous.vector, DENSE_DIMENSION));
+        let others = self.docs.len() - usize::from(previous_vec.is_some());
+        if others == 0 {
+            return;
+        }
         
-        // Update reversibility for each document
-        for (i, doc) in self.docs.iter_mut().enumerate() {
-            // Get all vectors except this document's vector
-            let others_vectors: Vec<Vec<f64>> = all_vectors.iter()
-                .enumerate()
-                .filter(|&(j, _)| j != i) // Skip the current document
-                .map(|(_, vec)| vec.clone())
-                .collect();
+        let mut total = 0.0;
+        for (other_id, other) in self.docs.iter_mut().enumerate() {
+            if other_id == id {
+                continue;
+            }
+            let other_vec = project_dense_sparse(&other.vector, DENSE_DIMENSION);
+            let similarity = calculate_reversibility(&current_vec, std::slice::from_ref(&other_vec));
+            total += similarity;
             
-            // Update reversibility and add to historical vectors
-            if !others_vectors.is_empty() {
-                let current_vec = &all_vectors[i];
-                doc.reversibility = calculate_reversibility(current_vec, &others_vectors);
-                
-                // Only keep a reasonable number of historical vectors (e.g., up to 5)
-                if doc.historical_vectors.len() < 5 {
-                    doc.historical_vectors.push(current_vec.clone());
+            // A replaced document's similarity gives way to its successor's;
+            // a new one joins the others the document is averaged over
+            let reversibility = match &previous_vec {
+                Some(previous_vec) => {
+                    let previous = calculate_reversibility(previous_vec, std::slice::from_ref(&other_vec));
+                    other.reversibility + (similarity - previous) / others as f64
                 }
-            }
+                None => (other.reversibility * (others - 1) as f64 + similarity) / others as f64,
+            };
+            other.reversibility = reversibility.clamp(0.0, 1.0);
         }
+        doc.reversibility = (total / others as f64).clamp(0.0, 1.0);
     }
 
     /// Calculate quantum score for a document given a query
-    fn calculate_quantum_score(&self, query_vec: &PrimeVector, doc: &IndexedDocument) -> f64 {
+    fn calculate_quantum_score(&self, query_vec: &SparseVector, doc: &IndexedDocument) -> f64 {
         // Calculate basic resonance using dot product
-        let _basic_resonance = dot_product(query_vec, &doc.vector);
+        let _basic_resonance = query_vec.dot(&doc.vector);
         
         // Calculate complex resonance with decay
         // Use doc age for decay factor - newer documents have less decay
@@ -435,16 +754,13 @@ impl ResonantEngine {
         let doc_age = ((now - doc.timestamp) as f64) / (24.0 * 3600.0); // Age in days
         let decay_factor = 0.01 * doc_age.min(100.0); // Cap at 100 days
         
-        let complex_res = resonance_complex(query_vec, &doc.vector, decay_factor);
-        
         // For biorthogonal scoring
-        let query_bio = build_biorthogonal_vector(&self.tokenizer.tokenize_without_update(query_vec.keys().cloned().collect::<Vec<_>>().as_slice()));
+        let query_primes: Vec<u64> = query_vec.keys().collect();
+        let query_bio = build_query_biorthogonal(&query_primes, self.biorthogonal_views);
         let bio_score = biorthogonal_score(&query_bio, &doc.biorthogonal);
         
-        // Combine scores - weight the real part most heavily but consider phase
-        let quantum_score = complex_res.re * 0.6 + complex_res.im.abs() * 0.2 + bio_score * 0.2;
-        
-        quantum_score
+        // Combine the complex resonance with the biorthogonal score
+        self.quantum_scoring.score(query_vec, &doc.vector, &doc.postings, &doc.density, decay_factor, bio_score)
     }
     
     /// Calculate persistence score for a document
@@ -456,16 +772,16 @@ impl ResonantEngine {
             .as_secs();
         let doc_age = ((now - doc.timestamp) as f64) / (24.0 * 3600.0); // Age in days
         
-        // Calculate update frequency (using a default value for now)
-        let update_frequency = 0.1; // Lower means less frequent updates
+        // How often and how much the document changed across its fetches
+        let updates = UpdateStats::from_history(&doc.history);
         
         // Get the current vector for the document
-        let _current_vec = to_dense_vector(&doc.vector, 1000);
+        let _current_vec = project_dense_sparse(&doc.vector, DENSE_DIMENSION);
         
         // Calculate persistence score using the thermodynamic model
         let persistence = persistence_score(
-            doc.reversibility,
-            entropy_pressure(doc_age, update_frequency, self.trend_decay),
+            updates.reversibility_or(doc.reversibility),
+            self.decay_kernel.pressure(doc_age, updates.update_frequency, self.trend_decay),
             doc.buffering,
             self.fragility
         );
@@ -479,27 +795,213 @@ impl ResonantEngine {
 
     /// Performs a search query against the indexed documents.
     /// Returns a vector of `SearchResult`s, sorted by score in descending order.
-    pub fn search(&mut self, query: &str, top_k: usize) -> Vec<SearchResult> {
-        // First update document relationships to ensure reversibility is current
-        self.update_document_relationships();
+    pub fn search(&self, query: &str, top_k: usize) -> Vec<SearchResult> {
+        self.search_in_language(query, top_k, None)
+    }
+    
+    /// Performs a search over the documents in `lang` only (a code such as "en"),
+    /// analyzing the query the way those documents were; `None` searches everything
+    pub fn search_in_language(&self, query: &str, top_k: usize, lang: Option<&str>) -> Vec<SearchResult> {
+        let lang = lang.map(language_tag);
+        let known = self.known_query(query, lang.as_deref());
+        let weighted_query = self.weighting.query_vector(&known.primes, &self.corpus_stats);
+        self.rank(&known, &weighted_query, top_k, lang.as_deref())
+    }
+    
+    /// Performs a search with the query refined by relevance feedback (Rocchio)
+    ///
+    /// `relevant_ids` and `nonrelevant_ids` are the `id`s of earlier results
+    /// the user liked and disliked. The weighted query moves toward the
+    /// centroid of the liked documents and away from the disliked ones, and
+    /// keeps its own words plus the `FEEDBACK_TERMS` strongest new ones.
+    /// Unknown ids are ignored. Like `search_in_language`, only documents in
+    /// `lang` are returned when it is given.
+    pub fn search_with_feedback(&self, query: &str, relevant_ids: &[usize], nonrelevant_ids: &[usize], top_k: usize, lang: Option<&str>) -> FeedbackResults {
+        let lang = lang.map(language_tag);
+        let known = self.known_query(query, lang.as_deref());
+        let weighted_query = self.weighting.query_vector(&known.primes, &self.corpus_stats);
         
-        let query_tokens = self.tokenizer.tokenize(query);
-        if query_tokens.is_empty() {
+        let relevant = self.feedback_vectors(relevant_ids);
+        let nonrelevant = self.feedback_vectors(nonrelevant_ids);
+        let refined = rocchio(&weighted_query.to_prime_vector(), &relevant, &nonrelevant, ROCCHIO_ALPHA, ROCCHIO_BETA, ROCCHIO_GAMMA);
+        
+        let expansion: Vec<(u64, f64)> = top_terms(&refined, refined.len())
+            .into_iter()
+            .filter(|&(prime, _)| !weighted_query.contains_key(prime))
+            .take(FEEDBACK_TERMS)
+            .collect();
+        let mut expanded: PrimeVector = refined
+            .iter()
+            .filter(|&(&prime, _)| weighted_query.contains_key(prime))
+            .map(|(&prime, &weight)| (prime, weight))
+            .collect();
+        expanded.extend(expansion.iter().copied());
+        
+        let expansion_terms = expansion
+            .into_iter()
+            .filter_map(|(prime, weight)| Some((self.tokenizer.get_token(prime)?.clone(), weight)))
+            .collect();
+        FeedbackResults {
+            results: self.rank(&known, &SparseVector::from(expanded), top_k, lang.as_deref()),
+            expansion_terms,
+        }
+    }
+    
+    /// Find the documents most like the one with `id` by the cosine of their embeddings
+    ///
+    /// `ef` is how many candidates the nearest-neighbour search considers
+    /// (more is slower and misses fewer), `None` for the one set with
+    /// `set_ann_ef_search`. The similarity is each result's `score` and
+    /// `resonance`. The index is built over every document the first time.
+    pub fn similar_documents(&mut self, id: usize, top_k: usize, ef: Option<usize>) -> Vec<SearchResult> {
+        let ann_index = self.ann_index.get_or_insert_with(|| {
+            let mut ann_index = AnnIndex::new(self.ann_config);
+            for (id, doc) in self.docs.iter().enumerate() {
+                ann_index.insert_vector_sparse(id, &doc.vector);
+            }
+            ann_index.take_changed();
+            ann_index
+        });
+        
+        let Some(node) = ann_index.node(id) else {
             return Vec::new();
+        };
+        let ef = ef.unwrap_or(ann_index.config().ef_search);
+        let neighbours = ann_index.search_with_ef(&node.embedding, top_k + 1, ef);
+        neighbours
+            .into_iter()
+            .filter(|&(other, _)| other != id)
+            .take(top_k)
+            .filter_map(|(other, similarity)| {
+                let doc = self.docs.get(other)?;
+                Some(SearchResult {
+                    id: other,
+                    title: doc.title.clone(),
+                    resonance: similarity,
+                    delta_entropy: 0.0,
+                    score: similarity,
+                    quantum_score: 0.0,
+                    persistence_score: 0.0,
+                    phrase_score: 0.0,
+                    proximity: 0.0,
+                    snippet: doc.get_snippet(200),
+                    path: doc.path.to_string_lossy().into_owned(),
+                    lang: doc.lang.clone(),
+                    passage: None,
+                    duplicates: 0,
+                })
+            })
+            .collect()
+    }
+    
+    /// Look query words up without growing the vocabulary; unknown words can't match anything
+    ///
+    /// Without a language the query is analyzed like the documents of every language indexed,
+    /// keeping the words any of those analyses knows
+    fn known_query(&self, query: &str, lang: Option<&str>) -> QueryTokens {
+        let langs: Vec<&str> = match lang {
+            Some(lang) => vec![lang],
+            None if self.languages.is_empty() => vec![""],
+            None => self.languages.keys().map(String::as_str).collect(),
+        };
+        let mut known = self.tokenizer.tokenize_known_with(Self::document_analyzer(&self.language_analyzers, langs[0]), query);
+        for lang in &langs[1..] {
+            known.merge(self.tokenizer.tokenize_known_with(Self::document_analyzer(&self.language_analyzers, lang), query));
         }
+        known
+    }
+    
+    /// A document's word weights in the space queries are weighted in
+    fn feedback_vectors(&self, ids: &[usize]) -> Vec<PrimeVector> {
+        ids.iter()
+            .filter_map(|&id| self.docs.get(id))
+            .map(|doc| {
+                let vector: PrimeVector = if doc.postings.is_empty() {
+                    doc.vector.iter().filter(|&(prime, _)| primal::is_prime(prime)).collect()
+                } else {
+                    self.weighting.document_vector(&doc.postings, &self.corpus_stats).into()
+                };
+                // Weigh the words like the query does, so the expansion
+                // favours the documents' distinctive words over their common ones
+                if self.weighting.weighs_query_by_idf() {
+                    vector.into_iter().map(|(prime, weight)| (prime, weight * self.corpus_stats.idf(prime))).collect()
+                } else {
+                    vector
+                }
+            })
+            .collect()
+    }
+    
+    /// The documents with the highest resonance, with that resonance, for the full scoring in `rank`
+    ///
+    /// Indexed documents come from the inverted index, so only documents that
+    /// share a word with the query are looked at; documents without postings
+    /// are always scored.
+    fn candidates(&self, weighted_query: &SparseVector, count: usize, lang: Option<&str>) -> Vec<(usize, f64)> {
+        let resonance = |id: usize| {
+            let doc = &self.docs[id];
+            lang.is_none_or(|lang| doc.lang == lang)
+                .then(|| self.weighting.resonance(weighted_query, &doc.vector, &doc.postings, &self.corpus_stats))
+        };
         
-        let query_vec = build_vector(&query_tokens);
-        let query_entropy = shannon_entropy(&query_tokens);
+        let mut candidates = self.inverted_index.top_k(weighted_query, self.weighting, &self.corpus_stats, count, resonance);
+        candidates.extend(self.unindexed.iter().filter_map(|&id| Some((id, resonance(id)?))));
+        candidates
+    }
+    
+    fn rank(&self, known: &QueryTokens, weighted_query: &SparseVector, top_k: usize, lang: Option<&str>) -> Vec<SearchResult> {
+        if weighted_query.is_empty() {
+            return Vec::new();
+        }
+        let candidates = self.candidates(weighted_query, (top_k * CANDIDATES_PER_RESULT).max(MIN_CANDIDATES), lang);
+        let query_tokens = &known.primes;
+        
+        // Quoted phrases are matched exactly through the positional postings;
+        // documents without postings fall back to composite n-gram keys
+        let query_phrases: Vec<(&Vec<u64>, Vec<u64>)> = known.phrases
+            .iter()
+            .map(|phrase| (phrase, phrase_keys(phrase, MAX_NGRAM)))
+            .collect();
+        
+        let query_vec = SparseVector::from(build_vector(query_tokens));
+        let query_entropy = shannon_entropy(query_tokens);
+        let query_bio = build_query_biorthogonal(query_tokens, self.biorthogonal_views);
 
         // First get all the scores without using 'self' inside the closure
         let mut results: Vec<SearchResult> = Vec::new();
         
-        // Process each document individually to avoid borrowing conflicts
-        for doc in &mut self.docs {
-            // Standard resonance score
-            let resonance = dot_product(&query_vec, &doc.vector);
+        // Only the candidates get the full scoring
+        for (id, resonance) in candidates {
+            let doc = &self.docs[id];
             let delta_entropy = (doc.entropy - query_entropy).abs();
-            let standard_score = resonance - delta_entropy * self.entropy_weight;
+            
+            // Phrase matches outrank documents that only share the words
+            let phrase_score = if query_phrases.is_empty() {
+                0.0
+            } else {
+                query_phrases
+                    .iter()
+                    .map(|(phrase, keys)| {
+                        if doc.postings.is_empty() {
+                            phrase_match(keys, &doc.ngrams)
+                        } else if contains_phrase(phrase, &doc.postings) {
+                            1.0
+                        } else {
+                            0.0
+                        }
+                    })
+                    .sum::<f64>()
+                    / query_phrases.len() as f64
+            };
+            
+            // Query words close together count for more than scattered ones
+            let proximity = proximity_score(query_tokens, &doc.postings);
+            
+            // A language model already compares the word distributions, not just their spread
+            let entropy_penalty = if self.weighting.is_language_model() { 0.0 } else { delta_entropy * self.entropy_weight };
+            let standard_score = resonance - entropy_penalty
+                + phrase_score * self.phrase_weight
+                + proximity * self.proximity_weight;
             
             // Quantum-inspired score
             let quantum_score = if self.use_quantum_score {
@@ -512,14 +1014,11 @@ impl ResonantEngine {
                 let doc_age = ((now - doc.timestamp) as f64) / (24.0 * 3600.0); // Age in days
                 let decay_factor = 0.01 * doc_age.min(100.0); // Cap at 100 days
                 
-                let complex_res = resonance_complex(&query_vec, &doc.vector, decay_factor);
-                
                 // For biorthogonal scoring
-                let query_bio = build_biorthogonal_vector(&self.tokenizer.tokenize_without_update(query_vec.keys().cloned().collect::<Vec<_>>().as_slice()));
                 let bio_score = biorthogonal_score(&query_bio, &doc.biorthogonal);
                 
-                // Combine scores - weight the real part most heavily but consider phase
-                complex_res.re * 0.6 + complex_res.im.abs() * 0.2 + bio_score * 0.2
+                // Combine the complex resonance with the biorthogonal score
+                self.quantum_scoring.score(&query_vec, &doc.vector, &doc.postings, &doc.density, decay_factor, bio_score)
                 // End quantum score calculation
             } else {
                 0.0
@@ -535,13 +1034,13 @@ impl ResonantEngine {
                     .as_secs();
                 let doc_age = ((now - doc.timestamp) as f64) / (24.0 * 3600.0); // Age in days
                 
-                // Calculate update frequency (using a default value for now)
-                let update_frequency = 0.1; // Lower means less frequent updates
+                // How often and how much the document changed across its fetches
+                let updates = UpdateStats::from_history(&doc.history);
                 
                 // Calculate persistence score using the thermodynamic model
                 let persistence = persistence_score(
-                    doc.reversibility,
-                    entropy_pressure(doc_age, update_frequency, self.trend_decay),
+                    updates.reversibility_or(doc.reversibility),
+                    self.decay_kernel.pressure(doc_age, updates.update_frequency, self.trend_decay),
                     doc.buffering,
                     self.fragility
                 );
@@ -555,19 +1054,22 @@ impl ResonantEngine {
             } else {
                 0.0
             };
-            
-            // Generate snippet
-            let snippet = doc.get_snippet(200);
 
             results.push(SearchResult {
+                id,
                 title: doc.title.clone(),
                 resonance,
                 delta_entropy,
                 score: standard_score,
                 quantum_score,
                 persistence_score,
-                snippet,
+                phrase_score,
+                proximity,
+                snippet: String::new(),
                 path: doc.path.to_string_lossy().into_owned(),
+                lang: doc.lang.clone(),
+                passage: None,
+                duplicates: 0,
             });
         }
 
@@ -595,8 +1097,26 @@ impl ResonantEngine {
             
             b_combined.partial_cmp(&a_combined).unwrap_or(std::cmp::Ordering::Equal)
         });
-
-        results.into_iter().take(top_k).collect()
+        
+        // Near-duplicates of a better result are folded into it
+        if self.collapse_duplicates {
+            results = collapse_near_duplicates(results, top_k, |result| self.docs[result.id].fingerprint.clone())
+                .into_iter()
+                .map(|(result, duplicates)| SearchResult { duplicates, ..result })
+                .collect();
+        } else {
+            results.truncate(top_k);
+        }
+        
+        // Generate snippets and pick passages for the results shown only
+        for result in &mut results {
+            let doc = &self.docs[result.id];
+            if let Some((passage, score)) = doc.passages.best_match(weighted_query, &doc.postings) {
+                result.passage = PassageMatch::new(passage, &doc.decompress_text(), score);
+            }
+            result.snippet = doc.get_snippet(200);
+        }
+        results
     }
 
     // Method to set the entropy weight
@@ -614,20 +1134,51 @@ impl ResonantEngine {
         self.trend_decay = decay;
     }
     
+    /// Set how much a quoted phrase match adds to the score
+    pub fn set_phrase_weight(&mut self, weight: f64) {
+        self.phrase_weight = weight;
+    }
+    
+    /// Set how much query word proximity adds to the score
+    pub fn set_proximity_weight(&mut self, weight: f64) {
+        self.proximity_weight = weight;
+    }
+    
+    /// Set how many candidates `similar_documents` considers, trading latency for recall
+    pub fn set_ann_ef_search(&mut self, ef_search: usize) {
+        self.ann_config.ef_search = ef_search.max(1);
+        if let Some(ann_index) = &mut self.ann_index {
+            ann_index.set_ef_search(ef_search);
+        }
+    }
+    
+    /// Choose how term counts are weighed in the resonance score
+    pub fn set_weighting(&mut self, weighting: Weighting) {
+        self.weighting = weighting;
+    }
+    
+    /// Choose the two document views the biorthogonal vectors are built from
+    ///
+    /// Only affects documents added afterwards.
+    pub fn set_biorthogonal_views(&mut self, views: BiorthogonalViews) {
+        self.biorthogonal_views = views;
+    }
+    
     // Apply a quantum jump to the documents (for dynamic updates)
     pub fn apply_quantum_jump(&mut self, query: &str, importance: f64) {
-        let query_tokens = self.tokenizer.tokenize(query);
+        let query_tokens = self.tokenizer.tokenize_known(query).primes;
         if query_tokens.is_empty() {
             return;
         }
         
         let query_vec = build_vector(&query_tokens);
+        let query_sparse = SparseVector::from(&query_vec);
         
         // Create a simple Hamiltonian for the system
         for doc in &mut self.docs {
             // Convert vectors to dense format for quantum operations
-            let doc_dense = to_dense_vector(&doc.vector, 100);
-            let query_dense = to_dense_vector(&query_vec, 100);
+            let doc_dense = project_dense_sparse(&doc.vector, 100);
+            let query_dense = project_dense(&query_vec, 100);
             
             // Skip if too small
             if doc_dense.is_empty() || query_dense.is_empty() {
@@ -635,12 +1186,12 @@ impl ResonantEngine {
             }
             
             // Calculate resonance as overlap
-            let resonance = dot_product(&query_vec, &doc.vector);
+            let resonance = query_sparse.dot(&doc.vector);
             
             // If the document resonates with the query, boost its relevance
             if resonance > 0.1 {
                 // Add the query vector to the document's historical vectors
-                let current_vec = to_dense_vector(&doc.vector, 1000);
+                let current_vec = project_dense_sparse(&doc.vector, DENSE_DIMENSION);
                 if doc.historical_vectors.len() < 5 {
                     doc.historical_vectors.push(current_vec);
                 } else if !doc.historical_vectors.is_empty() {
@@ -650,7 +1201,7 @@ impl ResonantEngine {
                 }
                 
                 // Increase reversibility based on match strength
-                doc.reversibility = doc.reversibility * 0.9 + 0.1 * (resonance * importance);
+                doc.reversibility = (doc.reversibility * 0.9 + 0.1 * (resonance * importance)).clamp(0.0, 1.0);
                 
                 // Update timestamp to mark it as "fresher"
                 let now = SystemTime::now()
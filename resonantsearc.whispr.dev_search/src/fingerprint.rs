@@ -0,0 +1,325 @@
+// src/fingerprint.rs
+
+use crate::postings::Postings;
+use crate::prime_hilbert::mix_hash;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Consecutive tokens hashed together into one shingle.
+pub const SHINGLE_SIZE: usize = 4;
+
+/// Number of MinHash values in a fingerprint.
+pub const MINHASH_SIZE: usize = 64;
+
+/// Bands the MinHash values are split into to find candidate duplicates;
+/// two documents become candidates when all the values of one band agree.
+pub const MINHASH_BANDS: usize = 16;
+
+/// Estimated shingle overlap (Jaccard similarity) from which two documents
+/// count as near-duplicates.
+pub const NEAR_DUPLICATE_SIMILARITY: f64 = 0.8;
+
+/// Seed of the hash that derives the MinHash permutations, fixed so stored
+/// and freshly computed fingerprints line up.
+const MINHASH_SEED: u64 = 0x3c6e_f372_fe94_f82b;
+
+/// MinHash signature of a document's token shingles. Copies of the same
+/// content (mirrors, print versions, pages repeated with small edits)
+/// share most of the values.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fingerprint {
+    /// For each permutation, the smallest shingle hash; empty for a document
+    /// without tokens.
+    pub minhash: Vec<u64>,
+}
+
+impl Fingerprint {
+    /// Fingerprints a document's prime tokens, in document order. A document
+    /// shorter than `SHINGLE_SIZE` is one shingle.
+    pub fn new(primes: &[u64]) -> Self {
+        if primes.is_empty() {
+            return Fingerprint::default();
+        }
+
+        let seeds: Vec<u64> = (0..MINHASH_SIZE as u64).map(|i| mix_hash(MINHASH_SEED ^ i)).collect();
+        let mut minhash = vec![u64::MAX; MINHASH_SIZE];
+        for shingle in primes.windows(SHINGLE_SIZE.min(primes.len())) {
+            let hash = shingle.iter().fold(MINHASH_SEED, |hash, &prime| mix_hash(hash ^ prime));
+            for (value, &seed) in minhash.iter_mut().zip(&seeds) {
+                *value = (*value).min(mix_hash(hash ^ seed));
+            }
+        }
+        Fingerprint { minhash }
+    }
+
+    /// Fingerprints the token stream a document's postings were built from.
+    pub fn from_postings(postings: &Postings) -> Self {
+        let mut positions: Vec<(u32, u64)> = postings
+            .iter()
+            .flat_map(|(&prime, positions)| positions.iter().map(move |&position| (position, prime)))
+            .collect();
+        positions.sort_unstable();
+        let primes: Vec<u64> = positions.into_iter().map(|(_, prime)| prime).collect();
+        Self::new(&primes)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.minhash.is_empty()
+    }
+
+    /// Estimated Jaccard similarity of the two documents' shingles: the
+    /// fraction of MinHash values they share (0 if either is empty).
+    pub fn similarity(&self, other: &Fingerprint) -> f64 {
+        if self.is_empty() || self.minhash.len() != other.minhash.len() {
+            return 0.0;
+        }
+        let shared = self.minhash.iter().zip(&other.minhash).filter(|(a, b)| a == b).count();
+        shared as f64 / self.minhash.len() as f64
+    }
+
+    pub fn is_near_duplicate(&self, other: &Fingerprint) -> bool {
+        self.similarity(other) >= NEAR_DUPLICATE_SIMILARITY
+    }
+
+    /// One hash per band of the MinHash values, including the band's
+    /// position, so fingerprints sharing any of them are candidates.
+    pub fn band_hashes(&self) -> Vec<u64> {
+        if self.is_empty() {
+            return Vec::new();
+        }
+        let rows = self.minhash.len().div_ceil(MINHASH_BANDS);
+        self.minhash
+            .chunks(rows)
+            .enumerate()
+            .map(|(band, values)| values.iter().fold(band as u64, |hash, &value| mix_hash(hash ^ value)))
+            .collect()
+    }
+
+    /// The MinHash values as little-endian bytes, for storage.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.minhash.iter().flat_map(|value| value.to_le_bytes()).collect()
+    }
+
+    /// Reads a fingerprint written by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let minhash = bytes
+            .chunks_exact(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap_or_default()))
+            .collect();
+        Fingerprint { minhash }
+    }
+}
+
+/// Fingerprints of a collection by document id, with the MinHash bands
+/// that find a document's near-duplicates without comparing it to all of
+/// them.
+#[derive(Debug, Default)]
+pub struct DuplicateIndex {
+    fingerprints: HashMap<usize, Fingerprint>,
+    buckets: HashMap<u64, Vec<usize>>,
+}
+
+impl DuplicateIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.fingerprints.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fingerprints.is_empty()
+    }
+
+    pub fn contains(&self, id: usize) -> bool {
+        self.fingerprints.contains_key(&id)
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = usize> + '_ {
+        self.fingerprints.keys().copied()
+    }
+
+    /// Adds a document's fingerprint, replacing any it had.
+    pub fn insert(&mut self, id: usize, fingerprint: Fingerprint) {
+        self.remove(id);
+        for hash in fingerprint.band_hashes() {
+            self.buckets.entry(hash).or_default().push(id);
+        }
+        self.fingerprints.insert(id, fingerprint);
+    }
+
+    pub fn remove(&mut self, id: usize) {
+        let Some(fingerprint) = self.fingerprints.remove(&id) else {
+            return;
+        };
+        for hash in fingerprint.band_hashes() {
+            if let Some(ids) = self.buckets.get_mut(&hash) {
+                ids.retain(|&other| other != id);
+                if ids.is_empty() {
+                    self.buckets.remove(&hash);
+                }
+            }
+        }
+    }
+
+    /// Documents that are near-duplicates of `fingerprint`, most similar first.
+    pub fn near_duplicates(&self, fingerprint: &Fingerprint) -> Vec<(usize, f64)> {
+        let mut candidates: Vec<usize> = fingerprint
+            .band_hashes()
+            .iter()
+            .filter_map(|hash| self.buckets.get(hash))
+            .flatten()
+            .copied()
+            .collect();
+        candidates.sort_unstable();
+        candidates.dedup();
+
+        let mut duplicates: Vec<(usize, f64)> = candidates
+            .into_iter()
+            .map(|id| (id, fingerprint.similarity(&self.fingerprints[&id])))
+            .filter(|&(_, similarity)| similarity >= NEAR_DUPLICATE_SIMILARITY)
+            .collect();
+        duplicates.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        duplicates
+    }
+
+    /// Groups of two or more documents linked by near-duplicate pairs, each
+    /// sorted by id, smallest first.
+    pub fn clusters(&self) -> Vec<Vec<usize>> {
+        let mut ids: Vec<usize> = self.fingerprints.keys().copied().collect();
+        ids.sort_unstable();
+        let position: HashMap<usize, usize> = ids.iter().enumerate().map(|(i, &id)| (id, i)).collect();
+
+        // Union-find over positions, always keeping the smaller root
+        let mut parents: Vec<usize> = (0..ids.len()).collect();
+        for (i, &id) in ids.iter().enumerate() {
+            for (other, _) in self.near_duplicates(&self.fingerprints[&id]) {
+                let (a, b) = (root(&mut parents, i), root(&mut parents, position[&other]));
+                parents[a.max(b)] = a.min(b);
+            }
+        }
+
+        let mut groups: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (i, &id) in ids.iter().enumerate() {
+            groups.entry(root(&mut parents, i)).or_default().push(id);
+        }
+        let mut clusters: Vec<Vec<usize>> = groups.into_values().filter(|group| group.len() > 1).collect();
+        clusters.sort_unstable();
+        clusters
+    }
+}
+
+/// Root of `i` in a union-find forest, halving the path on the way.
+fn root(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+/// Keeps the best-ranked of each group of near-duplicates in `ranked`, up
+/// to `limit` results, with how many lower-ranked near-duplicates each one
+/// absorbed before the limit was reached. Results with an empty fingerprint
+/// are never collapsed.
+pub fn collapse_near_duplicates<T>(
+    ranked: Vec<T>,
+    limit: usize,
+    mut fingerprint: impl FnMut(&T) -> Fingerprint,
+) -> Vec<(T, usize)> {
+    let mut kept: Vec<(T, usize, Fingerprint)> = Vec::new();
+    for result in ranked {
+        if kept.len() >= limit {
+            break;
+        }
+        let result_fingerprint = fingerprint(&result);
+        if let Some((_, duplicates, _)) = kept
+            .iter_mut()
+            .find(|(_, _, kept_fingerprint)| kept_fingerprint.is_near_duplicate(&result_fingerprint))
+        {
+            *duplicates += 1;
+        } else {
+            kept.push((result, 0, result_fingerprint));
+        }
+    }
+    kept.into_iter().map(|(result, duplicates, _)| (result, duplicates)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::postings::build_postings;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    // A document of `length` words drawn from a vocabulary of a few hundred
+    fn document(rng: &mut StdRng, vocabulary: &[u64], length: usize) -> Vec<u64> {
+        (0..length).map(|_| vocabulary[rng.gen_range(0..vocabulary.len())]).collect()
+    }
+
+    fn vocabulary() -> Vec<u64> {
+        primal::Primes::all().take(400).map(|prime| prime as u64).collect()
+    }
+
+    // A copy with a word replaced, one inserted and one removed
+    fn lightly_edited(rng: &mut StdRng, vocabulary: &[u64], original: &[u64]) -> Vec<u64> {
+        let mut copy = original.to_vec();
+        let length = copy.len();
+        copy[rng.gen_range(0..length)] = vocabulary[rng.gen_range(0..vocabulary.len())];
+        copy.insert(rng.gen_range(0..length), vocabulary[rng.gen_range(0..vocabulary.len())]);
+        copy.remove(rng.gen_range(0..length));
+        copy
+    }
+
+    #[test]
+    fn lightly_edited_copies_are_near_duplicates() {
+        let vocabulary = vocabulary();
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..20 {
+            let original = document(&mut rng, &vocabulary, 300);
+            let copy = lightly_edited(&mut rng, &vocabulary, &original);
+
+            let similarity = Fingerprint::new(&original).similarity(&Fingerprint::new(&copy));
+            assert!(similarity >= NEAR_DUPLICATE_SIMILARITY, "similarity {}", similarity);
+            assert!(Fingerprint::new(&original).is_near_duplicate(&Fingerprint::new(&copy)));
+        }
+    }
+
+    #[test]
+    fn unrelated_documents_are_not_near_duplicates() {
+        let vocabulary = vocabulary();
+        let mut rng = StdRng::seed_from_u64(2);
+        for _ in 0..20 {
+            let first = document(&mut rng, &vocabulary, 300);
+            let second = document(&mut rng, &vocabulary, 300);
+            let similarity = Fingerprint::new(&first).similarity(&Fingerprint::new(&second));
+            assert!(similarity < NEAR_DUPLICATE_SIMILARITY, "similarity {}", similarity);
+
+            // The same words reversed share no shingles either
+            let mut shuffled = first.clone();
+            shuffled.reverse();
+            assert!(!Fingerprint::new(&first).is_near_duplicate(&Fingerprint::new(&shuffled)));
+        }
+    }
+
+    #[test]
+    fn duplicate_index_finds_the_edited_copy_only() {
+        let vocabulary = vocabulary();
+        let mut rng = StdRng::seed_from_u64(3);
+        let original = document(&mut rng, &vocabulary, 300);
+        let copy = lightly_edited(&mut rng, &vocabulary, &original);
+
+        let mut index = DuplicateIndex::new();
+        index.insert(0, Fingerprint::new(&original));
+        for id in 1..50 {
+            index.insert(id, Fingerprint::new(&document(&mut rng, &vocabulary, 300)));
+        }
+        index.insert(50, Fingerprint::from_postings(&build_postings(&copy)));
+
+        let duplicates = index.near_duplicates(&Fingerprint::new(&original));
+        let ids: Vec<usize> = duplicates.iter().map(|&(id, _)| id).collect();
+        assert_eq!(ids, vec![0, 50]);
+        assert_eq!(index.clusters(), vec![vec![0, 50]]);
+    }
+}
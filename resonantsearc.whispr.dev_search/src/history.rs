@@ -0,0 +1,184 @@
+// src/history.rs
+
+use crate::prime_hilbert::PrimeVector;
+use crate::sparse_vector::SparseVector;
+use crate::quantum_types::jensen_shannon_divergence;
+use serde::{Deserialize, Serialize};
+
+/// Content changes per day assumed for a document that has not been
+/// fetched twice yet.
+pub const DEFAULT_UPDATE_FREQUENCY: f64 = 0.1;
+
+/// Shortest span of history, in days, the update frequency is measured
+/// over, so two fetches a minute apart do not make a document look like
+/// it changes thousands of times a day.
+const MIN_HISTORY_DAYS: f64 = 1.0;
+
+/// One fetch of a document.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Fetch {
+    /// Unix time of the fetch, in seconds.
+    pub timestamp: u64,
+    /// `content_hash` of the text fetched.
+    pub content_hash: u64,
+    /// How far the content moved from the previous fetch, from
+    /// `content_change` (0 to 1); 0 for the first fetch.
+    pub change: f64,
+}
+
+/// How often and how much a document changes, from its fetch history.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UpdateStats {
+    /// Content changes per day.
+    pub update_frequency: f64,
+    /// Mean change of the fetches that changed the content (0 to 1).
+    pub change_magnitude: f64,
+    /// Number of fetches the statistics come from.
+    pub fetches: usize,
+}
+
+impl Default for UpdateStats {
+    fn default() -> Self {
+        UpdateStats { update_frequency: DEFAULT_UPDATE_FREQUENCY, change_magnitude: 0.0, fetches: 0 }
+    }
+}
+
+impl UpdateStats {
+    /// Statistics of a fetch history in any order. With fewer than two
+    /// fetches nothing is known about updates, and the defaults are used.
+    pub fn from_history(history: &[Fetch]) -> Self {
+        if history.len() < 2 {
+            return UpdateStats { fetches: history.len(), ..UpdateStats::default() };
+        }
+
+        let first = history.iter().map(|fetch| fetch.timestamp).min().unwrap_or(0);
+        let last = history.iter().map(|fetch| fetch.timestamp).max().unwrap_or(0);
+        let days = ((last - first) as f64 / (24.0 * 3600.0)).max(MIN_HISTORY_DAYS);
+
+        let changes: Vec<f64> = history.iter().map(|fetch| fetch.change).filter(|&change| change > 0.0).collect();
+        let change_magnitude = if changes.is_empty() {
+            0.0
+        } else {
+            changes.iter().sum::<f64>() / changes.len() as f64
+        };
+
+        UpdateStats {
+            update_frequency: changes.len() as f64 / days,
+            change_magnitude,
+            fetches: history.len(),
+        }
+    }
+
+    /// Reversibility of the document across its own versions: one minus the
+    /// change magnitude, or `fallback` until it has been fetched twice.
+    pub fn reversibility_or(&self, fallback: f64) -> f64 {
+        if self.fetches < 2 {
+            fallback
+        } else {
+            1.0 - self.change_magnitude
+        }
+    }
+}
+
+/// FNV-1a over the text, stable across platforms and compiler versions.
+pub fn content_hash(text: &str) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in text.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+/// Jensen-Shannon divergence between the word distributions of two
+/// versions of a document (0 for the same words, 1 for none in common).
+pub fn content_change(old: &PrimeVector, new: &PrimeVector) -> f64 {
+    let mut primes: Vec<u64> = old.keys().chain(new.keys()).copied().collect();
+    primes.sort_unstable();
+    primes.dedup();
+
+    let old_weights: Vec<f64> = primes.iter().map(|prime| old.get(prime).copied().unwrap_or(0.0)).collect();
+    let new_weights: Vec<f64> = primes.iter().map(|prime| new.get(prime).copied().unwrap_or(0.0)).collect();
+    jensen_shannon_divergence(&old_weights, &new_weights)
+}
+
+/// Like `content_change`, for `SparseVector`s.
+pub fn content_change_sparse(old: &SparseVector, new: &SparseVector) -> f64 {
+    let mut primes: Vec<u64> = old.keys().chain(new.keys()).collect();
+    primes.sort_unstable();
+    primes.dedup();
+
+    let old_weights: Vec<f64> = primes.iter().map(|&prime| old.get(prime).unwrap_or(0.0)).collect();
+    let new_weights: Vec<f64> = primes.iter().map(|&prime| new.get(prime).unwrap_or(0.0)).collect();
+    jensen_shannon_divergence(&old_weights, &new_weights)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: u64 = 24 * 3600;
+
+    fn fetch(day: u64, content_hash: u64, change: f64) -> Fetch {
+        Fetch { timestamp: 1_700_000_000 + day * DAY, content_hash, change }
+    }
+
+    #[test]
+    fn fewer_than_two_fetches_use_the_defaults() {
+        for history in [Vec::new(), vec![fetch(0, 1, 0.0)]] {
+            let stats = UpdateStats::from_history(&history);
+            assert_eq!(stats.update_frequency, DEFAULT_UPDATE_FREQUENCY);
+            assert_eq!(stats.change_magnitude, 0.0);
+            assert_eq!(stats.fetches, history.len());
+            assert_eq!(stats.reversibility_or(0.7), 0.7);
+        }
+    }
+
+    #[test]
+    fn unchanged_content_never_changes() {
+        let hash = content_hash("the same page");
+        assert_eq!(hash, content_hash("the same page"));
+        assert_ne!(hash, content_hash("the same page, edited"));
+
+        let history: Vec<Fetch> = (0..5).map(|day| fetch(day, hash, 0.0)).collect();
+        let stats = UpdateStats::from_history(&history);
+        assert_eq!(stats.update_frequency, 0.0);
+        assert_eq!(stats.change_magnitude, 0.0);
+        assert_eq!(stats.fetches, 5);
+        assert_eq!(stats.reversibility_or(0.7), 1.0);
+    }
+
+    #[test]
+    fn changes_are_counted_per_day_in_any_order() {
+        // Two changes, of 0.2 and 0.6, over ten days
+        let history = [fetch(10, 3, 0.6), fetch(0, 1, 0.0), fetch(5, 2, 0.2), fetch(7, 2, 0.0)];
+        let stats = UpdateStats::from_history(&history);
+        assert!((stats.update_frequency - 0.2).abs() < 1e-12, "{}", stats.update_frequency);
+        assert!((stats.change_magnitude - 0.4).abs() < 1e-12, "{}", stats.change_magnitude);
+        assert!((stats.reversibility_or(0.0) - 0.6).abs() < 1e-12);
+
+        // Fetches a minute apart count as a day
+        let first = fetch(0, 1, 0.0);
+        let history = [first, Fetch { timestamp: first.timestamp + 60, content_hash: 2, change: 0.5 }];
+        assert_eq!(UpdateStats::from_history(&history).update_frequency, 1.0);
+    }
+
+    #[test]
+    fn fnv_hash_is_stable() {
+        assert_eq!(content_hash(""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(content_hash("a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn content_change_runs_from_same_words_to_none_in_common() {
+        let old: PrimeVector = [(2, 1.0), (3, 2.0)].into_iter().collect();
+        let reweighted: PrimeVector = [(2, 2.0), (3, 1.0)].into_iter().collect();
+        let disjoint: PrimeVector = [(5, 1.0), (7, 1.0)].into_iter().collect();
+
+        assert!(content_change(&old, &old).abs() < 1e-12);
+        assert!((content_change(&old, &disjoint) - 1.0).abs() < 1e-12);
+        let change = content_change(&old, &reweighted);
+        assert!(change > 0.0 && change < 1.0, "{}", change);
+        assert!((content_change_sparse(&old.clone().into(), &reweighted.clone().into()) - change).abs() < 1e-12);
+    }
+}
@@ -2,6 +2,7 @@
 
 use crate::database::{DocumentDatabase, StoredDocument, prime_vector_to_document};
 use crate::prime_hilbert::{PrimeVector, BiorthogonalVector};
+use crate::language::detect_language;
 use std::path::Path;
 use std::fs::File;
 use std::io::{self, BufRead, BufReader};
@@ -57,7 +58,8 @@ impl ImportTool {
             let url = parts[0].to_string();
             let title = parts[1].to_string();
             let entropy: f64 = parts[2].parse().unwrap_or(0.0);
-            let reversibility: f64 = parts[3].parse().unwrap_or(1.0);
+            // Older checkpoints could hold reversibility above 1
+            let reversibility: f64 = parts[3].parse::<f64>().unwrap_or(1.0).clamp(0.0, 1.0);
             let timestamp: u64 = parts[4].parse().unwrap_or(0);
             
             // Create placeholder document (without actual text content)
@@ -82,10 +84,16 @@ impl ImportTool {
                 id: None,
                 url,
                 title,
+                headings: String::new(),
                 text_snippet: placeholder_text.clone(),
                 compressed_text,
                 vector_data: serde_json::to_string(&vector)?,
+                ngram_data: "{}".to_string(),
                 biorthogonal_data: serde_json::to_string(&biorthogonal)?,
+                postings_data: "{}".to_string(), // Token positions are not part of the source data
+                passages_data: "{}".to_string(), // Nor are the passages cut from them
+                density_data: "{}".to_string(),
+                lang: String::new(),
                 entropy,
                 reversibility,
                 buffering,
@@ -155,10 +163,16 @@ impl ImportTool {
                 id: None,
                 url,
                 title,
+                headings: String::new(),
                 text_snippet: placeholder_text.clone(),
                 compressed_text,
                 vector_data: serde_json::to_string(&vector)?,
+                ngram_data: "{}".to_string(),
                 biorthogonal_data: serde_json::to_string(&biorthogonal)?,
+                postings_data: "{}".to_string(), // Token positions are not part of the source data
+                passages_data: "{}".to_string(), // Nor are the passages cut from them
+                density_data: "{}".to_string(),
+                lang: String::new(),
                 entropy,
                 reversibility,
                 buffering,
@@ -220,7 +234,7 @@ impl ImportTool {
             let title = item["title"].as_str().unwrap_or("Untitled").to_string();
             let text = item["text"].as_str().unwrap_or("").to_string();
             let entropy = item["entropy"].as_f64().unwrap_or(0.0);
-            let reversibility = item["reversibility"].as_f64().unwrap_or(1.0);
+            let reversibility = item["reversibility"].as_f64().unwrap_or(1.0).clamp(0.0, 1.0);
             let buffering = item["buffering"].as_f64().unwrap_or(0.5);
             let timestamp = item["timestamp"].as_u64().unwrap_or_else(|| {
                 std::time::SystemTime::now()
@@ -294,10 +308,16 @@ impl ImportTool {
                 id: None,
                 url,
                 title,
+                headings: String::new(),
                 text_snippet: snippet,
                 compressed_text,
                 vector_data: serde_json::to_string(&vector)?,
+                ngram_data: "{}".to_string(),
                 biorthogonal_data: serde_json::to_string(&biorthogonal)?,
+                postings_data: "{}".to_string(), // Token positions are not part of the source data
+                passages_data: "{}".to_string(), // Nor are the passages cut from them
+                density_data: "{}".to_string(),
+                lang: detect_language(&text, None),
                 entropy,
                 reversibility,
                 buffering,
@@ -0,0 +1,367 @@
+// src/inverted_index.rs
+
+use crate::postings::Postings;
+use crate::sparse_vector::SparseVector;
+use crate::weighting::{document_length, log_tf, CorpusStats, Weighting};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+/// One document in a word's posting list.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Posting {
+    /// Position of the document in the engine.
+    pub doc: usize,
+    /// How often the word occurs in it.
+    pub tf: u32,
+}
+
+/// The extremes of a word's weights over the documents that contain it,
+/// from which `Weighting::max_document_weight` bounds its contribution.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TermBounds {
+    pub max_tf: u32,
+    /// Length of the shortest document containing the word.
+    pub min_length: u64,
+    /// Largest cosine-normalized log-TF weight (the lnc document weight).
+    pub max_log_tf: f64,
+    /// Largest share of a document's tokens that are this word.
+    pub max_relative_tf: f64,
+    /// Largest value in the documents' stored frequency vectors.
+    pub max_raw: f64,
+}
+
+impl Default for TermBounds {
+    fn default() -> Self {
+        TermBounds {
+            max_tf: 0,
+            min_length: u64::MAX,
+            max_log_tf: 0.0,
+            max_relative_tf: 0.0,
+            max_raw: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct PostingList {
+    postings: Vec<Posting>,
+    bounds: TermBounds,
+}
+
+/// Maps each word prime to the documents containing it, in document order.
+///
+/// Top-k retrieval uses WAND: documents are visited in order across the
+/// query's posting lists, and a document is only scored once the summed
+/// upper bounds of the lists it appears in could beat the k-th best score
+/// so far. Documents that share no word with the query are never visited.
+#[derive(Debug, Clone, Default)]
+pub struct InvertedIndex {
+    lists: HashMap<u64, PostingList>,
+}
+
+impl InvertedIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a document's words, keeping every posting list in document order.
+    pub fn add_document(&mut self, doc: usize, postings: &Postings, vector: &SparseVector) {
+        let length = document_length(postings);
+        let norm = postings
+            .values()
+            .map(|positions| log_tf(positions.len() as f64).powi(2))
+            .sum::<f64>()
+            .sqrt();
+
+        for (&prime, positions) in postings {
+            let tf = positions.len() as u32;
+            let list = self.lists.entry(prime).or_default();
+            let position = list.postings.partition_point(|posting| posting.doc < doc);
+            list.postings.insert(position, Posting { doc, tf });
+
+            let bounds = &mut list.bounds;
+            bounds.max_tf = bounds.max_tf.max(tf);
+            bounds.min_length = bounds.min_length.min(length);
+            if norm > 0.0 {
+                bounds.max_log_tf = bounds.max_log_tf.max(log_tf(tf as f64) / norm);
+            }
+            if length > 0 {
+                bounds.max_relative_tf = bounds.max_relative_tf.max(tf as f64 / length as f64);
+            }
+            bounds.max_raw = bounds.max_raw.max(vector.get(prime).unwrap_or(0.0));
+        }
+    }
+
+    /// Removes a document added with these postings. The bounds of its words
+    /// are left as they were, still above every remaining posting.
+    pub fn remove_document(&mut self, doc: usize, postings: &Postings) {
+        for prime in postings.keys() {
+            if let Some(list) = self.lists.get_mut(prime) {
+                list.postings.retain(|posting| posting.doc != doc);
+                if list.postings.is_empty() {
+                    self.lists.remove(prime);
+                }
+            }
+        }
+    }
+
+    /// Number of words with a posting list.
+    pub fn len(&self) -> usize {
+        self.lists.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lists.is_empty()
+    }
+
+    /// The documents containing a word, in document order.
+    pub fn postings(&self, prime: u64) -> &[Posting] {
+        self.lists.get(&prime).map_or(&[], |list| &list.postings)
+    }
+
+    pub fn bounds(&self, prime: u64) -> Option<TermBounds> {
+        self.lists.get(&prime).map(|list| list.bounds)
+    }
+
+    /// Finds the `k` documents with the highest `score` among those sharing a
+    /// word with `query`, best first.
+    ///
+    /// `score` returns a document's exact resonance, or `None` to leave it
+    /// out (for example when it is in another language). It must never exceed
+    /// the sum over the document's query words of the query weight times
+    /// `weighting.max_document_weight`, which holds for `Weighting::resonance`.
+    pub fn top_k(
+        &self,
+        query: &SparseVector,
+        weighting: Weighting,
+        stats: &CorpusStats,
+        k: usize,
+        mut score: impl FnMut(usize) -> Option<f64>,
+    ) -> Vec<(usize, f64)> {
+        if k == 0 {
+            return Vec::new();
+        }
+
+        let mut cursors: Vec<Cursor> = query
+            .iter()
+            .filter_map(|(prime, weight)| {
+                let list = self.lists.get(&prime)?;
+                let upper_bound = weight.max(0.0) * weighting.max_document_weight(prime, &list.bounds, stats);
+                Some(Cursor { postings: &list.postings, position: 0, upper_bound })
+            })
+            .collect();
+
+        let mut heap: BinaryHeap<Candidate> = BinaryHeap::with_capacity(k + 1);
+        loop {
+            cursors.retain(|cursor| cursor.doc().is_some());
+            if cursors.is_empty() {
+                break;
+            }
+            cursors.sort_by_key(|cursor| cursor.doc());
+
+            // The first document whose lists could together beat the threshold
+            let threshold = if heap.len() < k { f64::NEG_INFINITY } else { heap.peek().map_or(f64::NEG_INFINITY, |c| c.score) };
+            let mut bound = 0.0;
+            let mut pivot = None;
+            for (index, cursor) in cursors.iter().enumerate() {
+                bound += cursor.upper_bound;
+                if bound > threshold {
+                    pivot = Some(index);
+                    break;
+                }
+            }
+            let Some(pivot) = pivot else {
+                break;
+            };
+            let pivot_doc = cursors[pivot].doc().unwrap_or(usize::MAX);
+
+            if cursors[0].doc() == Some(pivot_doc) {
+                if let Some(value) = score(pivot_doc) {
+                    if heap.len() < k || value > threshold {
+                        heap.push(Candidate { doc: pivot_doc, score: value });
+                        if heap.len() > k {
+                            heap.pop();
+                        }
+                    }
+                }
+                for cursor in cursors.iter_mut().filter(|cursor| cursor.doc() == Some(pivot_doc)) {
+                    cursor.position += 1;
+                }
+            } else {
+                // Nothing before the pivot can make it, so skip ahead to it
+                for cursor in &mut cursors[..pivot] {
+                    cursor.seek(pivot_doc);
+                }
+            }
+        }
+
+        let mut results: Vec<(usize, f64)> = heap.into_iter().map(|c| (c.doc, c.score)).collect();
+        results.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal).then(a.0.cmp(&b.0)));
+        results
+    }
+}
+
+struct Cursor<'a> {
+    postings: &'a [Posting],
+    position: usize,
+    upper_bound: f64,
+}
+
+impl Cursor<'_> {
+    fn doc(&self) -> Option<usize> {
+        self.postings.get(self.position).map(|posting| posting.doc)
+    }
+
+    // Moves to the first posting at or after `doc`
+    fn seek(&mut self, doc: usize) {
+        self.position += self.postings[self.position..].partition_point(|posting| posting.doc < doc);
+    }
+}
+
+// Min-heap entry: the worst of the current top k is on top
+struct Candidate {
+    doc: usize,
+    score: f64,
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .score
+            .partial_cmp(&self.score)
+            .unwrap_or(Ordering::Equal)
+            .then(self.doc.cmp(&other.doc))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::postings::build_postings;
+    use crate::prime_hilbert::build_vector;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    const WORDS: [u64; 12] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37];
+
+    // Documents of varied length with a skewed word distribution, so some
+    // words are common and others rare
+    fn corpus(rng: &mut StdRng, size: usize) -> Vec<(Postings, SparseVector)> {
+        (0..size)
+            .map(|_| {
+                let length = rng.gen_range(3..40);
+                let tokens: Vec<u64> = (0..length)
+                    .map(|_| WORDS[(rng.gen::<f64>().powi(2) * WORDS.len() as f64) as usize])
+                    .collect();
+                (build_postings(&tokens), SparseVector::from(build_vector(&tokens)))
+            })
+            .collect()
+    }
+
+    fn index(documents: &[(Postings, SparseVector)]) -> (InvertedIndex, CorpusStats) {
+        let mut index = InvertedIndex::new();
+        for (doc, (postings, vector)) in documents.iter().enumerate() {
+            index.add_document(doc, postings, vector);
+        }
+        (index, CorpusStats::from_postings(documents.iter().map(|(postings, _)| postings)))
+    }
+
+    // Scores every document sharing a word with the query
+    fn exhaustive(
+        documents: &[(Postings, SparseVector)],
+        query: &SparseVector,
+        weighting: Weighting,
+        stats: &CorpusStats,
+        k: usize,
+    ) -> Vec<(usize, f64)> {
+        let mut results: Vec<(usize, f64)> = documents
+            .iter()
+            .enumerate()
+            .filter(|(_, (postings, _))| query.keys().any(|prime| postings.contains_key(&prime)))
+            .map(|(doc, (postings, vector))| (doc, weighting.resonance(query, vector, postings, stats)))
+            .collect();
+        results.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal).then(a.0.cmp(&b.0)));
+        results.truncate(k);
+        results
+    }
+
+    fn assert_same_ranking(wand: &[(usize, f64)], exhaustive: &[(usize, f64)], weighting: Weighting) {
+        assert_eq!(wand.len(), exhaustive.len(), "{}", weighting.name());
+        for (&(wand_doc, wand_score), &(doc, score)) in wand.iter().zip(exhaustive) {
+            assert!((wand_score - score).abs() < 1e-9, "{}: {} against {}", weighting.name(), wand_score, score);
+            // Tied documents may come in either order
+            if (wand_score - score).abs() < 1e-12 && wand_doc != doc {
+                continue;
+            }
+            assert_eq!(wand_doc, doc, "{}", weighting.name());
+        }
+    }
+
+    #[test]
+    fn top_k_matches_exhaustive_scoring_for_every_weighting() {
+        let weightings = [
+            Weighting::Raw,
+            Weighting::LogTf,
+            Weighting::TfIdf,
+            Weighting::bm25(),
+            Weighting::Bm25 { k1: 2.0, b: 0.0 },
+            Weighting::dirichlet(),
+            Weighting::jelinek_mercer(),
+        ];
+        let mut rng = StdRng::seed_from_u64(7);
+        let documents = corpus(&mut rng, 300);
+        let (index, stats) = index(&documents);
+
+        for _ in 0..40 {
+            let query_length = rng.gen_range(1..5);
+            let primes: Vec<u64> = (0..query_length).map(|_| WORDS[rng.gen_range(0..WORDS.len())]).collect();
+            for weighting in weightings {
+                let query = weighting.query_vector(&primes, &stats);
+                for k in [1, 5, 20] {
+                    let wand = index.top_k(&query, weighting, &stats, k, |doc| {
+                        let (postings, vector) = &documents[doc];
+                        Some(weighting.resonance(&query, vector, postings, &stats))
+                    });
+                    assert_same_ranking(&wand, &exhaustive(&documents, &query, weighting, &stats, k), weighting);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn top_k_matches_exhaustive_scoring_after_removals() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let mut documents = corpus(&mut rng, 100);
+        let (mut index, _) = index(&documents);
+
+        // Removed documents keep their place, with nothing in them
+        for doc in (0..documents.len()).step_by(3) {
+            index.remove_document(doc, &documents[doc].0);
+            documents[doc] = (Postings::new(), SparseVector::new());
+        }
+        let stats = CorpusStats::from_postings(documents.iter().map(|(postings, _)| postings));
+
+        for weighting in [Weighting::TfIdf, Weighting::bm25(), Weighting::dirichlet()] {
+            let query = weighting.query_vector(&[2, 13, 29], &stats);
+            let wand = index.top_k(&query, weighting, &stats, 10, |doc| {
+                let (postings, vector) = &documents[doc];
+                Some(weighting.resonance(&query, vector, postings, &stats))
+            });
+            assert_same_ranking(&wand, &exhaustive(&documents, &query, weighting, &stats, 10), weighting);
+        }
+    }
+}
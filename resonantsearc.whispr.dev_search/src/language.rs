@@ -0,0 +1,128 @@
+// src/language.rs
+
+use crate::analyzer::{Analyzer, AnalyzerConfig};
+use std::collections::HashMap;
+use whatlang::{Lang, Script};
+
+/// Languages with their own analyzer settings, with the code documents are tagged with.
+const KNOWN_LANGUAGES: &[(Lang, &str)] = &[
+    (Lang::Eng, "en"),
+    (Lang::Deu, "de"),
+    (Lang::Fra, "fr"),
+    (Lang::Spa, "es"),
+    (Lang::Ita, "it"),
+    (Lang::Por, "pt"),
+    (Lang::Nld, "nl"),
+    (Lang::Swe, "sv"),
+    (Lang::Rus, "ru"),
+    (Lang::Cmn, "zh"),
+    (Lang::Jpn, "ja"),
+    (Lang::Kor, "ko"),
+];
+
+/// Characters of text looked at when guessing the language.
+const SAMPLE_CHARS: usize = 4000;
+
+/// Detector confidence needed to trust a guess. whatlang only calls a guess
+/// reliable at 0.9, which ordinary sentences rarely reach.
+const MIN_CONFIDENCE: f64 = 0.3;
+
+/// Guesses the language of a document, returning a lowercase code such as
+/// "en", "de" or "zh", or an empty string if it cannot tell.
+///
+/// A confident guess from the text wins over `declared` (the page's
+/// `<html lang>`), which is often left at a template default. Short CJK text
+/// that defeats the detector is still tagged by its script.
+pub fn detect_language(text: &str, declared: Option<&str>) -> String {
+    let sample: String = text.chars().take(SAMPLE_CHARS).collect();
+    let info = whatlang::detect(&sample);
+
+    if let Some(info) = info.as_ref().filter(|info| info.confidence() >= MIN_CONFIDENCE) {
+        return lang_code(info.lang()).to_string();
+    }
+
+    let declared = declared.map(language_tag).unwrap_or_default();
+    if !declared.is_empty() {
+        return declared;
+    }
+
+    match info.map(|info| info.script()) {
+        Some(Script::Mandarin) => "zh".to_string(),
+        Some(Script::Hiragana | Script::Katakana) => "ja".to_string(),
+        Some(Script::Hangul) => "ko".to_string(),
+        _ => String::new(),
+    }
+}
+
+/// Reduces a language tag to its lowercase primary subtag ("en-US" gives "en").
+pub fn language_tag(tag: &str) -> String {
+    tag.trim()
+        .split(['-', '_'])
+        .next()
+        .unwrap_or_default()
+        .to_lowercase()
+}
+
+/// The codes of the languages with their own analyzer settings.
+pub fn known_language_codes() -> impl Iterator<Item = &'static str> {
+    KNOWN_LANGUAGES.iter().map(|(_, code)| *code)
+}
+
+/// Returns the two-letter code for a known language and the ISO 639-3 code otherwise.
+fn lang_code(lang: Lang) -> &'static str {
+    KNOWN_LANGUAGES
+        .iter()
+        .find(|(known, _)| *known == lang)
+        .map(|(_, code)| *code)
+        .unwrap_or_else(|| lang.code())
+}
+
+/// Builds an analyzer for every known language from the same base settings.
+///
+/// The entry under the empty code is for documents in any other language.
+pub fn language_analyzers(config: &AnalyzerConfig) -> HashMap<String, Analyzer> {
+    known_language_codes()
+        .chain([""])
+        .map(|code| (code.to_string(), Analyzer::new(config.for_language(code))))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn confident_guesses_win_over_the_declared_language() {
+        let german = "Der schnelle braune Fuchs springt über den faulen Hund, und die Katze schläft auf dem Sofa im Wohnzimmer.";
+        assert_eq!(detect_language(german, Some("en-US")), "de");
+        assert_eq!(detect_language(german, None), "de");
+    }
+
+    #[test]
+    fn unsure_guesses_fall_back_to_the_declared_language() {
+        // Too little text for the detector to reach MIN_CONFIDENCE
+        for text in ["", "ok", "404 - 2024"] {
+            let confidence = whatlang::detect(text).map_or(0.0, |info| info.confidence());
+            assert!(confidence < MIN_CONFIDENCE, "{:?}: {}", text, confidence);
+            assert_eq!(detect_language(text, Some("fr_CA")), "fr", "{:?}", text);
+            assert_eq!(detect_language(text, Some(" ")), "", "{:?}", text);
+            assert_eq!(detect_language(text, None), "", "{:?}", text);
+        }
+        // Short CJK text is still tagged by its script
+        for (text, lang) in [("東京", "zh"), ("ひらがな", "ja"), ("한국", "ko")] {
+            assert_eq!(detect_language(text, None), lang, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn every_known_language_has_an_analyzer() {
+        let analyzers = language_analyzers(&AnalyzerConfig::default());
+        assert_eq!(analyzers.len(), KNOWN_LANGUAGES.len() + 1);
+        for (lang, code) in KNOWN_LANGUAGES {
+            assert_eq!(lang_code(*lang), *code);
+            assert!(analyzers.contains_key(*code), "{}", code);
+        }
+        assert!(analyzers.contains_key(""));
+        assert_eq!(lang_code(Lang::Ukr), "ukr");
+    }
+}
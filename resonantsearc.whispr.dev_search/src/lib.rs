@@ -1,8 +1,19 @@
 // src/lib.rs
 
+pub mod analyzer;
+pub mod language;
 pub mod tokenizer;
 pub mod entropy;
 pub mod prime_hilbert;
+pub mod sparse_vector;
+pub mod postings;
+pub mod passages;
+pub mod history;
+pub mod fingerprint;
+pub mod density;
+pub mod weighting;
+pub mod inverted_index;
+pub mod ann_index;
 pub mod engine;
 pub mod crawler;
 pub mod quantum_types;
@@ -10,6 +21,7 @@ pub mod quantum_types;
 // Re-export key types and functions
 pub use engine::ResonantEngine;
 pub use engine::SearchResult;
+pub use analyzer::{Analyzer, AnalyzerConfig};
 pub use crawler::CrawledDocument;
 pub use prime_hilbert::{PrimeVector, BiorthogonalVector};
 pub use quantum_types::{MatrixComplex, VectorComplex};
@@ -23,5 +35,6 @@ pub use entropy::{
     persistence_score,
     apply_non_hermitian_decay,
     apply_fragility,
-    resonant_persistence_score
+    resonant_persistence_score,
+    DecayKernel
 };
\ No newline at end of file
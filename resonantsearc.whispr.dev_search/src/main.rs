@@ -1,8 +1,19 @@
 v// src/main.rs - Web Search Engine Version
 
+mod analyzer;
+mod language;
 mod tokenizer;
 mod entropy;
 mod prime_hilbert;
+mod sparse_vector;
+mod postings;
+mod passages;
+mod history;
+mod fingerprint;
+mod density;
+mod weighting;
+mod inverted_index;
+mod ann_index;
 mod engine;
 mod crawler;
 mod quantum_types;
@@ -12,62 +23,156 @@ mod web_server;
 mod advanced_crawler;
 mod import_tool;
 
+use std::collections::HashMap;
 use std::env;
 use std::io::{self, Write};
 use std::path::Path;
 use std::sync::{Arc, Mutex};
 use tokio::sync::mpsc;
+use futures::StreamExt;
 use url::Url;
 use clap::{App, Arg, SubCommand};
 use search_api::{SearchAPI, SearchConfig};
 use database::{DocumentDatabase, StoredDocument, prime_vector_to_document};
 use crawler::CrawledDocument;
 use advanced_crawler::AdvancedCrawler;
+use analyzer::AnalyzerConfig;
+use prime_hilbert::BiorthogonalViews;
+use ann_index::{AnnConfig, AnnIndex};
+use fingerprint::{DuplicateIndex, Fingerprint};
 use web_server::start_server;
 use import_tool::ImportTool;
 
+// A crawled document run through its language's analyzer, before its words get primes
+struct AnalyzedDocument {
+    doc: CrawledDocument,
+    tokens: Vec<String>,
+    offsets: Vec<std::ops::Range<usize>>,
+    heading_tokens: Vec<String>,
+}
+
+// A crawled document with the primes of its words, ready to be stored
+struct TokenizedDocument {
+    doc: CrawledDocument,
+    tokens: Vec<u64>,
+    offsets: Vec<std::ops::Range<usize>>,
+    heading_tokens: Vec<u64>,
+}
+
 // Document processor that handles converting crawled documents to database entries
 struct DocumentProcessor {
-    tokenizer: Arc<Mutex<tokenizer::PrimeTokenizer>>,
+    // Shared by the processing workers; only new words take the write lock
+    tokenizer: Arc<tokenizer::SharedTokenizer>,
+    // Each document is tokenized with the analyzer for its language
+    language_analyzers: HashMap<String, analyzer::Analyzer>,
+    // The document views biorthogonal vectors are built from
+    biorthogonal_views: BiorthogonalViews,
     db: Arc<Mutex<DocumentDatabase>>,
+    // Nearest-neighbour graph saved next to the documents as they are stored
+    ann_index: Arc<Mutex<AnnIndex>>,
+    // Fingerprints of the stored documents, when near-duplicates are skipped
+    duplicate_index: Option<Arc<Mutex<DuplicateIndex>>>,
+    // Whether each document's density is stored for fidelity scoring; without
+    // it the density is built when a fidelity search reads the document
+    build_density: bool,
     processed_count: Arc<Mutex<usize>>,
 }
 
 impl DocumentProcessor {
-    fn new(db_path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+    fn new(db_path: &str, analyzer: AnalyzerConfig, views: BiorthogonalViews, skip_near_duplicates: bool, build_density: bool) -> Result<Self, Box<dyn std::error::Error>> {
         let db = DocumentDatabase::new(db_path)?;
+        // Continue the vocabulary already stored so primes stay consistent across crawls
+        let tokenizer = db.load_tokenizer(analyzer)?;
+        let language_analyzers = language::language_analyzers(tokenizer.analyzer().config());
+        
+        // Documents already stored were built from the recorded views
+        let biorthogonal_views = match db.get_biorthogonal_views()? {
+            Some(stored) => stored,
+            None => {
+                db.set_biorthogonal_views(views)?;
+                views
+            }
+        };
+        
+        // Pick up documents stored without the graph (imports, older databases)
+        let mut ann_index = db.load_ann_index(AnnConfig::default())?;
+        db.sync_ann_index(&mut ann_index)?;
+        
+        let duplicate_index = if skip_near_duplicates {
+            let mut duplicate_index = db.load_duplicate_index()?;
+            db.sync_duplicate_index(&mut duplicate_index)?;
+            Some(Arc::new(Mutex::new(duplicate_index)))
+        } else {
+            None
+        };
         
         Ok(DocumentProcessor {
-            tokenizer: Arc::new(Mutex::new(tokenizer::PrimeTokenizer::new())),
+            tokenizer: Arc::new(tokenizer::SharedTokenizer::new(tokenizer)),
+            language_analyzers,
+            biorthogonal_views,
             db: Arc::new(Mutex::new(db)),
+            ann_index: Arc::new(Mutex::new(ann_index)),
+            duplicate_index,
+            build_density,
             processed_count: Arc::new(Mutex::new(0)),
         })
     }
     
-    async fn process_document(&self, doc: CrawledDocument) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // Analysis needs no vocabulary, so any number of documents can go through it at once
+    fn analyze_document(&self, doc: CrawledDocument) -> Result<Option<AnalyzedDocument>, Box<dyn std::error::Error + Send + Sync>> {
         if doc.text.trim().is_empty() {
             println!("Skipping empty document: {}", doc.url);
-            return Ok(());
+            return Ok(None);
         }
         
-        // Tokenize text
-        let tokens = {
-            let mut tokenizer = self.tokenizer.lock().unwrap();
-            tokenizer.tokenize(&doc.text)
-        };
+        let analyzer = self.language_analyzers.get(&doc.lang).unwrap_or(&self.language_analyzers[""]);
+        let (tokens, offsets) = analyzer.analyze_with_offsets(&doc.text).into_iter().unzip();
+        
+        // The title and headings form the other view of the document
+        let heading_tokens = analyzer.analyze(&format!("{}\n{}", doc.title, doc.headings));
         
+        Ok(Some(AnalyzedDocument { doc, tokens, offsets, heading_tokens }))
+    }
+    
+    // Primes go to new words in the order documents pass through here, so this
+    // runs for one document at a time, in the order they were crawled
+    fn assign_primes(&self, analyzed: AnalyzedDocument) -> Result<Option<TokenizedDocument>, Box<dyn std::error::Error + Send + Sync>> {
+        let AnalyzedDocument { doc, tokens, offsets, heading_tokens } = analyzed;
         if tokens.is_empty() {
             println!("Skipping document with no tokens: {}", doc.url);
-            return Ok(());
+            return Ok(None);
         }
         
+        // Persist any newly assigned primes before the next document adds more
+        let assign = |tokens: &[String]| self.tokenizer.assign_and_commit(tokens, |tokenizer, last_prime| {
+            self.db.lock().unwrap().store_vocabulary(tokenizer, last_prime)
+        });
+        let tokens = assign(&tokens)?;
+        let heading_tokens = assign(&heading_tokens)?;
+        
+        Ok(Some(TokenizedDocument { doc, tokens, offsets, heading_tokens }))
+    }
+    
+    fn store_document(&self, tokenized: TokenizedDocument) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let TokenizedDocument { doc, tokens, offsets, heading_tokens } = tokenized;
+        
         // Calculate vector representations
         let vector = prime_hilbert::build_vector(&tokens);
-        let biorthogonal = prime_hilbert::build_biorthogonal_vector(&tokens);
+        let ngrams = prime_hilbert::build_ngram_vector(&tokens, tokenizer::MAX_NGRAM);
+        let biorthogonal = prime_hilbert::build_biorthogonal_vector(&heading_tokens, &tokens, self.biorthogonal_views);
+        let postings = postings::build_postings(&tokens);
+        let passages = passages::DocumentPassages::new(&tokens, &offsets);
+        let density = if self.build_density {
+            density::DocumentDensity::new(&postings, &passages)
+        } else {
+            density::DocumentDensity::default()
+        };
+        let fingerprint = Fingerprint::new(&tokens);
         let entropy = entropy::shannon_entropy(&tokens);
         
         // Convert to dense vector for historical comparisons
-        let dense_vec = prime_hilbert::to_dense_vector(&vector, 1000);
+        let dense_vec = prime_hilbert::project_dense(&vector, prime_hilbert::DENSE_DIMENSION);
+        let embedding = ann_index::embed(&vector);
         
         // Calculate persistence metrics
         let reversibility = 1.0; // New document is fully reversible with itself
@@ -82,10 +187,16 @@ impl DocumentProcessor {
         let stored_doc = prime_vector_to_document(
             doc.url,
             doc.title,
+            doc.headings,
             doc.text.clone(),
             compressed_text,
             vector,
+            ngrams,
             biorthogonal,
+            postings,
+            passages,
+            density,
+            doc.lang,
             entropy,
             reversibility,
             buffering,
@@ -94,7 +205,38 @@ impl DocumentProcessor {
         // Store in database
         {
             let mut db = self.db.lock().unwrap();
-            db.store_document(&stored_doc)?;
+            let old_id = db.get_document_id(&stored_doc.url)?;
+            
+            // Mirrors and copies of a stored page are left out; checked under the
+            // database lock so two copies crawled at once cannot both get in
+            let mut duplicate_index = self.duplicate_index.as_ref().map(|index| index.lock().unwrap());
+            if let Some(duplicate_index) = &duplicate_index {
+                let original = duplicate_index
+                    .near_duplicates(&fingerprint)
+                    .into_iter()
+                    .find(|&(id, _)| Some(id as i64) != old_id);
+                if let Some((original, similarity)) = original {
+                    let original_url = db.get_document_by_id(original as i64)?.map(|doc| doc.url).unwrap_or_default();
+                    println!("Skipping near-duplicate ({:.0}% similar) of {}: {}", similarity * 100.0, original_url, stored_doc.url);
+                    return Ok(());
+                }
+            }
+            
+            let id = db.store_document(&stored_doc)?;
+            if let Some(duplicate_index) = &mut duplicate_index {
+                if let Some(old_id) = old_id {
+                    duplicate_index.remove(old_id as usize);
+                }
+                duplicate_index.insert(id as usize, fingerprint);
+            }
+            
+            // A recrawled page keeps its id, but its node holds the old embedding
+            let mut ann_index = self.ann_index.lock().unwrap();
+            if let Some(old_id) = old_id {
+                ann_index.remove(old_id as usize);
+            }
+            ann_index.insert(id as usize, embedding);
+            db.save_ann_index(&mut ann_index)?;
             
             // Update count
             let mut count = self.processed_count.lock().unwrap();
@@ -159,6 +301,24 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .arg(Arg::with_name("stay-in-domain")
                      .long("stay-in-domain")
                      .help("Stay within the initial domain(s)"))
+                .arg(Arg::with_name("skip-duplicates")
+                     .long("skip-duplicates")
+                     .help("Skip pages that are near-duplicates of one already stored (mirrors, print versions)"))
+                .arg(Arg::with_name("density")
+                     .long("density")
+                     .help("Store each page's passages as a density matrix for fidelity scoring"))
+                .arg(Arg::with_name("analyzer")
+                     .long("analyzer")
+                     .value_name("NAME")
+                     .help("Text analysis for a new index (plain, english); existing indexes keep theirs")
+                     .default_value("english")
+                     .takes_value(true))
+                .arg(Arg::with_name("views")
+                     .long("views")
+                     .value_name("VIEWS")
+                     .help("Biorthogonal document views for a new index (title-body, cooccurrence[:WINDOW]); existing indexes keep theirs")
+                     .default_value("title-body")
+                     .takes_value(true))
                 .arg(Arg::with_name("db-path")
                      .long("db-path")
                      .value_name("PATH")
@@ -229,6 +389,36 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                      .default_value("data/search_db.sqlite")
                      .takes_value(true))
         )
+        .subcommand(
+            SubCommand::with_name("reindex")
+                .about("Reassign primes by token frequency and remap the stored index")
+                .arg(Arg::with_name("db-path")
+                     .long("db-path")
+                     .value_name("PATH")
+                     .help("Path to the database file")
+                     .default_value("data/search_db.sqlite")
+                     .takes_value(true))
+        )
+        .subcommand(
+            SubCommand::with_name("duplicates")
+                .about("List groups of stored pages that are near-duplicates of each other")
+                .arg(Arg::with_name("db-path")
+                     .long("db-path")
+                     .value_name("PATH")
+                     .help("Path to the database file")
+                     .default_value("data/search_db.sqlite")
+                     .takes_value(true))
+        )
+        .subcommand(
+            SubCommand::with_name("compact")
+                .about("Drop unused vocabulary entries and renumber the rest onto the smallest primes")
+                .arg(Arg::with_name("db-path")
+                     .long("db-path")
+                     .value_name("PATH")
+                     .help("Path to the database file")
+                     .default_value("data/search_db.sqlite")
+                     .takes_value(true))
+        )
         .get_matches();
 
     // Handle subcommands
@@ -245,6 +435,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         ("import", Some(import_matches)) => {
             run_import(import_matches).await?;
         },
+        ("reindex", Some(reindex_matches)) => {
+            run_reindex(reindex_matches)?;
+        },
+        ("compact", Some(compact_matches)) => {
+            run_compact(compact_matches)?;
+        },
+        ("duplicates", Some(duplicates_matches)) => {
+            run_duplicates(duplicates_matches)?;
+        },
         _ => {
             println!("No subcommand provided. Use --help to see available commands.");
         }
@@ -304,6 +503,56 @@ async fn run_import(matches: &clap::ArgMatches<'_>) -> Result<(), Box<dyn std::e
     Ok(())
 }
 
+// Rebuild the vocabulary in frequency order and remap every stored vector
+fn run_reindex(matches: &clap::ArgMatches<'_>) -> Result<(), Box<dyn std::error::Error>> {
+    let db_path = matches.value_of("db-path").unwrap_or("data/search_db.sqlite");
+    
+    println!("Reindexing {}...", db_path);
+    
+    let db = DocumentDatabase::new(db_path)?;
+    let count = db.reindex()?;
+    
+    let vocab_size = db.get_vocabulary_stamp()?.map(|stamp| stamp.size).unwrap_or(0);
+    println!("Remapped {} documents onto {} frequency-ranked primes", count, vocab_size);
+    
+    Ok(())
+}
+
+// Remove vocabulary entries no stored document uses
+fn run_compact(matches: &clap::ArgMatches<'_>) -> Result<(), Box<dyn std::error::Error>> {
+    let db_path = matches.value_of("db-path").unwrap_or("data/search_db.sqlite");
+    
+    println!("Compacting vocabulary of {}...", db_path);
+    
+    let db = DocumentDatabase::new(db_path)?;
+    let dropped = db.compact_vocabulary()?;
+    
+    let vocab_size = db.get_vocabulary_stamp()?.map(|stamp| stamp.size).unwrap_or(0);
+    println!("Dropped {} unused tokens, {} remain", dropped, vocab_size);
+    
+    Ok(())
+}
+
+// List the clusters of near-duplicate pages
+fn run_duplicates(matches: &clap::ArgMatches<'_>) -> Result<(), Box<dyn std::error::Error>> {
+    let db_path = matches.value_of("db-path").unwrap_or("data/search_db.sqlite");
+    
+    let db = DocumentDatabase::new(db_path)?;
+    let clusters = db.duplicate_clusters()?;
+    
+    for (number, cluster) in clusters.iter().enumerate() {
+        println!("\nCluster {} ({} pages):", number + 1, cluster.len());
+        for &id in cluster {
+            if let Some(doc) = db.get_document_by_id(id)? {
+                println!("  [{}] {}", id, doc.url);
+            }
+        }
+    }
+    println!("\n{} clusters, {} pages in them", clusters.len(), clusters.iter().map(Vec::len).sum::<usize>());
+    
+    Ok(())
+}
+
 // Run the web crawler
 async fn run_crawler(matches: &clap::ArgMatches<'_>) -> Result<(), Box<dyn std::error::Error>> {
     println!("Starting web crawler...");
@@ -313,7 +562,29 @@ async fn run_crawler(matches: &clap::ArgMatches<'_>) -> Result<(), Box<dyn std::
     let max_depth = matches.value_of("depth").unwrap_or("3").parse::<u32>().unwrap_or(3);
     let num_workers = matches.value_of("workers").unwrap_or("10").parse::<usize>().unwrap_or(10);
     let stay_in_domain = matches.is_present("stay-in-domain");
+    let skip_duplicates = matches.is_present("skip-duplicates");
+    let build_density = matches.is_present("density");
     let db_path = matches.value_of("db-path").unwrap_or("data/search_db.sqlite");
+    let analyzer_name = matches.value_of("analyzer").unwrap_or("english");
+    let analyzer = match AnalyzerConfig::preset(analyzer_name) {
+        Some(config) => config,
+        None => {
+            return Err(Box::new(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Unknown analyzer: {}", analyzer_name)
+            )));
+        }
+    };
+    let views_name = matches.value_of("views").unwrap_or("title-body");
+    let views = match BiorthogonalViews::parse(views_name) {
+        Some(views) => views,
+        None => {
+            return Err(Box::new(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Unknown biorthogonal views: {}", views_name)
+            )));
+        }
+    };
     
     // Ensure database directory exists
     if let Some(parent) = Path::new(db_path).parent() {
@@ -376,12 +647,18 @@ async fn run_crawler(matches: &clap::ArgMatches<'_>) -> Result<(), Box<dyn std::
     if stay_in_domain {
         println!("Staying within initial domain(s)");
     }
+    if skip_duplicates {
+        println!("Skipping near-duplicates of stored pages");
+    }
+    if build_density {
+        println!("Storing density matrices for fidelity scoring");
+    }
     
     // Setup document processor
-    let processor = DocumentProcessor::new(db_path)?;
+    let processor = Arc::new(DocumentProcessor::new(db_path, analyzer, views, skip_duplicates, build_density)?);
     
     // Setup channels
-    let (doc_sender, mut doc_receiver) = mpsc::channel::<CrawledDocument>(100);
+    let (doc_sender, doc_receiver) = mpsc::channel::<CrawledDocument>(100);
     
     let mut crawler = AdvancedCrawler::new(
     doc_sender.clone(),
@@ -412,12 +689,31 @@ async fn run_crawler(matches: &clap::ArgMatches<'_>) -> Result<(), Box<dyn std::
         drop(doc_sender);
     });
     
-    // Process documents as they arrive
-    while let Some(doc) = doc_receiver.recv().await {
-        if let Err(e) = processor.process_document(doc).await {
-            eprintln!("Error processing document: {}", e);
-        }
-    }
+    // Process documents as they arrive, several at a time. Analyzed documents are
+    // handed on in arrival order and get their primes one by one, so the same
+    // pages crawled in the same order always end up with the same vocabulary
+    let documents = futures::stream::unfold(doc_receiver, |mut receiver| async move {
+        receiver.recv().await.map(|doc| (doc, receiver))
+    });
+    documents
+        .map(|doc| {
+            let processor = Arc::clone(&processor);
+            processing_step(move || processor.analyze_document(doc))
+        })
+        .buffered(num_workers)
+        .filter_map(futures::future::ready)
+        .then(|analyzed| {
+            let processor = Arc::clone(&processor);
+            processing_step(move || processor.assign_primes(analyzed))
+        })
+        .filter_map(futures::future::ready)
+        .for_each_concurrent(num_workers, |tokenized| {
+            let processor = Arc::clone(&processor);
+            async move {
+                processing_step(move || processor.store_document(tokenized).map(Some)).await;
+            }
+        })
+        .await;
     
     // Wait for crawler to finish
     crawler_handle.await?;
@@ -428,6 +724,25 @@ async fn run_crawler(matches: &clap::ArgMatches<'_>) -> Result<(), Box<dyn std::
     Ok(())
 }
 
+// Run one step of document processing on the blocking pool, reporting why a document was dropped
+async fn processing_step<T, F>(step: F) -> Option<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<Option<T>, Box<dyn std::error::Error + Send + Sync>> + Send + 'static,
+{
+    match tokio::task::spawn_blocking(step).await {
+        Ok(Ok(result)) => result,
+        Ok(Err(e)) => {
+            eprintln!("Error processing document: {}", e);
+            None
+        }
+        Err(e) => {
+            eprintln!("Document processing task failed: {}", e);
+            None
+        }
+    }
+}
+
 // Run interactive search mode
 async fn run_search_mode(matches: &clap::ArgMatches<'_>) -> Result<(), Box<dyn std::error::Error>> {
     println!("Starting interactive search mode...");
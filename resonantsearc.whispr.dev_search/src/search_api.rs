@@ -1,12 +1,22 @@
 // src/search_api.rs
 
 use crate::engine::{ResonantEngine, SearchResult};
-use crate::database::{DocumentDatabase, StoredDocument, parse_stored_document};
-use crate::prime_hilbert::{dot_product, resonance_complex, biorthogonal_score};
-use crate::entropy::{persistence_score, entropy_pressure, buffering_capacity};
-use crate::tokenizer::PrimeTokenizer;
+use crate::database::{DocumentDatabase, StoredDocument, parse_stored_document, parse_ngrams, parse_postings, parse_passages, parse_density};
+use crate::density::DocumentDensity;
+use crate::passages::{Passage, PassageMatch};
+use crate::postings::{contains_phrase, proximity_score};
+use crate::prime_hilbert::{biorthogonal_score, build_query_biorthogonal, phrase_match, BiorthogonalViews, QuantumScoring};
+use crate::sparse_vector::SparseVector;
+use crate::weighting::{CorpusStats, Weighting};
+use crate::entropy::{persistence_score, buffering_capacity, DecayKernel};
+use crate::analyzer::{Analyzer, AnalyzerConfig};
+use crate::language::{language_analyzers, language_tag};
+use crate::tokenizer::{phrase_keys, SharedTokenizer, VocabularyStamp, MAX_NGRAM};
+use crate::ann_index::{AnnConfig, AnnIndex};
+use crate::fingerprint::collapse_near_duplicates;
 
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicI64, Ordering};
 use std::collections::HashMap;
 use std::time::{SystemTime, UNIX_EPOCH};
 use flate2::read::GzDecoder;
@@ -15,7 +25,18 @@ use std::io::Read;
 /// Search API that combines traditional FTS (Full-Text Search) with quantum resonance
 pub struct SearchAPI {
     db: DocumentDatabase,
-    tokenizer: Arc<Mutex<PrimeTokenizer>>,
+    // Searches only read the vocabulary, so concurrent queries never wait on each other
+    tokenizer: Arc<SharedTokenizer>,
+    // Stamp of the stored vocabulary the tokenizer was loaded from, to notice when the crawler adds words
+    vocabulary_stamp: Mutex<Option<VocabularyStamp>>,
+    // Database data version the stamp was last checked at; it only moves when another connection commits
+    data_version: AtomicI64,
+    // Queries are analyzed like the documents in the language searched
+    language_analyzers: RwLock<HashMap<String, Analyzer>>,
+    // Query views must match the ones the documents were built from
+    biorthogonal_views: BiorthogonalViews,
+    // Nearest-neighbour graph stored next to the documents, for `similar_documents`
+    ann_index: Mutex<AnnIndex>,
     use_quantum: bool,
     use_persistence: bool,
     entropy_weight: f64,
@@ -29,6 +50,20 @@ pub struct SearchConfig {
     pub use_quantum: bool,
     pub use_persistence: bool,
     pub hybrid_search: bool,
+    /// How much a quoted phrase match adds to the score
+    pub phrase_weight: f64,
+    /// How much query word proximity adds to the score
+    pub proximity_weight: f64,
+    /// Only search documents in this language (a code such as "en")
+    pub lang: Option<String>,
+    /// How term counts are weighed in the resonance score
+    pub weighting: Weighting,
+    /// How the quantum score is computed
+    pub quantum_scoring: QuantumScoring,
+    /// How entropy pressure grows with document age
+    pub decay_kernel: DecayKernel,
+    /// Leave near-duplicates of a better-ranked result out of the results
+    pub collapse_duplicates: bool,
 }
 
 impl Default for SearchConfig {
@@ -38,6 +73,13 @@ impl Default for SearchConfig {
             use_quantum: true,
             use_persistence: true,
             hybrid_search: true,
+            phrase_weight: 1.0,
+            proximity_weight: 0.5,
+            lang: None,
+            weighting: Weighting::default(),
+            quantum_scoring: QuantumScoring::default(),
+            decay_kernel: DecayKernel::default(),
+            collapse_duplicates: true,
         }
     }
 }
@@ -46,11 +88,23 @@ impl SearchAPI {
     /// Create a new SearchAPI instance
     pub fn new(db_path: &str) -> Result<Self, Box<dyn std::error::Error>> {
         let db = DocumentDatabase::new(db_path)?;
-        let tokenizer = Arc::new(Mutex::new(PrimeTokenizer::new()));
+        // Queries must use the vocabulary the documents were indexed with
+        let data_version = AtomicI64::new(db.data_version()?);
+        let vocabulary_stamp = Mutex::new(db.get_vocabulary_stamp()?);
+        let tokenizer = db.load_tokenizer(AnalyzerConfig::default())?;
+        let language_analyzers = RwLock::new(language_analyzers(tokenizer.analyzer().config()));
+        let tokenizer = Arc::new(SharedTokenizer::new(tokenizer));
+        let biorthogonal_views = db.get_biorthogonal_views()?.unwrap_or_default();
+        let ann_index = Mutex::new(db.load_ann_index(AnnConfig::default())?);
         
         Ok(SearchAPI {
             db,
             tokenizer,
+            vocabulary_stamp,
+            data_version,
+            language_analyzers,
+            biorthogonal_views,
+            ann_index,
             use_quantum: true,
             use_persistence: true,
             entropy_weight: 0.1,
@@ -76,29 +130,96 @@ impl SearchAPI {
         self
     }
     
+    /// Analyzer the documents in `lang` went through, falling back to the one for unknown languages
+    fn query_analyzer<'a>(language_analyzers: &'a HashMap<String, Analyzer>, lang: Option<&String>) -> &'a Analyzer {
+        lang.and_then(|lang| language_analyzers.get(lang))
+            .unwrap_or(&language_analyzers[""])
+    }
+    
+    /// Reload the vocabulary if the crawler stored new words since it was loaded
+    ///
+    /// Until another connection writes to the database only its data version
+    /// is read. After a write the stored stamp is compared, and if the
+    /// vocabulary changed the tokenizer and the analyzers are replaced before
+    /// the query is looked up.
+    fn sync_tokenizer(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let version = self.db.data_version()?;
+        if self.data_version.load(Ordering::Acquire) == version {
+            return Ok(());
+        }
+        
+        let stored = self.db.get_vocabulary_stamp()?;
+        let mut loaded = self.vocabulary_stamp.lock().map_err(|e| e.to_string())?;
+        if *loaded != stored {
+            let tokenizer = self.db.load_tokenizer(AnalyzerConfig::default())?;
+            *self.language_analyzers.write().map_err(|e| e.to_string())? = language_analyzers(tokenizer.analyzer().config());
+            *self.tokenizer.write() = tokenizer;
+            *loaded = stored;
+        }
+        self.data_version.store(version, Ordering::Release);
+        Ok(())
+    }
+    
     /// Search using both text search and quantum resonance
     pub fn search(&self, query: &str, config: SearchConfig) -> Result<Vec<SearchResult>, Box<dyn std::error::Error>> {
         // Track search performance
         let start_time = std::time::Instant::now();
         
-        // Step 1: Tokenize the query
-        let query_tokens = {
-            let mut tokenizer = self.tokenizer.lock().unwrap();
-            tokenizer.tokenize(query)
+        // Documents stored since the last search may have brought new words
+        self.sync_tokenizer()?;
+        
+        // Step 1: Tokenize the query against the stored vocabulary (never adds words),
+        // the same way the documents in the requested language were tokenized, or
+        // without a language the documents of every language stored
+        let lang = config.lang.as_deref().map(language_tag);
+        let langs = match &lang {
+            Some(lang) => vec![lang.clone()],
+            None => self.db.languages().unwrap_or_else(|e| {
+                eprintln!("Failed to load document languages: {}", e);
+                Vec::new()
+            }),
         };
+        let analyzers = self.language_analyzers.read().map_err(|e| e.to_string())?;
+        let mut query_tokens = self.tokenizer.tokenize_known_with(Self::query_analyzer(&analyzers, langs.first()), query);
+        for lang in langs.iter().skip(1) {
+            query_tokens.merge(self.tokenizer.tokenize_known_with(Self::query_analyzer(&analyzers, Some(lang)), query));
+        }
+        drop(analyzers);
+        
+        if !query_tokens.unknown.is_empty() {
+            eprintln!("Ignoring terms not in the index: {}", query_tokens.unknown.join(", "));
+        }
+        
+        // Quoted phrases are matched exactly through the stored token positions;
+        // documents without positions fall back to composite n-gram keys
+        let query_phrases: Vec<(Vec<u64>, Vec<u64>)> = query_tokens.phrases
+            .into_iter()
+            .map(|phrase| {
+                let keys = phrase_keys(&phrase, MAX_NGRAM);
+                (phrase, keys)
+            })
+            .collect();
+        let query_tokens = query_tokens.primes;
         
         if query_tokens.is_empty() {
             return Ok(Vec::new());
         }
         
         // Build query vector from tokens
-        let query_vec = crate::prime_hilbert::build_vector(&query_tokens);
+        let query_vec = SparseVector::from(crate::prime_hilbert::build_vector(&query_tokens));
         let query_entropy = crate::entropy::shannon_entropy(&query_tokens);
         
+        // Document frequencies of the query words, for IDF and BM25 weighting
+        let corpus_stats = self.db.corpus_stats(&query_tokens).unwrap_or_else(|e| {
+            eprintln!("Failed to load term statistics: {}", e);
+            CorpusStats::new()
+        });
+        let weighted_query = config.weighting.query_vector(&query_tokens, &corpus_stats);
+        
         // Step 2: Get initial candidates using text search
         let mut candidates = if config.hybrid_search {
             // Use FTS to get initial candidates
-            match self.db.text_search(query, config.limit * 3) {
+            match self.db.text_search(query, config.limit * 3, lang.as_deref()) {
                 Ok(docs) => docs,
                 Err(e) => {
                     eprintln!("Text search error: {}", e);
@@ -107,7 +228,7 @@ impl SearchAPI {
             }
         } else {
             // Get all documents from DB (limited to a reasonable number)
-            match self.db.get_all_documents(config.limit * 5) {
+            match self.db.get_all_documents(config.limit * 5, lang.as_deref()) {
                 Ok(docs) => docs,
                 Err(e) => {
                     eprintln!("Failed to get documents: {}", e);
@@ -118,6 +239,8 @@ impl SearchAPI {
         
         // Step 3: Score candidates using resonance
         let mut results = Vec::new();
+        // The best passage of each candidate with the compressed text to cut it from
+        let mut passage_sources: HashMap<usize, (Passage, f64, Vec<u8>)> = HashMap::new();
         let now = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap_or_default()
@@ -126,7 +249,7 @@ impl SearchAPI {
         for doc in candidates {
             // Parse vector data
             let (vector, biorthogonal) = match parse_stored_document(&doc) {
-                Ok(data) => data,
+                Ok(parsed) => parsed,
                 Err(e) => {
                     eprintln!("Error parsing document {}: {}", doc.url, e);
                     continue;
@@ -134,23 +257,56 @@ impl SearchAPI {
             };
             
             // Compute basic resonance score
-            let resonance = dot_product(&query_vec, &vector);
+            let postings = parse_postings(&doc).unwrap_or_default();
+            let passages = parse_passages(&doc).unwrap_or_default();
+            let resonance = config.weighting.resonance(&weighted_query, &vector, &postings, &corpus_stats);
             let delta_entropy = (doc.entropy - query_entropy).abs();
-            let standard_score = resonance - delta_entropy * self.entropy_weight;
+            
+            // Phrase matches outrank documents that only share the words
+            let phrase_score = if query_phrases.is_empty() {
+                0.0
+            } else {
+                query_phrases
+                    .iter()
+                    .map(|(phrase, keys)| {
+                        if postings.is_empty() {
+                            phrase_match(keys, &parse_ngrams(&doc).unwrap_or_default())
+                        } else if contains_phrase(phrase, &postings) {
+                            1.0
+                        } else {
+                            0.0
+                        }
+                    })
+                    .sum::<f64>()
+                    / query_phrases.len() as f64
+            };
+            
+            // Query words close together count for more than scattered ones
+            let proximity = proximity_score(&query_tokens, &postings);
+            
+            // A language model already compares the word distributions, not just their spread
+            let entropy_penalty = if config.weighting.is_language_model() { 0.0 } else { delta_entropy * self.entropy_weight };
+            let standard_score = resonance - entropy_penalty
+                + phrase_score * config.phrase_weight
+                + proximity * config.proximity_weight;
             
             // Compute quantum score if enabled
             let quantum_score = if config.use_quantum && self.use_quantum {
                 let doc_age = ((now - doc.timestamp) as f64) / (24.0 * 3600.0); // Age in days
                 let decay_factor = 0.01 * doc_age.min(100.0); // Cap at 100 days
                 
-                let complex_res = resonance_complex(&query_vec, &vector, decay_factor);
-                
                 // For biorthogonal scoring
-                let query_bio = crate::prime_hilbert::build_biorthogonal_vector(&query_tokens);
+                let query_bio = build_query_biorthogonal(&query_tokens, self.biorthogonal_views);
                 let bio_score = biorthogonal_score(&query_bio, &biorthogonal);
                 
+                // Only fidelity scoring reads the document's density
+                let density = match config.quantum_scoring {
+                    QuantumScoring::Fidelity => parse_density(&doc, &postings, &passages).unwrap_or_default(),
+                    _ => DocumentDensity::default(),
+                };
+                
                 // Combine scores
-                complex_res.re * 0.6 + complex_res.im.abs() * 0.2 + bio_score * 0.2
+                config.quantum_scoring.score(&query_vec, &vector, &postings, &density, decay_factor, bio_score)
             } else {
                 0.0
             };
@@ -158,11 +314,13 @@ impl SearchAPI {
             // Compute persistence score if enabled
             let persistence_score_val = if config.use_persistence && self.use_persistence {
                 let doc_age = ((now - doc.timestamp) as f64) / (24.0 * 3600.0); // Age in days
-                let update_frequency = 0.1; // Lower means less frequent updates
+                
+                // How often and how much the page changed across its fetches
+                let updates = self.db.update_stats(&doc.url).unwrap_or_default();
                 
                 let p_score = persistence_score(
-                    doc.reversibility,
-                    entropy_pressure(doc_age, update_frequency, self.trend_decay),
+                    updates.reversibility_or(doc.reversibility),
+                    config.decay_kernel.pressure(doc_age, updates.update_frequency, self.trend_decay),
                     doc.buffering,
                     self.fragility
                 );
@@ -196,15 +354,26 @@ impl SearchAPI {
                 "[No content available]".to_string()
             };
             
+            let id = doc.id.unwrap_or_default() as usize;
+            if let Some((passage, passage_score)) = passages.best_match(&weighted_query, &postings) {
+                passage_sources.insert(id, (*passage, passage_score, doc.compressed_text));
+            }
+            
             results.push(SearchResult {
+                id,
                 title: doc.title,
                 resonance,
                 delta_entropy,
                 score: standard_score,
                 quantum_score: quantum_score,
                 persistence_score: persistence_score_val,
+                phrase_score,
+                proximity,
                 snippet,
                 path: doc.url,
+                lang: doc.lang,
+                passage: None,
+                duplicates: 0,
             });
         }
         
@@ -233,8 +402,29 @@ impl SearchAPI {
             b_combined.partial_cmp(&a_combined).unwrap_or(std::cmp::Ordering::Equal)
         });
         
-        // Limit to requested number
-        let limited_results = results.into_iter().take(config.limit).collect();
+        // Near-duplicates of a better result are folded into it
+        let shown: Vec<(SearchResult, usize)> = if config.collapse_duplicates {
+            collapse_near_duplicates(results, config.limit, |result| {
+                self.db.get_fingerprint(result.id as i64).ok().flatten().unwrap_or_default()
+            })
+        } else {
+            results.into_iter().take(config.limit).map(|result| (result, 0)).collect()
+        };
+        
+        // Limit to requested number, decompressing only the passages shown
+        let limited_results = shown
+            .into_iter()
+            .map(|(mut result, duplicates)| {
+                result.duplicates = duplicates;
+                if let Some((passage, score, compressed_text)) = passage_sources.remove(&result.id) {
+                    let mut text = String::new();
+                    if GzDecoder::new(&compressed_text[..]).read_to_string(&mut text).is_ok() {
+                        result.passage = PassageMatch::new(&passage, &text, score);
+                    }
+                }
+                result
+            })
+            .collect();
         
         // Log search time
         let elapsed = start_time.elapsed();
@@ -243,20 +433,63 @@ impl SearchAPI {
         Ok(limited_results)
     }
     
+    /// Find the stored documents most like the one with `id` by the cosine of their embeddings
+    ///
+    /// `ef` is how many candidates the nearest-neighbour search considers
+    /// (more is slower and misses fewer), `None` for the default. The
+    /// similarity is each result's `score` and `resonance`.
+    pub fn similar_documents(&self, id: i64, limit: usize, ef: Option<usize>) -> Result<Vec<SearchResult>, Box<dyn std::error::Error>> {
+        let mut ann_index = self.ann_index.lock().map_err(|e| e.to_string())?;
+        
+        // The crawler may have stored or replaced documents since the last call
+        self.db.sync_ann_index(&mut ann_index)?;
+        
+        let Some(node) = ann_index.node(id as usize) else {
+            return Ok(Vec::new());
+        };
+        let ef = ef.unwrap_or(ann_index.config().ef_search);
+        let neighbours = ann_index.search_with_ef(&node.embedding, limit + 1, ef);
+        
+        let mut results = Vec::new();
+        for (other, similarity) in neighbours {
+            if other == id as usize || results.len() >= limit {
+                continue;
+            }
+            if let Some(doc) = self.db.get_document_by_id(other as i64)? {
+                results.push(SearchResult {
+                    id: other,
+                    title: doc.title,
+                    resonance: similarity,
+                    delta_entropy: 0.0,
+                    score: similarity,
+                    quantum_score: 0.0,
+                    persistence_score: 0.0,
+                    phrase_score: 0.0,
+                    proximity: 0.0,
+                    snippet: doc.text_snippet,
+                    path: doc.url,
+                    lang: doc.lang,
+                    passage: None,
+                    duplicates: 0,
+                });
+            }
+        }
+        
+        Ok(results)
+    }
+    
     /// Apply quantum jump to update document relevance
     pub fn apply_quantum_jump(&self, query: &str, importance: f64) -> Result<(), Box<dyn std::error::Error>> {
         // Tokenize query
-        let query_tokens = {
-            let mut tokenizer = self.tokenizer.lock().unwrap();
-            tokenizer.tokenize(query)
-        };
+        self.sync_tokenizer()?;
+        let query_tokens = self.tokenizer.read().tokenize_known(query).primes;
         
         if query_tokens.is_empty() {
             return Ok(());
         }
         
         // Build query vector
-        let query_vec = crate::prime_hilbert::build_vector(&query_tokens);
+        let query_vec = SparseVector::from(crate::prime_hilbert::build_vector(&query_tokens));
         
         // Get all document vectors
         let docs = match self.db.get_all_document_vectors() {
@@ -274,14 +507,14 @@ impl SearchAPI {
         
         for (id, vector) in docs {
             // Calculate resonance
-            let resonance = dot_product(&query_vec, &vector);
+            let resonance = query_vec.dot(&vector);
             
             // If the document resonates with the query, boost its relevance
             if resonance > 0.1 {
                 // Get document
                 if let Ok(Some(mut doc)) = self.db.get_document_by_id(id) {
                     // Update reversibility based on match strength
-                    let new_reversibility = doc.reversibility * 0.9 + 0.1 * (resonance * importance);
+                    let new_reversibility = (doc.reversibility * 0.9 + 0.1 * (resonance * importance)).clamp(0.0, 1.0);
                     
                     // Update timestamp to mark it as "fresher"
                     let now = SystemTime::now()
@@ -337,7 +570,7 @@ impl DocumentDatabase {
         let mut stmt = self.conn.prepare(
             "SELECT id, url, title, text_snippet, compressed_text, 
                     vector_data, biorthogonal_data, entropy,
-                    reversibility, buffering, timestamp
+                    reversibility, buffering, timestamp, postings_data, lang, passages_data, density_data, ngram_data, headings
              FROM documents 
              WHERE id = ?"
         )?;
@@ -347,10 +580,16 @@ impl DocumentDatabase {
                 id: Some(row.get(0)?),
                 url: row.get(1)?,
                 title: row.get(2)?,
+                headings: row.get(16)?,
                 text_snippet: row.get(3)?,
                 compressed_text: row.get(4)?,
                 vector_data: row.get(5)?,
+                ngram_data: row.get(15)?,
                 biorthogonal_data: row.get(6)?,
+                postings_data: row.get(11)?,
+                passages_data: row.get(13)?,
+                density_data: row.get(14)?,
+                lang: row.get(12)?,
                 entropy: row.get(7)?,
                 reversibility: row.get(8)?,
                 buffering: row.get(9)?,
@@ -369,26 +608,33 @@ impl DocumentDatabase {
         Ok(())
     }
     
-    /// Get all documents (limited)
-    pub fn get_all_documents(&self, limit: usize) -> rusqlite::Result<Vec<StoredDocument>> {
+    /// Get all documents (limited), optionally only those in one language
+    pub fn get_all_documents(&self, limit: usize, lang: Option<&str>) -> rusqlite::Result<Vec<StoredDocument>> {
         let mut stmt = self.conn.prepare(
             "SELECT id, url, title, text_snippet, compressed_text, 
                     vector_data, biorthogonal_data, entropy,
-                    reversibility, buffering, timestamp
+                    reversibility, buffering, timestamp, postings_data, lang, passages_data, density_data, ngram_data, headings
              FROM documents
+             WHERE ?2 IS NULL OR lang = ?2
              ORDER BY timestamp DESC
-             LIMIT ?"
+             LIMIT ?1"
         )?;
         
-        let rows = stmt.query_map(rusqlite::params![limit as i64], |row| {
+        let rows = stmt.query_map(rusqlite::params![limit as i64, lang], |row| {
             Ok(StoredDocument {
                 id: Some(row.get(0)?),
                 url: row.get(1)?,
                 title: row.get(2)?,
+                headings: row.get(16)?,
                 text_snippet: row.get(3)?,
                 compressed_text: row.get(4)?,
                 vector_data: row.get(5)?,
+                ngram_data: row.get(15)?,
                 biorthogonal_data: row.get(6)?,
+                postings_data: row.get(11)?,
+                passages_data: row.get(13)?,
+                density_data: row.get(14)?,
+                lang: row.get(12)?,
                 entropy: row.get(7)?,
                 reversibility: row.get(8)?,
                 buffering: row.get(9)?,
@@ -1,65 +1,462 @@
 // src/tokenizer.rs
 
-use regex::Regex;
-use std::collections::HashMap;
+use crate::analyzer::{Analyzer, AnalyzerConfig};
+use serde::{Serialize, Deserialize};
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::fs;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 use primal::Primes; // Import the Primes struct
 
+/// Version of the vocabulary format written by `save_vocabulary` and stored in the database.
+pub const VOCABULARY_FORMAT_VERSION: u32 = 2;
+
+/// Identifies the vocabulary an index was built against.
+///
+/// Primes are handed out in increasing order, so a vocabulary that has only
+/// grown since the stamp was taken still agrees on its first `size` entries.
+/// The fingerprint also covers the analyzer chain, since the same words
+/// analyzed differently produce different tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VocabularyStamp {
+    pub format_version: u32,
+    pub size: usize,
+    pub fingerprint: u64,
+}
+
+/// Query words looked up against an existing vocabulary.
+#[derive(Debug, Clone, Default)]
+pub struct QueryTokens {
+    /// Primes of the words the vocabulary already knows, in query order.
+    pub primes: Vec<u64>,
+    /// Words with no prime assigned; they cannot match any indexed document.
+    pub unknown: Vec<String>,
+    /// Word primes of each quoted phrase whose words are all known, in phrase order.
+    pub phrases: Vec<Vec<u64>>,
+}
+
+impl QueryTokens {
+    /// Adds what another analysis of the same query found: the occurrences
+    /// of primes this one lacks and phrases it does not have yet. A token
+    /// stays unknown only if the other analysis left it unknown too.
+    pub fn merge(&mut self, other: QueryTokens) {
+        let known: HashSet<u64> = self.primes.iter().copied().collect();
+        self.primes.extend(other.primes.into_iter().filter(|prime| !known.contains(prime)));
+        for phrase in other.phrases {
+            if !self.phrases.contains(&phrase) {
+                self.phrases.push(phrase);
+            }
+        }
+        self.unknown.retain(|token| other.unknown.contains(token));
+    }
+}
+
+/// Longest word run encoded as a composite key by default.
+pub const MAX_NGRAM: usize = 3;
+
+/// Multiplies each run of `n` consecutive primes into one composite key.
+///
+/// By unique factorization a key never collides with a word prime or with a
+/// key of another length, and a document contains a phrase only if the key
+/// is divisible by each of its word primes. Word order within a run is not
+/// encoded. Runs whose product overflows `u64` are skipped.
+pub fn ngram_keys(primes: &[u64], n: usize) -> Vec<u64> {
+    if n < 2 {
+        return Vec::new();
+    }
+    primes
+        .windows(n)
+        .filter_map(|run| run.iter().try_fold(1u64, |key, &prime| key.checked_mul(prime)))
+        .collect()
+}
+
+/// Returns the composite keys of every run of 2 to `max_n` words in a phrase.
+pub fn phrase_keys(phrase: &[u64], max_n: usize) -> Vec<u64> {
+    (2..=max_n.min(phrase.len()))
+        .flat_map(|n| ngram_keys(phrase, n))
+        .collect()
+}
+
+/// Counts token frequencies over a corpus to build a frequency-ranked vocabulary.
+///
+/// `PrimeTokenizer::tokenize` hands out primes in first-seen order, so the
+/// assignment depends on the order documents arrive in. A vocabulary built
+/// from these counts only depends on the corpus itself.
+#[derive(Debug, Clone, Default)]
+pub struct VocabularyBuilder {
+    counts: HashMap<String, u64>,
+}
+
+impl VocabularyBuilder {
+    /// Creates an empty builder.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts already-analyzed tokens, e.g. from `Analyzer::analyze`.
+    pub fn add_tokens<I: IntoIterator<Item = String>>(&mut self, tokens: I) {
+        for token in tokens {
+            *self.counts.entry(token).or_insert(0) += 1;
+        }
+    }
+
+    /// Returns the number of distinct tokens counted so far.
+    pub fn vocab_size(&self) -> usize {
+        self.counts.len()
+    }
+
+    /// Returns the tokens, most frequent first; ties are broken by the token itself.
+    pub fn ranked_tokens(&self) -> Vec<&str> {
+        let mut ranked: Vec<(&str, u64)> = self.counts
+            .iter()
+            .map(|(token, &count)| (token.as_str(), count))
+            .collect();
+        ranked.sort_by(|(token_a, count_a), (token_b, count_b)| {
+            count_b.cmp(count_a).then_with(|| token_a.cmp(token_b))
+        });
+        ranked.into_iter().map(|(token, _)| token).collect()
+    }
+
+    /// Builds a tokenizer that gives the smallest primes to the most frequent tokens.
+    pub fn build(&self, analyzer: Analyzer) -> PrimeTokenizer {
+        let mut tokenizer = PrimeTokenizer::with_analyzer(analyzer);
+        tokenizer.rebuild_by_frequency(self);
+        tokenizer
+    }
+}
+
 /// A tokenizer that maps words to unique prime numbers.
 pub struct PrimeTokenizer {
     token_to_prime: HashMap<String, u64>,
     prime_to_token: HashMap<u64, String>,
     current_prime: u64,
-    word_regex: Regex,
+    analyzer: Analyzer,
     primal_generator: Primes, // Keep the Primes struct instance
 }
 
 impl PrimeTokenizer {
-    /// Creates a new `PrimeTokenizer`.
+    /// Creates a new `PrimeTokenizer` with the default (lowercase-only) analyzer.
     pub fn new() -> Self {
-        let word_regex = Regex::new(r"\b\w+\b").expect("Failed to create word regex");
+        Self::with_analyzer(Analyzer::default())
+    }
+
+    /// Creates a new `PrimeTokenizer` that runs text through `analyzer` before assigning primes.
+    pub fn with_analyzer(analyzer: Analyzer) -> Self {
         PrimeTokenizer {
             token_to_prime: HashMap::new(),
             prime_to_token: HashMap::new(),
             current_prime: 2, // Start with the first prime
-            word_regex,
+            analyzer,
             primal_generator: Primes::all(), // Create a prime number iterator
         }
     }
 
+    /// Returns the analyzer applied to both documents and queries.
+    pub fn analyzer(&self) -> &Analyzer {
+        &self.analyzer
+    }
+
+    /// Returns the prime for `token`, assigning the next unused prime if it is new.
+    fn assign_prime(&mut self, token: &str) -> u64 {
+        if let Some(&prime) = self.token_to_prime.get(token) {
+            return prime;
+        }
+
+        // Find the next prime greater than the current_prime using the iterator
+        // We skip primes until we find one greater than the current_prime
+        let next_p_usize = self.primal_generator
+            .by_ref() // Use by_ref to borrow the iterator mutably
+            // Cast p (usize) to u64 for comparison with self.current_prime (u64)
+            .find(|&p| p as u64 > self.current_prime)
+            .expect("Should always be able to find a next prime"); // Assuming primes are infinite
+
+        // Cast the usize result to u64 before storing
+        let next_p = next_p_usize as u64;
+
+        self.token_to_prime.insert(token.to_string(), next_p);
+        self.prime_to_token.insert(next_p, token.to_string());
+        self.current_prime = next_p; // Update current_prime to the newly assigned prime
+        next_p
+    }
+
     /// Tokenizes the input text into a vector of prime numbers.
     pub fn tokenize(&mut self, text: &str) -> Vec<u64> {
-        let lower_text = text.to_lowercase();
         let mut primes_list = Vec::new(); // Renamed from 'primes' to avoid shadowing
 
-        for mat in self.word_regex.find_iter(&lower_text) {
-            let token = mat.as_str().to_string();
-            if !self.token_to_prime.contains_key(&token) {
-                // Find the next prime greater than the current_prime using the iterator
-                // We skip primes until we find one greater than the current_prime
-                let next_p_usize = self.primal_generator
-                    .by_ref() // Use by_ref to borrow the iterator mutably
-                    // Cast p (usize) to u64 for comparison with self.current_prime (u64)
-                    .find(|&p| p as u64 > self.current_prime)
-                    .expect("Should always be able to find a next prime"); // Assuming primes are infinite
-
-                // Cast the usize result to u64 before storing
-                let next_p = next_p_usize as u64;
-
-                self.token_to_prime.insert(token.clone(), next_p);
-                self.prime_to_token.insert(next_p, token.clone());
-                self.current_prime = next_p; // Update current_prime to the newly assigned prime
-            }
-            primes_list.push(*self.token_to_prime.get(&token).unwrap());
+        for token in self.analyzer.analyze(text) {
+            primes_list.push(self.assign_prime(&token));
         }
 
         primes_list
     }
     
-    /// Tokenizes the input prime numbers without updating the vocabulary.
-    /// This is useful when we want to generate tokens without affecting the tokenizer's state.
-    pub fn tokenize_without_update(&self, primes: &[u64]) -> Vec<u64> {
-        // Simply return the primes as is, since they're already prime tokens
-        primes.to_vec()
+    /// Tokenizes with a different analyzer than the vocabulary's own, e.g. one
+    /// for source code, while still sharing its primes.
+    pub fn tokenize_with(&mut self, analyzer: &Analyzer, text: &str) -> Vec<u64> {
+        analyzer.analyze(text).iter().map(|token| self.assign_prime(token)).collect()
+    }
+
+    /// Like `tokenize_with`, also returning the byte range in `text` of the
+    /// word each prime came from.
+    pub fn tokenize_with_offsets(&mut self, analyzer: &Analyzer, text: &str) -> (Vec<u64>, Vec<Range<usize>>) {
+        analyzer
+            .analyze_with_offsets(text)
+            .into_iter()
+            .map(|(token, range)| (self.assign_prime(&token), range))
+            .unzip()
+    }
+    
+    /// Looks up the words of `text` without assigning primes to unseen words.
+    ///
+    /// Use this for queries so typos and one-off words never enter the vocabulary.
+    ///
+    /// Text in double quotes is also collected as a phrase, provided all of
+    /// its words are known.
+    pub fn tokenize_known(&self, text: &str) -> QueryTokens {
+        self.tokenize_known_with(&self.analyzer, text)
+    }
+
+    /// Like `tokenize_known`, but analyzes the query with `analyzer`.
+    pub fn tokenize_known_with(&self, analyzer: &Analyzer, text: &str) -> QueryTokens {
+        let mut query_tokens = QueryTokens::default();
+
+        // Odd segments between double quotes are phrases
+        for (index, segment) in text.split('"').enumerate() {
+            let mut phrase = Vec::new();
+            let mut phrase_complete = true;
+
+            for token in analyzer.analyze(segment) {
+                match self.token_to_prime.get(&token) {
+                    Some(&prime) => {
+                        query_tokens.primes.push(prime);
+                        phrase.push(prime);
+                    }
+                    None => {
+                        query_tokens.unknown.push(token);
+                        phrase_complete = false;
+                    }
+                }
+            }
+
+            if index % 2 == 1 && phrase_complete && phrase.len() > 1 {
+                query_tokens.phrases.push(phrase);
+            }
+        }
+
+        query_tokens
+    }
+
+    /// Rebuilds a tokenizer from `(token, prime)` pairs, e.g. a persisted vocabulary.
+    pub fn from_entries<I: IntoIterator<Item = (String, u64)>>(analyzer: Analyzer, entries: I) -> Self {
+        let mut tokenizer = Self::with_analyzer(analyzer);
+        for (token, prime) in entries {
+            tokenizer.current_prime = tokenizer.current_prime.max(prime);
+            tokenizer.prime_to_token.insert(prime, token.clone());
+            tokenizer.token_to_prime.insert(token, prime);
+        }
+        tokenizer
+    }
+
+    /// Returns the number of tokens in the vocabulary.
+    pub fn vocab_size(&self) -> usize {
+        self.token_to_prime.len()
+    }
+
+    /// Returns the highest prime handed out so far.
+    pub fn last_prime(&self) -> u64 {
+        self.current_prime
+    }
+
+    /// Returns the vocabulary as `(token, prime)` pairs in assignment order.
+    pub fn entries(&self) -> Vec<(&str, u64)> {
+        let mut entries: Vec<(&str, u64)> = self.token_to_prime
+            .iter()
+            .map(|(token, &prime)| (token.as_str(), prime))
+            .collect();
+        entries.sort_by_key(|&(_, prime)| prime);
+        entries
+    }
+
+    /// Returns the entries assigned after `prime`, in assignment order.
+    pub fn entries_after(&self, prime: u64) -> Vec<(&str, u64)> {
+        self.entries().into_iter().filter(|&(_, p)| p > prime).collect()
+    }
+
+    /// Replaces the vocabulary with one ranked by `counts`, keeping the analyzer.
+    ///
+    /// Returns a map from each old prime to the new prime of the same token.
+    /// Tokens that were not counted are dropped and have no entry in the map.
+    pub fn rebuild_by_frequency(&mut self, counts: &VocabularyBuilder) -> HashMap<u64, u64> {
+        let old_primes = self.clear();
+        for token in counts.ranked_tokens() {
+            self.assign_prime(token);
+        }
+        self.mapping_from(old_primes)
+    }
+
+    /// Drops every token whose prime is not in `used` and renumbers the rest
+    /// onto the smallest primes, keeping their order.
+    ///
+    /// Returns a map from each kept token's old prime to its new one.
+    pub fn compact(&mut self, used: &HashSet<u64>) -> HashMap<u64, u64> {
+        let kept: Vec<String> = self.entries()
+            .into_iter()
+            .filter(|(_, prime)| used.contains(prime))
+            .map(|(token, _)| token.to_string())
+            .collect();
+
+        let old_primes = self.clear();
+        for token in &kept {
+            self.assign_prime(token);
+        }
+        self.mapping_from(old_primes)
+    }
+
+    /// Empties the vocabulary so primes start again from the smallest,
+    /// returning the old token-to-prime table.
+    fn clear(&mut self) -> HashMap<String, u64> {
+        self.prime_to_token.clear();
+        self.current_prime = 2;
+        self.primal_generator = Primes::all();
+        std::mem::take(&mut self.token_to_prime)
+    }
+
+    /// Removes the tokens assigned after `prime`, so it is handed out next again.
+    fn forget_after(&mut self, prime: u64) {
+        self.token_to_prime.retain(|_, &mut assigned| assigned <= prime);
+        self.prime_to_token.retain(|&assigned, _| assigned <= prime);
+        self.current_prime = prime;
+        self.primal_generator = Primes::all();
+    }
+
+    /// Pairs each old prime with the prime its token has now.
+    fn mapping_from(&self, old_primes: HashMap<String, u64>) -> HashMap<u64, u64> {
+        old_primes
+            .into_iter()
+            .filter_map(|(token, old_prime)| self.token_to_prime.get(&token).map(|&new_prime| (old_prime, new_prime)))
+            .collect()
+    }
+
+    /// Returns a stamp identifying the current vocabulary.
+    pub fn stamp(&self) -> VocabularyStamp {
+        let entries = self.entries();
+        VocabularyStamp {
+            format_version: VOCABULARY_FORMAT_VERSION,
+            size: entries.len(),
+            fingerprint: self.fingerprint(&entries),
+        }
+    }
+
+    /// Checks whether an index stamped with `stamp` can be scored with this vocabulary.
+    pub fn is_compatible_with(&self, stamp: &VocabularyStamp) -> bool {
+        let entries = self.entries();
+        stamp.format_version == VOCABULARY_FORMAT_VERSION
+            && entries.len() >= stamp.size
+            && self.fingerprint(&entries[..stamp.size]) == stamp.fingerprint
+    }
+
+    /// FNV-1a over the analyzer signature and the entries, stable across
+    /// platforms and compiler versions.
+    fn fingerprint(&self, entries: &[(&str, u64)]) -> u64 {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        let signature = self.analyzer.signature();
+        let signature_entry = (signature.as_str(), 0);
+        for (token, prime) in std::iter::once(&signature_entry).chain(entries) {
+            for byte in token.bytes().chain([0xff]).chain(prime.to_le_bytes()) {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(0x0100_0000_01b3);
+            }
+        }
+        hash
+    }
+
+    /// Writes the vocabulary to a file so primes stay stable across runs.
+    pub fn save_vocabulary<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let stamp = self.stamp();
+        let mut writer = BufWriter::new(fs::File::create(path)?);
+        writeln!(writer, "# prime-vocabulary\t{}\t{}\t{}", stamp.format_version, stamp.size, stamp.fingerprint)?;
+        writeln!(writer, "# analyzer\t{}", self.analyzer.signature())?;
+        for (token, prime) in self.entries() {
+            writeln!(writer, "{}\t{}", prime, token)?;
+        }
+        writer.flush()
+    }
+
+    /// Loads a vocabulary written by `save_vocabulary`, rejecting other format versions.
+    ///
+    /// The analyzer is rebuilt from the settings stored in the file; use
+    /// `load_vocabulary_with` if the chain includes custom filters.
+    pub fn load_vocabulary<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::load_vocabulary_impl(path, None)
+    }
+
+    /// Loads a vocabulary written by `save_vocabulary` for use with `analyzer`,
+    /// failing if the file was built with a different chain.
+    pub fn load_vocabulary_with<P: AsRef<Path>>(path: P, analyzer: Analyzer) -> io::Result<Self> {
+        Self::load_vocabulary_impl(path, Some(analyzer))
+    }
+
+    fn load_vocabulary_impl<P: AsRef<Path>>(path: P, analyzer: Option<Analyzer>) -> io::Result<Self> {
+        let reader = BufReader::new(fs::File::open(path)?);
+        let mut lines = reader.lines();
+
+        let header = lines.next().transpose()?.unwrap_or_default();
+        let fields: Vec<&str> = header.split('\t').collect();
+        if fields.len() != 4 || fields[0] != "# prime-vocabulary" {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Missing vocabulary header"));
+        }
+        let stamp = VocabularyStamp {
+            format_version: fields[1].parse().unwrap_or(0),
+            size: fields[2].parse().unwrap_or(0),
+            fingerprint: fields[3].parse().unwrap_or(0),
+        };
+        if stamp.format_version != VOCABULARY_FORMAT_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unsupported vocabulary format version {}", stamp.format_version)
+            ));
+        }
+
+        let analyzer_line = lines.next().transpose()?.unwrap_or_default();
+        let Some(signature) = analyzer_line.strip_prefix("# analyzer\t") else {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Missing analyzer header"));
+        };
+        let analyzer = match analyzer {
+            Some(analyzer) => analyzer,
+            None => {
+                let spec = signature.split('|').next().unwrap_or_default();
+                let config = AnalyzerConfig::parse_spec(spec).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, format!("Invalid analyzer spec: {}", spec))
+                })?;
+                Analyzer::new(config)
+            }
+        };
+        if analyzer.signature() != signature {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Vocabulary was built with analyzer '{}', not '{}'", signature, analyzer.signature())
+            ));
+        }
+
+        let mut entries = Vec::with_capacity(stamp.size);
+        for line in lines {
+            let line = line?;
+            let Some((prime, token)) = line.split_once('\t') else {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Invalid vocabulary line: {}", line)));
+            };
+            let prime: u64 = prime.parse()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid prime: {}", prime)))?;
+            entries.push((token.to_string(), prime));
+        }
+
+        let tokenizer = Self::from_entries(analyzer, entries);
+        if tokenizer.stamp() != stamp {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Vocabulary file does not match its header"));
+        }
+        Ok(tokenizer)
     }
 
     #[allow(dead_code)]
@@ -81,4 +478,171 @@ impl PrimeTokenizer {
     pub fn get_prime(&self, token: &str) -> Option<&u64> {
         self.token_to_prime.get(token)
     }
-}
\ No newline at end of file
+}
+
+/// A `PrimeTokenizer` that many threads can share.
+///
+/// Analysis and lookups of known words only take a read lock. The write lock
+/// is taken just for documents with unseen words, which all get their primes
+/// in one step in order of first appearance. The vocabulary only depends on
+/// the order documents assign their words in: callers that analyze documents
+/// in parallel should assign them one document at a time, in a fixed order.
+pub struct SharedTokenizer {
+    inner: RwLock<PrimeTokenizer>,
+}
+
+impl SharedTokenizer {
+    pub fn new(tokenizer: PrimeTokenizer) -> Self {
+        SharedTokenizer {
+            inner: RwLock::new(tokenizer),
+        }
+    }
+
+    /// Shared access for lookups, stamps and saving.
+    pub fn read(&self) -> RwLockReadGuard<'_, PrimeTokenizer> {
+        self.inner.read().unwrap()
+    }
+
+    /// Exclusive access, e.g. to rebuild or replace the vocabulary.
+    pub fn write(&self) -> RwLockWriteGuard<'_, PrimeTokenizer> {
+        self.inner.write().unwrap()
+    }
+
+    /// See `PrimeTokenizer::tokenize_known_with`.
+    pub fn tokenize_known_with(&self, analyzer: &Analyzer, text: &str) -> QueryTokens {
+        self.read().tokenize_known_with(analyzer, text)
+    }
+
+    /// Tokenizes a document with `analyzer`, assigning primes to unseen words.
+    pub fn tokenize_with(&self, analyzer: &Analyzer, text: &str) -> Vec<u64> {
+        match self.assign_and_commit(&analyzer.analyze(text), |_, _| Ok::<(), Infallible>(())) {
+            Ok(primes) => primes,
+            Err(never) => match never {},
+        }
+    }
+
+    /// Looks up the primes of already analyzed `tokens`, assigning primes to
+    /// unseen ones. When there are new words `commit` runs while the write lock
+    /// is still held, with the tokenizer and the last prime before the new
+    /// ones, so they can be persisted before any other document adds words. If
+    /// `commit` fails the new words are forgotten again.
+    pub fn assign_and_commit<E, F>(&self, tokens: &[String], commit: F) -> Result<Vec<u64>, E>
+    where
+        F: FnOnce(&PrimeTokenizer, u64) -> Result<(), E>,
+    {
+        let known: Option<Vec<u64>> = {
+            let tokenizer = self.read();
+            tokens.iter().map(|token| tokenizer.token_to_prime.get(token).copied()).collect()
+        };
+        if let Some(primes) = known {
+            return Ok(primes);
+        }
+
+        // Another document may have added some of the words since the lookup
+        let mut tokenizer = self.write();
+        let last_prime = tokenizer.last_prime();
+        let primes: Vec<u64> = tokens.iter().map(|token| tokenizer.assign_prime(token)).collect();
+        if tokenizer.last_prime() > last_prime {
+            if let Err(e) = commit(&tokenizer, last_prime) {
+                tokenizer.forget_after(last_prime);
+                return Err(e);
+            }
+        }
+        Ok(primes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    // A file in the temp directory that is removed again when dropped
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            TempFile(std::env::temp_dir().join(format!("resonant-{}-{}.vocab", std::process::id(), name)))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn tokenizer(text: &str) -> PrimeTokenizer {
+        let mut tokenizer = PrimeTokenizer::with_analyzer(Analyzer::new(AnalyzerConfig::english()));
+        tokenizer.tokenize(text);
+        tokenizer
+    }
+
+    #[test]
+    fn saved_vocabulary_loads_with_the_same_primes_and_stamp() {
+        let file = TempFile::new("round-trip");
+        let original = tokenizer("The quick brown fox jumps over the lazy dog, and the dog sleeps");
+        original.save_vocabulary(&file.0).unwrap();
+
+        for loaded in [
+            PrimeTokenizer::load_vocabulary(&file.0).unwrap(),
+            PrimeTokenizer::load_vocabulary_with(&file.0, Analyzer::new(AnalyzerConfig::english())).unwrap(),
+        ] {
+            assert_eq!(loaded.entries(), original.entries());
+            assert_eq!(loaded.stamp(), original.stamp());
+            assert_eq!(loaded.analyzer().config(), original.analyzer().config());
+            assert_eq!(loaded.tokenize_known("lazy foxes sleeping").primes, original.tokenize_known("lazy foxes sleeping").primes);
+        }
+    }
+
+    #[test]
+    fn vocabulary_for_another_chain_is_rejected() {
+        let file = TempFile::new("other-chain");
+        tokenizer("quick brown fox").save_vocabulary(&file.0).unwrap();
+        assert!(PrimeTokenizer::load_vocabulary_with(&file.0, Analyzer::default()).is_err());
+    }
+
+    #[test]
+    fn tampered_or_outdated_vocabulary_is_rejected() {
+        let file = TempFile::new("tampered");
+        tokenizer("quick brown fox").save_vocabulary(&file.0).unwrap();
+        let saved = fs::read_to_string(&file.0).unwrap();
+
+        // An entry the header does not account for
+        fs::write(&file.0, format!("{}97\textra\n", saved)).unwrap();
+        assert!(PrimeTokenizer::load_vocabulary(&file.0).is_err());
+
+        // An older format version
+        let outdated = saved.replacen(
+            &format!("# prime-vocabulary\t{}\t", VOCABULARY_FORMAT_VERSION),
+            &format!("# prime-vocabulary\t{}\t", VOCABULARY_FORMAT_VERSION - 1),
+            1,
+        );
+        fs::write(&file.0, outdated).unwrap();
+        assert!(PrimeTokenizer::load_vocabulary(&file.0).is_err());
+    }
+
+    #[test]
+    fn only_extensions_of_a_vocabulary_are_compatible() {
+        let original = tokenizer("quick brown fox");
+        let stamp = original.stamp();
+        assert!(original.is_compatible_with(&stamp));
+
+        // More words later keep the primes of the first ones
+        let mut extended = tokenizer("quick brown fox");
+        extended.tokenize("lazy dog");
+        assert!(extended.is_compatible_with(&stamp));
+
+        // The same words in another order got other primes
+        let reordered = tokenizer("fox brown quick");
+        assert_eq!(reordered.vocab_size(), original.vocab_size());
+        assert!(!reordered.is_compatible_with(&stamp));
+
+        // A smaller vocabulary lacks some of the words
+        assert!(!tokenizer("quick brown").is_compatible_with(&stamp));
+
+        // A stamp of another format version never matches
+        let outdated = VocabularyStamp { format_version: VOCABULARY_FORMAT_VERSION - 1, ..stamp };
+        assert!(!original.is_compatible_with(&outdated));
+    }
+}
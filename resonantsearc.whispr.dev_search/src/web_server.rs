@@ -9,7 +9,7 @@ use axum::{
     Json, Router,
 };
 use serde::{Deserialize, Serialize};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, RwLock};
 use tokio::sync::mpsc;
 use tower_http::services::ServeDir;
 use tower_http::trace::TraceLayer;
@@ -17,7 +17,7 @@ use tracing::{info, warn};
 
 // Define the shared state for our web server
 pub struct AppState {
-    pub engine: Arc<Mutex<ResonantEngine>>,
+    pub engine: Arc<RwLock<ResonantEngine>>,
 }
 
 // Input query struct
@@ -26,28 +26,101 @@ pub struct SearchQuery {
     pub q: String,
     #[serde(default = "default_limit")]
     pub limit: usize,
+    /// Only return documents in this language (a code such as "en")
+    #[serde(default)]
+    pub lang: Option<String>,
+    /// Comma-separated ids of earlier results the user liked, for relevance feedback
+    #[serde(default)]
+    pub relevant: Option<String>,
+    /// Comma-separated ids of earlier results the user disliked
+    #[serde(default)]
+    pub nonrelevant: Option<String>,
 }
 
 fn default_limit() -> usize {
     10
 }
 
+// Query for documents like a given one
+#[derive(Debug, Deserialize)]
+pub struct SimilarQuery {
+    pub id: usize,
+    #[serde(default = "default_limit")]
+    pub limit: usize,
+    /// Candidates the nearest-neighbour search considers; higher is slower and misses fewer
+    #[serde(default)]
+    pub ef: Option<usize>,
+}
+
+fn parse_ids(ids: Option<&str>) -> Vec<usize> {
+    ids.unwrap_or_default()
+        .split(',')
+        .filter_map(|id| id.trim().parse().ok())
+        .collect()
+}
+
 // Search result response struct
 #[derive(Debug, Serialize)]
 pub struct SearchResponse {
     query: String,
     results: Vec<SearchResultResponse>,
+    /// Words relevance feedback added to the query
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    expansion_terms: Vec<String>,
+    elapsed_ms: u64,
+}
+
+// Similar documents response struct
+#[derive(Debug, Serialize)]
+pub struct SimilarResponse {
+    id: usize,
+    results: Vec<SearchResultResponse>,
     elapsed_ms: u64,
 }
 
 #[derive(Debug, Serialize)]
 pub struct SearchResultResponse {
+    id: usize,
     title: String,
     url: String,
     snippet: String,
     score: f64,
     quantum_score: Option<f64>,
     persistence_score: Option<f64>,
+    phrase_score: Option<f64>,
+    proximity: Option<f64>,
+    lang: Option<String>,
+    /// The passage with the most of the query's words
+    passage: Option<PassageResponse>,
+    /// Near-duplicates of the document left out of the results
+    duplicates: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PassageResponse {
+    /// Byte offsets of the passage in the document text
+    start: usize,
+    end: usize,
+    text: String,
+}
+
+impl From<SearchResult> for SearchResultResponse {
+    fn from(r: SearchResult) -> Self {
+        SearchResultResponse {
+            id: r.id,
+            title: r.title,
+            url: r.path,
+            snippet: r.snippet,
+            score: r.score,
+            quantum_score: if r.quantum_score != 0.0 { Some(r.quantum_score) } else { None },
+            persistence_score: if r.persistence_score != 0.0 { Some(r.persistence_score) } else { None },
+            phrase_score: if r.phrase_score != 0.0 { Some(r.phrase_score) } else { None },
+            proximity: if r.proximity != 0.0 { Some(r.proximity) } else { None },
+            lang: if !r.lang.is_empty() { Some(r.lang) } else { None },
+            passage: r.passage.map(|p| PassageResponse { start: p.start, end: p.end, text: p.text }),
+            duplicates: if r.duplicates > 0 { Some(r.duplicates) } else { None },
+        }
+    }
 }
 
 // Initialize and start the web server
@@ -55,15 +128,16 @@ pub async fn start_server(
     engine: ResonantEngine,
     addr: &str,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    // Wrap the engine in Arc and Mutex for thread safety
+    // Wrap the engine in Arc and RwLock so searches can run side by side
     let shared_state = Arc::new(AppState {
-        engine: Arc::new(Mutex::new(engine)),
+        engine: Arc::new(RwLock::new(engine)),
     });
 
     // Build our router
     let app = Router::new()
         // API routes
         .route("/api/search", get(search_handler))
+        .route("/api/similar", get(similar_handler))
         .route("/api/health", get(health_handler))
         
         // Web interface routes
@@ -99,6 +173,7 @@ async fn search_handler(
             Json(SearchResponse {
                 query: params.q,
                 results: vec![],
+                expansion_terms: vec![],
                 elapsed_ms: 0,
             }),
         );
@@ -106,16 +181,25 @@ async fn search_handler(
     
     let start_time = std::time::Instant::now();
     
-    // Acquire lock and perform search
-    let results = {
-        match state.engine.lock() {
-            Ok(mut engine) => {
+    let relevant = parse_ids(params.relevant.as_deref());
+    let nonrelevant = parse_ids(params.nonrelevant.as_deref());
+    
+    // Searches only read the engine, so they share the lock
+    let (results, expansion_terms) = {
+        match state.engine.read() {
+            Ok(engine) if !relevant.is_empty() || !nonrelevant.is_empty() => {
+                // Refine the query with the results the user marked
+                let feedback = engine.search_with_feedback(&params.q, &relevant, &nonrelevant, params.limit, params.lang.as_deref());
+                let terms = feedback.expansion_terms.into_iter().map(|(word, _)| word).collect();
+                (feedback.results, terms)
+            }
+            Ok(engine) => {
                 // Perform the search
-                engine.search(&params.q, params.limit)
+                (engine.search_in_language(&params.q, params.limit, params.lang.as_deref()), vec![])
             }
             Err(e) => {
                 warn!("Failed to acquire lock on engine: {}", e);
-                vec![]
+                (vec![], vec![])
             }
         }
     };
@@ -123,14 +207,7 @@ async fn search_handler(
     // Convert internal results to response format
     let response_results = results
         .into_iter()
-        .map(|r| SearchResultResponse {
-            title: r.title,
-            url: r.path,
-            snippet: r.snippet,
-            score: r.score,
-            quantum_score: if r.quantum_score != 0.0 { Some(r.quantum_score) } else { None },
-            persistence_score: if r.persistence_score != 0.0 { Some(r.persistence_score) } else { None },
-        })
+        .map(SearchResultResponse::from)
         .collect();
 
     let elapsed = start_time.elapsed().as_millis() as u64;
@@ -142,11 +219,38 @@ async fn search_handler(
         Json(SearchResponse {
             query: params.q,
             results: response_results,
+            expansion_terms,
             elapsed_ms: elapsed,
         }),
     )
 }
 
+// Handler for documents like a given one
+async fn similar_handler(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<SimilarQuery>,
+) -> impl IntoResponse {
+    let start_time = std::time::Instant::now();
+    
+    // The nearest-neighbour index is built on first use, which needs the write lock
+    let results = match state.engine.write() {
+        Ok(mut engine) => engine.similar_documents(params.id, params.limit, params.ef),
+        Err(e) => {
+            warn!("Failed to acquire lock on engine: {}", e);
+            vec![]
+        }
+    };
+    
+    let elapsed = start_time.elapsed().as_millis() as u64;
+    info!("Similar documents for {} found in {}ms", params.id, elapsed);
+    
+    Json(SimilarResponse {
+        id: params.id,
+        results: results.into_iter().map(SearchResultResponse::from).collect(),
+        elapsed_ms: elapsed,
+    })
+}
+
 // Health check endpoint
 async fn health_handler() -> &'static str {
     "OK"
@@ -87,7 +87,7 @@ document.addEventListener('DOMContentLoaded', () => {
             resultCard.innerHTML = `
                 <h3><a href="${result.url}" target="_blank">${escapeHtml(result.title)}</a></h3>
                 <a href="${result.url}" class="url" target="_blank">${escapeHtml(result.url)}</a>
-                <p class="snippet">${escapeHtml(result.snippet)}</p>
+                <p class="snippet">${escapeHtml(result.passage ? result.passage.text : result.snippet)}</p>
                 <div class="scores">
                     <div class="score">
                         <span class="label">Resonance:</span>
@@ -105,6 +105,12 @@ document.addEventListener('DOMContentLoaded', () => {
                             <span class="value">${formatScore(result.persistence_score)}</span>
                         </div>
                     ` : ''}
+                    ${result.duplicates ? `
+                        <div class="score">
+                            <span class="label">Near-duplicates hidden:</span>
+                            <span class="value">${result.duplicates}</span>
+                        </div>
+                    ` : ''}
                 </div>
             `;
             
@@ -1,13 +1,25 @@
 // src/engine.rs - Enhanced with deep filesystem scanning
 
-use crate::tokenizer::PrimeTokenizer;
-use crate::prime_hilbert::{build_vector, dot_product, PrimeVector, build_biorthogonal_vector, BiorthogonalVector, resonance_complex};
-use crate::entropy::{shannon_entropy, entropy_pressure, persistence_score};
+use crate::analyzer::{Analyzer, AnalyzerConfig, CodeLanguage};
+use crate::language::{detect_language, language_analyzers, language_tag};
+use crate::tokenizer::{phrase_keys, PrimeTokenizer, QueryTokens, VocabularyBuilder, VocabularyStamp, MAX_NGRAM};
+use crate::prime_hilbert::{build_vector, build_ngram_vector, project_dense_sparse, DENSE_DIMENSION, phrase_match, build_biorthogonal_vector, build_query_biorthogonal, biorthogonal_score, BiorthogonalVector, BiorthogonalViews, QuantumScoring, PrimeVector, remap_vector_sparse, remap_ngram_vector, remap_biorthogonal, rocchio, token_primes, token_primes_sparse, top_terms};
+use crate::sparse_vector::SparseVector;
+use crate::weighting::{CorpusStats, Weighting};
+use crate::inverted_index::InvertedIndex;
+use crate::ann_index::{AnnConfig, AnnIndex};
+use crate::postings::{build_postings, contains_phrase, proximity_score, remap_postings, Postings};
+use crate::passages::{DocumentPassages, PassageMatch};
+use crate::density::DocumentDensity;
+use crate::entropy::{shannon_entropy, buffering_capacity, persistence_score, DecayKernel};
+use crate::history::{content_change_sparse, content_hash, Fetch, UpdateStats};
+use crate::fingerprint::{collapse_near_duplicates, DuplicateIndex, Fingerprint};
 use crate::crawler::CrawledDocument;
 
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::io::{self, Write, Read};
+use std::io::{self, BufReader, BufWriter, Write, Read};
 use std::time::{SystemTime, UNIX_EPOCH};
 use std::sync::{Arc, Mutex};
 use std::thread;
@@ -17,21 +29,51 @@ use flate2::read::GzDecoder;
 use flate2::Compression;
 use serde::{Serialize, Deserialize};
 
+/// Weight of the original query in `search_with_feedback`.
+pub const ROCCHIO_ALPHA: f64 = 1.0;
+
+/// Weight of the liked documents' centroid in `search_with_feedback`.
+pub const ROCCHIO_BETA: f64 = 0.75;
+
+/// Weight of the disliked documents' centroid in `search_with_feedback`.
+pub const ROCCHIO_GAMMA: f64 = 0.15;
+
+/// Most words `search_with_feedback` adds to a query.
+pub const FEEDBACK_TERMS: usize = 10;
+
+/// Candidates fully scored per result asked for; documents outside the
+/// candidate set are not returned.
+pub const CANDIDATES_PER_RESULT: usize = 10;
+
+/// Fewest candidates fully scored, however few results are asked for.
+pub const MIN_CANDIDATES: usize = 100;
+
 /// Represents a processed document in the engine's index.
 #[derive(Serialize, Deserialize)]
 struct IndexedDocument {
     title: String,
+    // Headings that went into the title view with the title, one per line
+    headings: String,
     text: String,
     compressed_text: Option<Vec<u8>>,
-    vector: PrimeVector,
+    vector: SparseVector,
+    // Composite keys for runs of words, for phrases when there are no postings
+    ngrams: SparseVector,
     biorthogonal: BiorthogonalVector,
+    postings: Postings,
+    passages: DocumentPassages,
+    // The passages as a mixed state, built only for `QuantumScoring::Fidelity`
+    density: DocumentDensity,
+    fingerprint: Fingerprint,
     entropy: f64,
     path: PathBuf,
+    lang: String,
     timestamp: u64,
     // Persistence theory metrics
     reversibility: f64,
     buffering: f64,
-    historical_vectors: Vec<Vec<f64>>,
+    // Every time the document's path was indexed, oldest first
+    history: Vec<Fetch>,
 }
 
 impl IndexedDocument {
@@ -62,11 +104,27 @@ impl IndexedDocument {
             self.text.clone()
         }
     }
+
+    /// The first 200 characters of the text, for search results (Unicode-safe).
+    fn snippet(&self) -> String {
+        snippet(&self.decompress_text())
+    }
+}
+
+fn snippet(full_text: &str) -> String {
+    if full_text.chars().count() > 200 {
+        let truncated: String = full_text.chars().take(200).collect();
+        format!("{}...", truncated)
+    } else {
+        full_text.to_string()
+    }
 }
 
 /// Represents a search result with scoring details and a snippet.
 #[derive(Debug)]
 pub struct SearchResult {
+    /// Position of the document in the engine, for `search_with_feedback`.
+    pub id: usize,
     pub title: String,
     pub snippet: String,
     pub resonance: f64,
@@ -74,66 +132,436 @@ pub struct SearchResult {
     pub score: f64,
     pub quantum_score: f64,
     pub persistence_score: f64,
+    /// Fraction of the quoted query phrases found in the document (0 to 1).
+    pub phrase_score: f64,
+    /// How close together the query words appear in the document (0 to 1).
+    pub proximity: f64,
     pub path: String,
+    /// Detected language of the document, empty if unknown.
+    pub lang: String,
+    /// The passage with the most of the query's words, if the document was
+    /// indexed with passages.
+    pub passage: Option<PassageMatch>,
+    /// Near-duplicates of this document ranked below it and left out of the results.
+    pub duplicates: usize,
+}
+
+/// Results of `ResonantEngine::search_with_feedback`.
+#[derive(Debug)]
+pub struct FeedbackResults {
+    pub results: Vec<SearchResult>,
+    /// Words the feedback added to the query with their weights, strongest first.
+    pub expansion_terms: Vec<(String, f64)>,
 }
 
 pub struct ResonantEngine {
     tokenizer: PrimeTokenizer,
+    // Source files are analyzed per programming language and everything else
+    // per detected language; queries split identifiers too
+    code_analyzers: HashMap<CodeLanguage, Analyzer>,
+    language_analyzers: HashMap<String, Analyzer>,
+    // How many documents each chain analyzed, as the programming language of
+    // source files or else the language code; queries run through all of them
+    chains: BTreeMap<(Option<CodeLanguage>, String), usize>,
     documents: Vec<IndexedDocument>,
+    // The document indexed from each path, which reindexing the path
+    // replaces in place and whose fetch history it continues
+    latest: HashMap<PathBuf, usize>,
+    // Word primes to the documents containing them, for top-k candidates;
+    // documents without postings are listed separately and always scored
+    inverted_index: InvertedIndex,
+    unindexed: Vec<usize>,
+    // Embedding graph for `similar_documents`, once `build_ann_index` has run
+    ann_index: Option<AnnIndex>,
+    // MinHash fingerprints of every document, to find near-duplicates
+    duplicate_index: DuplicateIndex,
+    collapse_duplicates: bool,
+    // Document frequencies and lengths of everything indexed
+    corpus_stats: CorpusStats,
+    weighting: Weighting,
     use_quantum_score: bool,
+    quantum_scoring: QuantumScoring,
     use_persistence_score: bool,
     // Persistence theory parameters
     fragility: f64,
     entropy_weight: f64,
+    // How entropy pressure grows with document age
+    decay_kernel: DecayKernel,
+    // Longest word run indexed as a composite key (1 disables phrases)
+    max_ngram: usize,
+    biorthogonal_views: BiorthogonalViews,
+    phrase_weight: f64,
+    proximity_weight: f64,
+}
+
+impl Default for ResonantEngine {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ResonantEngine {
     pub fn new() -> Self {
+        Self::with_analyzer(Analyzer::default())
+    }
+
+    /// Creates an engine whose documents and queries both go through `analyzer`.
+    ///
+    /// Source files found by `scan_filesystem` use the same settings with
+    /// identifier splitting and the keywords of their language dropped; other
+    /// documents use them adapted to their detected language.
+    pub fn with_analyzer(analyzer: Analyzer) -> Self {
+        let config = analyzer.config().clone();
         ResonantEngine {
-            tokenizer: PrimeTokenizer::new(),
+            tokenizer: PrimeTokenizer::with_analyzer(analyzer),
+            code_analyzers: Self::build_code_analyzers(&config),
+            language_analyzers: language_analyzers(&config),
+            chains: BTreeMap::new(),
             documents: Vec::new(),
+            latest: HashMap::new(),
+            inverted_index: InvertedIndex::new(),
+            unindexed: Vec::new(),
+            ann_index: None,
+            duplicate_index: DuplicateIndex::new(),
+            collapse_duplicates: true,
+            corpus_stats: CorpusStats::new(),
+            weighting: Weighting::default(),
             use_quantum_score: true,    // Enable by default
+            quantum_scoring: QuantumScoring::default(),
             use_persistence_score: true, // Enable by default
             fragility: 0.2,
             entropy_weight: 0.1,
+            decay_kernel: DecayKernel::default(),
+            max_ngram: MAX_NGRAM,
+            biorthogonal_views: BiorthogonalViews::default(),
+            phrase_weight: 1.0,
+            proximity_weight: 0.5,
         }
     }
 
+    fn build_code_analyzers(config: &AnalyzerConfig) -> HashMap<CodeLanguage, Analyzer> {
+        [
+            CodeLanguage::Rust, CodeLanguage::Python, CodeLanguage::JavaScript, CodeLanguage::CFamily,
+            CodeLanguage::Go, CodeLanguage::Php, CodeLanguage::Ruby, CodeLanguage::Shell,
+            CodeLanguage::Sql, CodeLanguage::Css,
+        ]
+        .into_iter()
+        .map(|language| (language, Analyzer::new(config.for_code(language))))
+        .collect()
+    }
+
+    /// Picks the analyzer for a document: by programming language for source
+    /// files, otherwise by detected language.
+    fn document_analyzer<'a>(
+        code_analyzers: &'a HashMap<CodeLanguage, Analyzer>,
+        language_analyzers: &'a HashMap<String, Analyzer>,
+        path: &Path,
+        lang: &str,
+    ) -> &'a Analyzer {
+        Self::code_language(path)
+            .and_then(|language| code_analyzers.get(&language))
+            .or_else(|| language_analyzers.get(lang))
+            .unwrap_or(&language_analyzers[""])
+    }
+
+    /// The chain a document is analyzed with, as counted in `chains`.
+    fn chain(path: &Path, lang: &str) -> (Option<CodeLanguage>, String) {
+        match Self::code_language(path) {
+            Some(language) => (Some(language), String::new()),
+            None => (None, lang.to_string()),
+        }
+    }
+
+    /// Returns the language of a source file, or `None` for anything else.
+    fn code_language(path: &Path) -> Option<CodeLanguage> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        CodeLanguage::from_extension(&extension)
+    }
+
     pub fn len(&self) -> usize {
         self.documents.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.documents.is_empty()
+    }
+
+    /// The settings of the analyzer documents are tokenized with, which
+    /// `load_vocabulary` replaces with the saved ones.
+    pub fn analyzer_config(&self) -> &AnalyzerConfig {
+        self.tokenizer.analyzer().config()
+    }
+
+    /// Saves the tokenizer vocabulary so a later run assigns the same primes.
+    pub fn save_vocabulary<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.tokenizer.save_vocabulary(path)
+    }
+
+    /// Replaces the tokenizer vocabulary with one saved by `save_vocabulary`.
+    ///
+    /// Fails if documents are already indexed and the new vocabulary would
+    /// map their primes to different words.
+    pub fn load_vocabulary<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let tokenizer = PrimeTokenizer::load_vocabulary(path)?;
+        if !self.documents.is_empty() && !tokenizer.is_compatible_with(&self.tokenizer.stamp()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Vocabulary does not match the documents already indexed"
+            ));
+        }
+        let config = tokenizer.analyzer().config().clone();
+        self.tokenizer = tokenizer;
+        self.code_analyzers = Self::build_code_analyzers(&config);
+        self.language_analyzers = language_analyzers(&config);
+        Ok(())
+    }
+
+    /// Saves the indexed documents, stamped with the vocabulary they were built with.
+    pub fn save_index<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let writer = BufWriter::new(fs::File::create(path)?);
+        bincode::serialize_into(writer, &(self.tokenizer.stamp(), &self.documents))
+            .map_err(|e| io::Error::other(e.to_string()))
+    }
+
+    /// Loads documents saved by `save_index`, returning how many were added.
+    ///
+    /// The current vocabulary must be the one the index was built with (or a
+    /// later extension of it), so load the matching vocabulary first.
+    pub fn load_index<P: AsRef<Path>>(&mut self, path: P) -> io::Result<usize> {
+        let reader = BufReader::new(fs::File::open(path)?);
+        let (stamp, documents): (VocabularyStamp, Vec<IndexedDocument>) = bincode::deserialize_from(reader)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+
+        if !self.tokenizer.is_compatible_with(&stamp) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Index was built with a different vocabulary ({} tokens, fingerprint {:016x})",
+                    stamp.size, stamp.fingerprint
+                )
+            ));
+        }
+
+        let count = documents.len();
+        for doc in documents {
+            self.push_document(doc);
+        }
+        Ok(count)
+    }
+
+    /// Reassigns primes so the most frequent tokens across the indexed documents
+    /// get the smallest ones, and remaps every stored vector to match.
+    ///
+    /// The result only depends on the documents, not on the order they were
+    /// added in. Returns the new vocabulary size.
+    pub fn rebuild_vocabulary(&mut self) -> usize {
+        let mut counts = VocabularyBuilder::new();
+        for doc in &self.documents {
+            let analyzer = Self::document_analyzer(&self.code_analyzers, &self.language_analyzers, &doc.path, &doc.lang);
+            // Count every word the document was indexed with: its title view
+            // holds crawled headings that need not appear in the text
+            counts.add_tokens(analyzer.analyze(&format!("{}\n{}", doc.title, doc.headings)));
+            counts.add_tokens(analyzer.analyze(&doc.decompress_text()));
+        }
+
+        let mapping = self.tokenizer.rebuild_by_frequency(&counts);
+        self.remap_documents(&mapping);
+        self.tokenizer.vocab_size()
+    }
+
+    /// Drops the tokens no indexed document uses, such as leftovers from a
+    /// loaded vocabulary, and moves the rest onto the smallest primes in their
+    /// current order, remapping every stored vector to match.
+    ///
+    /// Returns how many tokens were dropped.
+    pub fn compact_vocabulary(&mut self) -> usize {
+        let mut used = HashSet::new();
+        for doc in &self.documents {
+            used.extend(token_primes_sparse(&doc.vector));
+            used.extend(doc.postings.keys().copied());
+            used.extend(token_primes(&doc.biorthogonal.left));
+            used.extend(token_primes(&doc.biorthogonal.right));
+        }
+
+        let before = self.tokenizer.vocab_size();
+        let mapping = self.tokenizer.compact(&used);
+        self.remap_documents(&mapping);
+        before - self.tokenizer.vocab_size()
+    }
+
+    fn remap_documents(&mut self, mapping: &HashMap<u64, u64>) {
+        for doc in &mut self.documents {
+            doc.ngrams = remap_ngram_vector(&doc.ngrams, &doc.vector, mapping);
+            doc.vector = remap_vector_sparse(&doc.vector, mapping);
+            doc.biorthogonal = remap_biorthogonal(&doc.biorthogonal, mapping);
+            doc.postings = remap_postings(&doc.postings, mapping);
+            doc.density = doc.density.remap(mapping);
+            doc.fingerprint = Fingerprint::from_postings(&doc.postings);
+        }
+        self.corpus_stats = CorpusStats::from_postings(self.documents.iter().map(|doc| &doc.postings));
+
+        // Every posting list and fingerprint is keyed by the old primes
+        self.inverted_index = InvertedIndex::new();
+        self.unindexed.clear();
+        self.duplicate_index = DuplicateIndex::new();
+        for (id, doc) in self.documents.iter().enumerate() {
+            Self::index_for_retrieval(&mut self.inverted_index, &mut self.unindexed, id, doc);
+            self.duplicate_index.insert(id, doc.fingerprint.clone());
+        }
+
+        // Embeddings hash the primes, so they change with them
+        if let Some(config) = self.ann_index.as_ref().map(AnnIndex::config) {
+            self.build_ann_index(config);
+        }
+    }
+
+    /// Adds a document, or replaces the one indexed from the same path under
+    /// its id.
+    fn push_document(&mut self, mut doc: IndexedDocument) {
+        // Only fidelity scoring reads the density, so only then is it built
+        if self.quantum_scoring == QuantumScoring::Fidelity && doc.density.is_empty() {
+            doc.density = DocumentDensity::new(&doc.postings, &doc.passages);
+        }
+
+        let id = match self.latest.get(&doc.path) {
+            Some(&id) => {
+                self.retire_document(id);
+                id
+            }
+            None => self.documents.len(),
+        };
+
+        self.corpus_stats.add_document(&doc.postings);
+        Self::index_for_retrieval(&mut self.inverted_index, &mut self.unindexed, id, &doc);
+        if let Some(ann_index) = &mut self.ann_index {
+            ann_index.insert_vector_sparse(id, &doc.vector);
+        }
+        self.duplicate_index.insert(id, doc.fingerprint.clone());
+        *self.chains.entry(Self::chain(&doc.path, &doc.lang)).or_insert(0) += 1;
+        self.latest.insert(doc.path.clone(), id);
+        if id == self.documents.len() {
+            self.documents.push(doc);
+        } else {
+            self.documents[id] = doc;
+        }
+    }
+
+    /// Takes a document out of the statistics and every index, before it is
+    /// replaced by a newer version.
+    fn retire_document(&mut self, id: usize) {
+        let doc = &self.documents[id];
+        self.corpus_stats.remove_document(&doc.postings);
+        self.inverted_index.remove_document(id, &doc.postings);
+        self.unindexed.retain(|&other| other != id);
+        if let Some(ann_index) = &mut self.ann_index {
+            ann_index.remove(id);
+        }
+        self.duplicate_index.remove(id);
+
+        let chain = Self::chain(&doc.path, &doc.lang);
+        if let Some(count) = self.chains.get_mut(&chain) {
+            *count -= 1;
+            if *count == 0 {
+                self.chains.remove(&chain);
+            }
+        }
+    }
+
+    fn index_for_retrieval(index: &mut InvertedIndex, unindexed: &mut Vec<usize>, id: usize, doc: &IndexedDocument) {
+        if doc.postings.is_empty() {
+            unindexed.push(id);
+        } else {
+            index.add_document(id, &doc.postings, &doc.vector);
+        }
+    }
+
+    /// Adds a document, detecting its language unless it is a source file.
     pub fn add_document(&mut self, title: String, text: String, path: PathBuf) {
+        let lang = if Self::code_language(&path).is_some() {
+            String::new()
+        } else {
+            detect_language(&text, None)
+        };
+        self.add_document_with_language(title, text, path, lang);
+    }
+
+    /// Adds a document whose language code is already known ("" if unknown).
+    ///
+    /// The headings of Markdown files go into the title view along with the title.
+    pub fn add_document_with_language(&mut self, title: String, text: String, path: PathBuf, lang: String) {
+        let is_markdown = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("md") || extension.eq_ignore_ascii_case("markdown"));
+        let headings = if is_markdown { markdown_headings(&text) } else { String::new() };
+        self.index_document(title, &headings, text, path, lang);
+    }
+
+    fn index_document(&mut self, title: String, headings: &str, text: String, path: PathBuf, lang: String) {
         if text.trim().is_empty() {
             return; // Skip empty documents
         }
         
-        let tokens = self.tokenizer.tokenize(&text);
-        let vector = build_vector(&tokens);
-        let biorthogonal = build_biorthogonal_vector(&tokens);
+        let analyzer = Self::document_analyzer(&self.code_analyzers, &self.language_analyzers, &path, &lang);
+        let (tokens, offsets) = self.tokenizer.tokenize_with_offsets(analyzer, &text);
+        let heading_tokens = self.tokenizer.tokenize_with(analyzer, &format!("{}\n{}", title, headings));
+        let vector = SparseVector::from(build_vector(&tokens));
+        let ngrams = SparseVector::from(build_ngram_vector(&tokens, self.max_ngram));
+        let biorthogonal = build_biorthogonal_vector(&heading_tokens, &tokens, self.biorthogonal_views);
+        let postings = build_postings(&tokens);
+        let passages = DocumentPassages::new(&tokens, &offsets);
+        let fingerprint = Fingerprint::new(&tokens);
         let entropy = shannon_entropy(&tokens);
+        let buffering = buffering_capacity(&project_dense_sparse(&vector, DENSE_DIMENSION));
 
         let now = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap_or_default()
             .as_secs();
 
+        let history = self.fetch_history(&path, &text, &vector, now);
+        // One minus how much the path's content moved between fetches; a
+        // first fetch is fully reversible with itself
+        let reversibility = UpdateStats::from_history(&history).reversibility_or(1.0);
+
         let mut doc = IndexedDocument {
             title,
+            headings: headings.to_string(),
             text,
             compressed_text: None,
             vector,
+            ngrams,
             biorthogonal,
+            postings,
+            passages,
+            density: DocumentDensity::default(),
+            fingerprint,
             entropy,
             path,
+            lang,
             timestamp: now,
-            reversibility: 0.5,
-            buffering: 0.0,
-            historical_vectors: Vec::new(),
+            reversibility,
+            buffering,
+            history,
         };
         
         doc.compress_text(); // Compress immediately to save memory
-        self.documents.push(doc);
+        self.push_document(doc);
+    }
+
+    /// The fetch history of `path` with one more fetch of `text`, its change
+    /// measured against the latest document indexed from the path.
+    fn fetch_history(&self, path: &Path, text: &str, vector: &SparseVector, timestamp: u64) -> Vec<Fetch> {
+        let mut fetch = Fetch { timestamp, content_hash: content_hash(text), change: 0.0 };
+        let previous = self.latest.get(path).map(|&id| &self.documents[id]);
+        let mut history = previous.map(|doc| doc.history.clone()).unwrap_or_default();
+        if let (Some(previous), Some(last)) = (previous, history.last()) {
+            if last.content_hash != fetch.content_hash {
+                fetch.change = content_change_sparse(&previous.vector, vector);
+            }
+        }
+        history.push(fetch);
+        history
     }
 
     /// Deep filesystem scanning with configurable depth and file limits
@@ -190,7 +618,6 @@ impl ResonantEngine {
             let sender = sender.clone();
             let paths = paths.clone();
             let file_index = file_index.clone();
-            let total_files = total_files;
 
             let handle = thread::spawn(move || {
                 loop {
@@ -215,7 +642,7 @@ impl ResonantEngine {
                     // Process the file
                     match Self::process_file(file_path) {
                         Ok(Some((title, content))) => {
-                            if let Err(_) = sender.send((title, content, file_path.clone())) {
+                            if sender.send((title, content, file_path.clone())).is_err() {
                                 break; // Receiver hung up
                             }
                         }
@@ -250,10 +677,17 @@ impl ResonantEngine {
             handle.join().unwrap();
         }
 
+        // Workers finish in arbitrary order, so rank primes by frequency for a reproducible index
+        if indexed_count > 0 {
+            let vocab_size = self.rebuild_vocabulary();
+            println!("🔢 Assigned primes to {} tokens by frequency", vocab_size);
+        }
+
         println!("✅ Filesystem scan complete!");
         Ok(indexed_count)
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn collect_files_recursive<P: AsRef<Path>>(
         &self,
         dir: P,
@@ -366,24 +800,233 @@ impl ResonantEngine {
         Ok(Some((title, trimmed_content.to_string())))
     }
 
-    pub fn search(&mut self, query: &str, top_n: usize) -> Vec<SearchResult> {
-        if self.documents.is_empty() {
+    pub fn search(&self, query: &str, top_n: usize) -> Vec<SearchResult> {
+        self.search_in_language(query, top_n, None)
+    }
+
+    /// Searches only documents in `lang` (a code such as "en"), analyzing
+    /// the query the way those documents were; `None` searches everything.
+    pub fn search_in_language(&self, query: &str, top_n: usize, lang: Option<&str>) -> Vec<SearchResult> {
+        let lang = lang.map(language_tag);
+        let known = self.known_query(query, lang.as_deref());
+        let weighted_query = self.weighting.query_vector(&known.primes, &self.corpus_stats);
+        self.rank(&known, &weighted_query, top_n, lang.as_deref())
+    }
+
+    /// Searches with the query refined by relevance feedback (Rocchio).
+    ///
+    /// `relevant_ids` and `nonrelevant_ids` are the `id`s of earlier results
+    /// the user liked and disliked. The weighted query moves toward the
+    /// centroid of the liked documents and away from the disliked ones, and
+    /// keeps its own words plus the `FEEDBACK_TERMS` strongest new ones.
+    /// Unknown ids are ignored. Like `search_in_language`, only documents in
+    /// `lang` are returned when it is given.
+    pub fn search_with_feedback(&self, query: &str, relevant_ids: &[usize], nonrelevant_ids: &[usize], top_n: usize, lang: Option<&str>) -> FeedbackResults {
+        let lang = lang.map(language_tag);
+        let known = self.known_query(query, lang.as_deref());
+        let weighted_query = self.weighting.query_vector(&known.primes, &self.corpus_stats);
+
+        let relevant = self.feedback_vectors(relevant_ids);
+        let nonrelevant = self.feedback_vectors(nonrelevant_ids);
+        let refined = rocchio(&weighted_query.to_prime_vector(), &relevant, &nonrelevant, ROCCHIO_ALPHA, ROCCHIO_BETA, ROCCHIO_GAMMA);
+
+        let expansion: Vec<(u64, f64)> = top_terms(&refined, refined.len())
+            .into_iter()
+            .filter(|&(prime, _)| !weighted_query.contains_key(prime))
+            .take(FEEDBACK_TERMS)
+            .collect();
+        let mut expanded: PrimeVector = refined
+            .iter()
+            .filter(|&(&prime, _)| weighted_query.contains_key(prime))
+            .map(|(&prime, &weight)| (prime, weight))
+            .collect();
+        expanded.extend(expansion.iter().copied());
+
+        FeedbackResults {
+            results: self.rank(&known, &SparseVector::from(expanded), top_n, lang.as_deref()),
+            expansion_terms: expansion
+                .into_iter()
+                .filter_map(|(prime, weight)| Some((self.tokenizer.get_token(prime)?.clone(), weight)))
+                .collect(),
+        }
+    }
+
+    /// Builds the approximate nearest-neighbour index over every document's
+    /// embedding, so `similar_documents` can answer. Documents added later
+    /// are inserted as they come.
+    pub fn build_ann_index(&mut self, config: AnnConfig) {
+        let mut ann_index = AnnIndex::new(config);
+        for (id, doc) in self.documents.iter().enumerate() {
+            ann_index.insert_vector_sparse(id, &doc.vector);
+        }
+        ann_index.take_changed();
+        self.ann_index = Some(ann_index);
+    }
+
+    /// Sets how many candidates `similar_documents` considers: more finds
+    /// more of the true nearest neighbours and takes longer.
+    pub fn set_ann_ef_search(&mut self, ef_search: usize) {
+        if let Some(ann_index) = &mut self.ann_index {
+            ann_index.set_ef_search(ef_search);
+        }
+    }
+
+    /// Finds the documents most like the one with `id` by the cosine of their
+    /// embeddings, which is the `score` and `resonance` of each result.
+    ///
+    /// Empty until `build_ann_index` has been called.
+    pub fn similar_documents(&self, id: usize, top_n: usize) -> Vec<SearchResult> {
+        let Some(ann_index) = &self.ann_index else {
             return Vec::new();
+        };
+        ann_index
+            .nearest_to(id, top_n)
+            .into_iter()
+            .filter_map(|(other, similarity)| {
+                let doc = self.documents.get(other)?;
+                Some(SearchResult {
+                    id: other,
+                    title: doc.title.clone(),
+                    snippet: doc.snippet(),
+                    resonance: similarity,
+                    delta_entropy: 0.0,
+                    score: similarity,
+                    quantum_score: 0.0,
+                    persistence_score: 0.0,
+                    phrase_score: 0.0,
+                    proximity: 0.0,
+                    path: doc.path.to_string_lossy().into_owned(),
+                    lang: doc.lang.clone(),
+                    passage: None,
+                    duplicates: 0,
+                })
+            })
+            .collect()
+    }
+
+    // Words outside the vocabulary cannot resonate with any document. A query
+    // in one language is analyzed like its documents; otherwise documents may
+    // come from any chain, so the query goes through every chain in use and
+    // the words any of them knows are kept.
+    fn known_query(&self, query: &str, lang: Option<&str>) -> QueryTokens {
+        let no_path = Path::new("");
+        let mut analyzers: Vec<&Analyzer> = match lang {
+            Some(lang) => vec![Self::document_analyzer(&self.code_analyzers, &self.language_analyzers, no_path, lang)],
+            None => self.chains
+                .keys()
+                .map(|(code, lang)| match code {
+                    Some(language) => self.code_analyzers.get(language).unwrap_or(&self.language_analyzers[""]),
+                    None => Self::document_analyzer(&self.code_analyzers, &self.language_analyzers, no_path, lang),
+                })
+                .collect(),
+        };
+        if analyzers.is_empty() {
+            analyzers.push(&self.language_analyzers[""]);
         }
 
-        let query_tokens = self.tokenizer.tokenize(query);
-        let query_vec = build_vector(&query_tokens);
-        let query_entropy = shannon_entropy(&query_tokens);
+        let mut known = self.tokenizer.tokenize_known_with(analyzers[0], query);
+        for analyzer in &analyzers[1..] {
+            known.merge(self.tokenizer.tokenize_known_with(analyzer, query));
+        }
+        known
+    }
+
+    // A document's word weights in the space queries are weighted in
+    fn feedback_vectors(&self, ids: &[usize]) -> Vec<PrimeVector> {
+        ids.iter()
+            .filter_map(|&id| self.documents.get(id))
+            .map(|doc| {
+                let vector: PrimeVector = if doc.postings.is_empty() {
+                    doc.vector.iter().filter(|&(prime, _)| primal::is_prime(prime)).collect()
+                } else {
+                    self.weighting.document_vector(&doc.postings, &self.corpus_stats).into()
+                };
+                // Weigh the words like the query does, so the expansion
+                // favours the documents' distinctive words over their common ones
+                if self.weighting.weighs_query_by_idf() {
+                    vector.into_iter().map(|(prime, weight)| (prime, weight * self.corpus_stats.idf(prime))).collect()
+                } else {
+                    vector
+                }
+            })
+            .collect()
+    }
+
+    /// The documents with the highest resonance, with that resonance, for
+    /// the full scoring in `rank`.
+    ///
+    /// Indexed documents come from the inverted index, so only documents that
+    /// share a word with the query are looked at; documents indexed without
+    /// postings are always scored.
+    fn candidates(&self, weighted_query: &SparseVector, count: usize, lang: Option<&str>) -> Vec<(usize, f64)> {
+        let resonance = |id: usize| {
+            let doc = &self.documents[id];
+            lang.is_none_or(|lang| doc.lang == lang)
+                .then(|| self.weighting.resonance(weighted_query, &doc.vector, &doc.postings, &self.corpus_stats))
+        };
+
+        let mut candidates = self.inverted_index.top_k(weighted_query, self.weighting, &self.corpus_stats, count, resonance);
+        candidates.extend(self.unindexed.iter().filter_map(|&id| Some((id, resonance(id)?))));
+        candidates
+    }
+
+    fn rank(&self, known: &QueryTokens, weighted_query: &SparseVector, top_n: usize, lang: Option<&str>) -> Vec<SearchResult> {
+        if weighted_query.is_empty() {
+            return Vec::new();
+        }
+        let candidates = self.candidates(weighted_query, (top_n * CANDIDATES_PER_RESULT).max(MIN_CANDIDATES), lang);
+        let query_tokens = &known.primes;
+
+        // Quoted phrases are matched exactly through the positional postings;
+        // documents indexed without postings fall back to composite n-gram keys
+        let query_phrases: Vec<(&Vec<u64>, Vec<u64>)> = known.phrases
+            .iter()
+            .map(|phrase| (phrase, phrase_keys(phrase, self.max_ngram)))
+            .collect();
+
+        let query_vec = SparseVector::from(build_vector(query_tokens));
+        let query_bio = build_query_biorthogonal(query_tokens, self.biorthogonal_views);
+        let query_entropy = shannon_entropy(query_tokens);
 
         let mut results: Vec<SearchResult> = Vec::new();
 
-        for doc in &mut self.documents {
-            // Calculate standard resonance and delta entropy
-            let resonance = dot_product(&query_vec, &doc.vector);
+        for (id, resonance) in candidates {
+            let doc = &self.documents[id];
+
+            // Calculate delta entropy
             let delta_entropy = (query_entropy - doc.entropy).abs();
 
-            // Calculate standard relevance score
-            let mut score = resonance - delta_entropy * self.entropy_weight;
+            // Calculate standard relevance score; a language model already
+            // compares the word distributions, not just their spread
+            let mut score = if self.weighting.is_language_model() {
+                resonance
+            } else {
+                resonance - delta_entropy * self.entropy_weight
+            };
+
+            // Phrase matches outrank documents that only share the words
+            let phrase_score = if query_phrases.is_empty() {
+                0.0
+            } else {
+                query_phrases
+                    .iter()
+                    .map(|(phrase, keys)| {
+                        if doc.postings.is_empty() {
+                            phrase_match(keys, &doc.ngrams)
+                        } else if contains_phrase(phrase, &doc.postings) {
+                            1.0
+                        } else {
+                            0.0
+                        }
+                    })
+                    .sum::<f64>()
+                    / query_phrases.len() as f64
+            };
+            score += phrase_score * self.phrase_weight;
+
+            // Query words close together count for more than scattered ones
+            let proximity = proximity_score(query_tokens, &doc.postings);
+            score += proximity * self.proximity_weight;
 
             // Calculate quantum score if enabled
             let quantum_score = if self.use_quantum_score {
@@ -394,8 +1037,9 @@ impl ResonantEngine {
                 let doc_age = ((now - doc.timestamp) as f64) / (24.0 * 3600.0);
                 let decay_factor = 0.01 * doc_age.min(100.0);
                 
-                let complex_res = resonance_complex(&query_vec, &doc.vector, decay_factor);
-                complex_res.re * 0.6 + complex_res.im.abs() * 0.4
+                // Matching in both views of the document counts for more
+                let bio_score = biorthogonal_score(&query_bio, &doc.biorthogonal);
+                self.quantum_scoring.score(&query_vec, &doc.vector, &doc.postings, &doc.density, decay_factor, bio_score)
             } else {
                 0.0
             };
@@ -407,11 +1051,11 @@ impl ResonantEngine {
                     .unwrap_or_default()
                     .as_secs();
                 let doc_age = ((now - doc.timestamp) as f64) / (24.0 * 3600.0);
-                let update_frequency = 0.1;
+                let updates = UpdateStats::from_history(&doc.history);
                 
                 let persistence = persistence_score(
-                    doc.reversibility,
-                    entropy_pressure(doc_age, update_frequency, 0.05),
+                    updates.reversibility_or(doc.reversibility),
+                    self.decay_kernel.pressure(doc_age, updates.update_frequency, 0.05),
                     doc.buffering,
                     self.fragility
                 );
@@ -430,48 +1074,90 @@ impl ResonantEngine {
                 score += persistence_score * 0.2;
             }
 
-            // Create snippet from decompressed text (Unicode-safe)
-            let full_text = doc.decompress_text();
-            let snippet = if full_text.chars().count() > 200 {
-                let truncated: String = full_text.chars().take(200).collect();
-                format!("{}...", truncated)
-            } else {
-                full_text
-            };
-
             results.push(SearchResult {
+                id,
                 title: doc.title.clone(),
-                snippet,
+                snippet: String::new(),
                 resonance,
                 delta_entropy,
                 score,
                 quantum_score,
                 persistence_score,
+                phrase_score,
+                proximity,
                 path: doc.path.to_string_lossy().into_owned(),
+                lang: doc.lang.clone(),
+                passage: None,
+                duplicates: 0,
             });
         }
 
         // Sort results by combined score (descending)
         results.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
 
-        // Return top N results
-        results.into_iter().take(top_n).collect()
+        // Near-duplicates of a better result are folded into it
+        if self.collapse_duplicates {
+            results = collapse_near_duplicates(results, top_n, |result| self.documents[result.id].fingerprint.clone())
+                .into_iter()
+                .map(|(result, duplicates)| SearchResult { duplicates, ..result })
+                .collect();
+        } else {
+            results.truncate(top_n);
+        }
+
+        // Only the results shown need their text decompressed
+        for result in &mut results {
+            let doc = &self.documents[result.id];
+            let text = doc.decompress_text();
+            result.passage = doc.passages
+                .best_match(weighted_query, &doc.postings)
+                .and_then(|(passage, score)| PassageMatch::new(passage, &text, score));
+            result.snippet = snippet(&text);
+        }
+        results
     }
 
     // Add the missing methods that were in your original code
     pub fn add_crawled_document(&mut self, doc: CrawledDocument) {
         let path = PathBuf::from(&doc.url);
-        self.add_document(doc.title, doc.text, path);
+        self.index_document(doc.title, &doc.headings, doc.text, path, doc.lang);
     }
 
     pub fn set_use_quantum_score(&mut self, enabled: bool) {
         self.use_quantum_score = enabled;
     }
 
+    /// Chooses how the quantum score is computed.
+    ///
+    /// Switching to `Fidelity` builds the density of every document indexed
+    /// without one.
+    pub fn set_quantum_scoring(&mut self, scoring: QuantumScoring) {
+        self.quantum_scoring = scoring;
+        if scoring == QuantumScoring::Fidelity {
+            for doc in &mut self.documents {
+                if doc.density.is_empty() {
+                    doc.density = DocumentDensity::new(&doc.postings, &doc.passages);
+                }
+            }
+        }
+    }
+
+    /// Chooses how entropy pressure grows with document age in the
+    /// persistence score.
+    pub fn set_decay_kernel(&mut self, kernel: DecayKernel) {
+        self.decay_kernel = kernel;
+    }
+
     pub fn set_use_persistence_score(&mut self, enabled: bool) {
         self.use_persistence_score = enabled;
     }
 
+    /// Whether near-duplicates of a better-ranked result are left out of
+    /// search results (on by default).
+    pub fn set_collapse_duplicates(&mut self, enabled: bool) {
+        self.collapse_duplicates = enabled;
+    }
+
     pub fn set_fragility(&mut self, fragility: f64) {
         self.fragility = fragility;
     }
@@ -479,4 +1165,202 @@ impl ResonantEngine {
     pub fn set_entropy_weight(&mut self, weight: f64) {
         self.entropy_weight = weight;
     }
-}
\ No newline at end of file
+
+    /// Sets the longest word run indexed for phrase queries (1 disables them).
+    ///
+    /// Only affects documents added afterwards.
+    pub fn set_max_ngram(&mut self, max_n: usize) {
+        self.max_ngram = max_n.clamp(1, MAX_NGRAM);
+    }
+
+    pub fn set_phrase_weight(&mut self, weight: f64) {
+        self.phrase_weight = weight;
+    }
+
+    pub fn set_proximity_weight(&mut self, weight: f64) {
+        self.proximity_weight = weight;
+    }
+
+    /// Chooses the two document views the biorthogonal vectors are built from.
+    ///
+    /// Only affects documents added afterwards.
+    pub fn set_biorthogonal_views(&mut self, views: BiorthogonalViews) {
+        self.biorthogonal_views = views;
+    }
+
+    /// Chooses how term counts are weighed in the resonance score.
+    ///
+    /// Takes effect on the next search; nothing needs re-indexing.
+    pub fn set_weighting(&mut self, weighting: Weighting) {
+        self.weighting = weighting;
+    }
+
+    pub fn weighting(&self) -> Weighting {
+        self.weighting
+    }
+
+    /// The entropy profile and passages of the document with `id`.
+    pub fn passages(&self, id: usize) -> Option<&DocumentPassages> {
+        self.documents.get(id).map(|doc| &doc.passages)
+    }
+
+    /// Groups of indexed documents that are near-duplicates of each other
+    /// (mirrors, copies, a file indexed again with small edits), by id.
+    pub fn duplicate_clusters(&self) -> Vec<Vec<usize>> {
+        self.duplicate_index.clusters()
+    }
+
+    /// How often and how much the document with `id` changed across the
+    /// times its path was indexed.
+    pub fn update_stats(&self, id: usize) -> Option<UpdateStats> {
+        self.documents.get(id).map(|doc| UpdateStats::from_history(&doc.history))
+    }
+
+    /// Number of indexed documents containing a word, or 0 if it is unknown.
+    pub fn document_frequency(&self, word: &str) -> usize {
+        self.tokenizer
+            .tokenize_known(word)
+            .primes
+            .first()
+            .map_or(0, |&prime| self.corpus_stats.document_frequency(prime))
+    }
+}
+
+/// Collects the ATX headings ("# Title", "## Section") of a Markdown text, one per line.
+fn markdown_headings(text: &str) -> String {
+    text.lines()
+        .map(str::trim_start)
+        .filter(|line| line.starts_with('#'))
+        .map(|line| line.trim_start_matches('#').trim())
+        .filter(|heading| !heading.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    // A file in the temp directory that is removed again when dropped
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            TempFile(std::env::temp_dir().join(format!("resonant-engine-{}-{}.vocab", std::process::id(), name)))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn crawled(url: &str, title: &str, headings: &str, text: &str) -> CrawledDocument {
+        CrawledDocument {
+            url: url.to_string(),
+            title: title.to_string(),
+            headings: headings.to_string(),
+            text: text.to_string(),
+            lang: "en".to_string(),
+        }
+    }
+
+    fn corpus() -> Vec<CrawledDocument> {
+        vec![
+            crawled("https://a.example", "Prime numbers", "Sieve of Eratosthenes", "primes are numbers with two divisors and primes never end"),
+            crawled("https://b.example", "Hilbert spaces", "Inner products\nOrthonormal bases", "a hilbert space is complete and every space like it has an inner product"),
+            crawled("https://c.example", "Entropy", "Thermodynamics", "entropy measures disorder and entropy never decreases in closed systems"),
+        ]
+    }
+
+    // The words of every view of each document, by path
+    fn document_words(engine: &ResonantEngine) -> BTreeMap<PathBuf, BTreeSet<String>> {
+        engine.documents
+            .iter()
+            .map(|doc| {
+                let primes = token_primes_sparse(&doc.vector)
+                    .chain(doc.postings.keys().copied())
+                    .chain(token_primes(&doc.biorthogonal.left))
+                    .chain(token_primes(&doc.biorthogonal.right));
+                let words = primes.map(|prime| engine.tokenizer.get_token(prime).unwrap().clone()).collect();
+                (doc.path.clone(), words)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rebuild_keeps_every_word_of_every_view() {
+        let mut engine = ResonantEngine::new();
+        for doc in corpus() {
+            engine.add_crawled_document(doc);
+        }
+        let before = document_words(&engine);
+        assert!(before[&PathBuf::from("https://a.example")].contains("eratosthenes"));
+
+        engine.rebuild_vocabulary();
+        assert_eq!(document_words(&engine), before);
+        let heading_only = engine.tokenizer.tokenize_known("eratosthenes").primes;
+        assert_eq!(heading_only.len(), 1);
+        assert!(engine.documents[0].biorthogonal.left.contains_key(&heading_only[0]));
+    }
+
+    #[test]
+    fn rebuild_assigns_the_same_primes_whatever_the_crawl_order() {
+        let mut forward = ResonantEngine::new();
+        for doc in corpus() {
+            forward.add_crawled_document(doc);
+        }
+        let mut backward = ResonantEngine::new();
+        for doc in corpus().into_iter().rev() {
+            backward.add_crawled_document(doc);
+        }
+        forward.rebuild_vocabulary();
+        backward.rebuild_vocabulary();
+
+        let (forward_file, backward_file) = (TempFile::new("forward"), TempFile::new("backward"));
+        forward.save_vocabulary(&forward_file.0).unwrap();
+        backward.save_vocabulary(&backward_file.0).unwrap();
+        assert_eq!(fs::read(&forward_file.0).unwrap(), fs::read(&backward_file.0).unwrap());
+
+        for doc in &forward.documents {
+            let other = &backward.documents[backward.latest[&doc.path]];
+            assert_eq!(doc.vector, other.vector, "{}", doc.path.display());
+            assert_eq!(doc.postings, other.postings, "{}", doc.path.display());
+        }
+    }
+
+    #[test]
+    fn language_filter_holds_through_feedback() {
+        let german = |url: &str, title: &str, text: &str| CrawledDocument {
+            lang: "de".to_string(),
+            ..crawled(url, title, "", text)
+        };
+        let mut engine = ResonantEngine::new();
+        for doc in [
+            crawled("https://en1.example", "Radio", "", "the radio station plays music all night on the radio"),
+            crawled("https://en2.example", "Radio news", "", "radio news and weather every hour"),
+            german("https://de1.example", "Radio", "das radio spielt musik die ganze nacht im radio"),
+            german("https://de2.example", "Radio Nachrichten", "radio nachrichten und wetter jede stunde"),
+        ] {
+            engine.add_crawled_document(doc);
+        }
+        let langs = |results: &[SearchResult]| results.iter().map(|result| result.lang.clone()).collect::<BTreeSet<_>>();
+
+        let everything = engine.search("radio", 10);
+        assert_eq!(langs(&everything), BTreeSet::from(["de".to_string(), "en".to_string()]));
+
+        let german_only = engine.search_in_language("radio", 10, Some("de"));
+        assert_eq!(german_only.len(), 2);
+        assert_eq!(langs(&german_only), BTreeSet::from(["de".to_string()]));
+
+        // Feedback from an English document brings in its words but not the document
+        let english = everything.iter().find(|result| result.lang == "en").unwrap().id;
+        let feedback = engine.search_with_feedback("radio", &[english], &[], 10, Some("de"));
+        assert!(!feedback.results.is_empty() && !feedback.expansion_terms.is_empty());
+        assert_eq!(langs(&feedback.results), BTreeSet::from(["de".to_string()]));
+        let feedback = engine.search_with_feedback("radio", &[english], &[], 10, Some("DE-at"));
+        assert_eq!(langs(&feedback.results), BTreeSet::from(["de".to_string()]));
+    }
+}
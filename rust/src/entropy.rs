@@ -1,7 +1,8 @@
 // src/entropy.rs
 
 use std::collections::HashMap;
-use crate::quantum_types::{mutual_information, calculate_redundancy, calculate_symmetry};
+use crate::history::DEFAULT_UPDATE_FREQUENCY;
+use crate::quantum_types::{jensen_shannon_divergence, calculate_redundancy, calculate_symmetry};
 
 /// Calculates the Shannon entropy of a list of u64 values (prime tokens).
 pub fn shannon_entropy(primes: &[u64]) -> f64 {
@@ -27,24 +28,170 @@ pub fn shannon_entropy(primes: &[u64]) -> f64 {
     entropy
 }
 
+/// Tokens in each window of an entropy profile
+pub const PROFILE_WINDOW: usize = 64;
+
+/// Tokens between the starts of neighbouring windows of an entropy profile
+pub const PROFILE_STRIDE: usize = 32;
+
+/// Shannon entropy of each `window`-token window, one starting every `stride` tokens
+///
+/// A text no longer than one window gets a single value for all of it;
+/// otherwise trailing tokens that do not fill a window are left out.
+pub fn entropy_profile(primes: &[u64], window: usize, stride: usize) -> Vec<f64> {
+    if primes.is_empty() {
+        return Vec::new();
+    }
+    let (window, stride) = (window.max(1), stride.max(1));
+    if primes.len() <= window {
+        return vec![shannon_entropy(primes)];
+    }
+    
+    (0..=primes.len() - window)
+        .step_by(stride)
+        .map(|start| shannon_entropy(&primes[start..start + window]))
+        .collect()
+}
+
+/// Token positions where an entropy profile shifts, which is where new passages start
+///
+/// A shift is a jump between neighbouring windows that is at least as large
+/// as the jumps on either side and more than one standard deviation above
+/// the mean jump. It is placed halfway between the two windows' centres.
+pub fn passage_boundaries(profile: &[f64], window: usize, stride: usize) -> Vec<usize> {
+    let jumps: Vec<f64> = profile.windows(2).map(|pair| (pair[1] - pair[0]).abs()).collect();
+    if jumps.is_empty() {
+        return Vec::new();
+    }
+    
+    let mean = jumps.iter().sum::<f64>() / jumps.len() as f64;
+    let variance = jumps.iter().map(|jump| (jump - mean).powi(2)).sum::<f64>() / jumps.len() as f64;
+    let threshold = mean + variance.sqrt();
+    
+    (0..jumps.len())
+        .filter(|&i| {
+            jumps[i] > threshold
+                && (i == 0 || jumps[i] >= jumps[i - 1])
+                && jumps.get(i + 1).is_none_or(|&next| jumps[i] > next)
+        })
+        // Jump i is between the windows starting at i and i + 1 strides
+        .map(|i| i * stride + (window + stride) / 2)
+        .collect()
+}
+
 /// Calculate the reversibility between a document vector and historical vectors
-pub fn calculate_reversibility(doc_vector: &Vec<f64>, historical_vectors: &[Vec<f64>]) -> f64 {
+///
+/// This is one minus the average Jensen-Shannon divergence between the
+/// document's distribution and each historical one, so it stays between 0
+/// (nothing in common with any of them) and 1 (the same distribution).
+pub fn calculate_reversibility(doc_vector: &[f64], historical_vectors: &[Vec<f64>]) -> f64 {
     if historical_vectors.is_empty() {
         return 1.0; // By default, a vector is fully reversible with itself
     }
     
     historical_vectors.iter()
-        .map(|past_vec| mutual_information(doc_vector, past_vec))
+        .map(|past_vec| 1.0 - jensen_shannon_divergence(doc_vector, past_vec))
         .sum::<f64>() / historical_vectors.len() as f64
 }
 
 /// Calculate entropy pressure based on document age and frequency metrics
+///
+/// This is the original, unbounded formula: with the age in days it
+/// overflows for documents a few months old. It is kept as
+/// `DecayKernel::Legacy`; prefer `DecayKernel::pressure`.
 pub fn entropy_pressure(doc_age: f64, update_frequency: f64, trend_decay: f64) -> f64 {
     update_frequency * trend_decay * doc_age.exp()
 }
 
+/// Half-life of `DecayKernel::Exponential` when none is given, in days
+pub const DEFAULT_HALF_LIFE_DAYS: f64 = 30.0;
+
+/// How entropy pressure grows with a document's age (in days)
+///
+/// Every kernel except `Legacy` stays between 0 (new) and 1 (old), so the
+/// persistence score never collapses to 0 just because a document is old.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DecayKernel {
+    /// Age puts no pressure on documents
+    None,
+    /// 1 - 2^(-age / half_life): half the full pressure after `half_life_days`
+    Exponential { half_life_days: f64 },
+    /// 1 - (1 + age / scale)^(-exponent): a long tail, so pressure keeps
+    /// growing slowly for documents far older than the scale
+    PowerLaw { scale_days: f64, exponent: f64 },
+    /// A smooth step to full pressure around `midpoint_days`, `width_days` wide
+    Logistic { midpoint_days: f64, width_days: f64 },
+    /// The unbounded `entropy_pressure` formula
+    Legacy,
+}
+
+impl Default for DecayKernel {
+    fn default() -> Self {
+        DecayKernel::Exponential { half_life_days: DEFAULT_HALF_LIFE_DAYS }
+    }
+}
+
+impl DecayKernel {
+    /// Parse "none", "legacy", "exp[:half_life]", "power[:scale,exponent]" or
+    /// "logistic[:midpoint,width]", with times in days
+    pub fn parse(spec: &str) -> Option<Self> {
+        let spec = spec.trim().to_lowercase();
+        let (name, args) = match spec.split_once(':') {
+            Some((name, args)) => (name, Some(args)),
+            None => (spec.as_str(), None),
+        };
+        let numbers: Vec<f64> = match args {
+            Some(args) => args.split(',').map(|arg| arg.trim().parse().ok()).collect::<Option<_>>()?,
+            None => Vec::new(),
+        };
+        if numbers.iter().any(|&number| !number.is_finite() || number <= 0.0) {
+            return None;
+        }
+        
+        match (name, numbers.as_slice()) {
+            ("none", []) => Some(DecayKernel::None),
+            ("legacy", []) => Some(DecayKernel::Legacy),
+            ("exp" | "exponential", []) => Some(DecayKernel::default()),
+            ("exp" | "exponential", &[half_life_days]) => Some(DecayKernel::Exponential { half_life_days }),
+            ("power" | "powerlaw", []) => Some(DecayKernel::PowerLaw { scale_days: DEFAULT_HALF_LIFE_DAYS, exponent: 1.0 }),
+            ("power" | "powerlaw", &[scale_days, exponent]) => Some(DecayKernel::PowerLaw { scale_days, exponent }),
+            ("logistic", []) => Some(DecayKernel::Logistic { midpoint_days: 3.0 * DEFAULT_HALF_LIFE_DAYS, width_days: DEFAULT_HALF_LIFE_DAYS / 2.0 }),
+            ("logistic", &[midpoint_days, width_days]) => Some(DecayKernel::Logistic { midpoint_days, width_days }),
+            _ => None,
+        }
+    }
+    
+    /// The spec `parse` accepts for this kernel
+    pub fn name(&self) -> String {
+        match self {
+            DecayKernel::None => "none".to_string(),
+            DecayKernel::Exponential { half_life_days } => format!("exp:{}", half_life_days),
+            DecayKernel::PowerLaw { scale_days, exponent } => format!("power:{},{}", scale_days, exponent),
+            DecayKernel::Logistic { midpoint_days, width_days } => format!("logistic:{},{}", midpoint_days, width_days),
+            DecayKernel::Legacy => "legacy".to_string(),
+        }
+    }
+    
+    /// Entropy pressure on a document `doc_age` days old
+    ///
+    /// `update_frequency` is in content changes per day. The bounded kernels
+    /// age a document faster the more often it changes, at its own pace at
+    /// `DEFAULT_UPDATE_FREQUENCY`, and not at all if it never does;
+    /// `trend_decay` only feeds the `Legacy` kernel.
+    pub fn pressure(&self, doc_age: f64, update_frequency: f64, trend_decay: f64) -> f64 {
+        let age = doc_age.max(0.0) * update_frequency.max(0.0) / DEFAULT_UPDATE_FREQUENCY;
+        match *self {
+            DecayKernel::None => 0.0,
+            DecayKernel::Exponential { half_life_days } => 1.0 - 0.5f64.powf(age / half_life_days),
+            DecayKernel::PowerLaw { scale_days, exponent } => 1.0 - (1.0 + age / scale_days).powf(-exponent),
+            DecayKernel::Logistic { midpoint_days, width_days } => 1.0 / (1.0 + (-(age - midpoint_days) / width_days).exp()),
+            DecayKernel::Legacy => entropy_pressure(doc_age, update_frequency, trend_decay),
+        }
+    }
+}
+
 /// Calculate the buffering capacity of a document vector
-pub fn buffering_capacity(doc_vector: &Vec<f64>) -> f64 {
+pub fn buffering_capacity(doc_vector: &[f64]) -> f64 {
     let redundancy = calculate_redundancy(doc_vector);
     let symmetry = calculate_symmetry(doc_vector);
     redundancy + symmetry
@@ -61,4 +208,87 @@ pub fn persistence_score(
         return 0.0; // Avoid division by zero
     }
     ((-fragility) * (1.0 - reversibility) * (entropy_pressure / buffering)).exp()
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reversibility_is_bounded() {
+        let document = vec![3.0, 1.0, 0.0, 2.0];
+        let histories: [Vec<Vec<f64>>; 4] = [
+            Vec::new(),
+            vec![document.clone()],
+            vec![vec![0.0, 0.0, 5.0, 0.0]],
+            vec![vec![1.0, 1.0, 1.0, 1.0], vec![0.0, 0.0, 5.0, 0.0], document.clone()],
+        ];
+        for history in &histories {
+            let reversibility = calculate_reversibility(&document, history);
+            assert!((0.0..=1.0).contains(&reversibility), "{:?}: {}", history, reversibility);
+        }
+        // The same distribution and one with no word in common
+        assert!((calculate_reversibility(&document, &histories[1]) - 1.0).abs() < 1e-12);
+        assert!(calculate_reversibility(&document, &histories[2]).abs() < 1e-12);
+    }
+
+    #[test]
+    fn bounded_kernels_grow_with_age_between_0_and_1() {
+        let kernels = [
+            DecayKernel::default(),
+            DecayKernel::PowerLaw { scale_days: 10.0, exponent: 2.0 },
+            DecayKernel::parse("power").unwrap(),
+            DecayKernel::parse("logistic").unwrap(),
+        ];
+        for kernel in kernels {
+            let mut previous = kernel.pressure(0.0, DEFAULT_UPDATE_FREQUENCY, 1.0);
+            assert!(previous < 0.01, "{} on a new document: {}", kernel.name(), previous);
+            for age in (1..=2000).map(|day| day as f64) {
+                let pressure = kernel.pressure(age, DEFAULT_UPDATE_FREQUENCY, 1.0);
+                assert!((0.0..=1.0).contains(&pressure), "{} at {} days: {}", kernel.name(), age, pressure);
+                assert!(pressure >= previous, "{} falls at {} days", kernel.name(), age);
+                previous = pressure;
+            }
+        }
+        assert_eq!(DecayKernel::None.pressure(1000.0, DEFAULT_UPDATE_FREQUENCY, 1.0), 0.0);
+    }
+
+    #[test]
+    fn exponential_kernel_is_half_way_at_its_half_life() {
+        let pressure = DecayKernel::default().pressure(DEFAULT_HALF_LIFE_DAYS, DEFAULT_UPDATE_FREQUENCY, 1.0);
+        assert!((pressure - 0.5).abs() < 1e-12, "{}", pressure);
+
+        // A page that changes twice as often ages twice as fast; one that
+        // never changes does not age
+        let pressure = DecayKernel::default().pressure(DEFAULT_HALF_LIFE_DAYS / 2.0, 2.0 * DEFAULT_UPDATE_FREQUENCY, 1.0);
+        assert!((pressure - 0.5).abs() < 1e-12, "{}", pressure);
+        assert_eq!(DecayKernel::default().pressure(DEFAULT_HALF_LIFE_DAYS, 0.0, 1.0), 0.0);
+    }
+
+    #[test]
+    fn legacy_kernel_is_the_old_formula() {
+        for (age, update_frequency, trend_decay) in [(0.0, 0.1, 1.0), (1.5, 0.1, 0.8), (10.0, 0.3, 0.5), (40.0, 1.0, 2.0)] {
+            assert_eq!(
+                DecayKernel::Legacy.pressure(age, update_frequency, trend_decay),
+                entropy_pressure(age, update_frequency, trend_decay)
+            );
+        }
+        assert_eq!(DecayKernel::parse("legacy"), Some(DecayKernel::Legacy));
+    }
+
+    #[test]
+    fn kernel_names_parse_back() {
+        let kernels = [
+            DecayKernel::None,
+            DecayKernel::Legacy,
+            DecayKernel::Exponential { half_life_days: 7.5 },
+            DecayKernel::PowerLaw { scale_days: 30.0, exponent: 1.5 },
+            DecayKernel::Logistic { midpoint_days: 90.0, width_days: 15.0 },
+        ];
+        for kernel in kernels {
+            assert_eq!(DecayKernel::parse(&kernel.name()), Some(kernel));
+        }
+        assert_eq!(DecayKernel::parse("exp:-1"), None);
+        assert_eq!(DecayKernel::parse("gaussian"), None);
+    }
+}
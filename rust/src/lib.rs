@@ -1,8 +1,19 @@
 // src/lib.rs
 
+pub mod analyzer;
+pub mod language;
 pub mod tokenizer;
 pub mod entropy;
 pub mod prime_hilbert;
+pub mod sparse_vector;
+pub mod postings;
+pub mod passages;
+pub mod history;
+pub mod fingerprint;
+pub mod density;
+pub mod weighting;
+pub mod inverted_index;
+pub mod ann_index;
 pub mod engine;
 pub mod crawler;
 pub mod quantum_types;
@@ -10,8 +21,18 @@ pub mod quantum_types;
 // Re-export key types and functions
 pub use engine::ResonantEngine;
 pub use engine::SearchResult;
+pub use engine::FeedbackResults;
+pub use analyzer::{Analyzer, AnalyzerConfig};
 pub use crawler::CrawledDocument;
-pub use prime_hilbert::{PrimeVector, BiorthogonalVector};
+pub use prime_hilbert::{PrimeVector, BiorthogonalVector, BiorthogonalViews, QuantumScoring};
+pub use sparse_vector::SparseVector;
+pub use weighting::{CorpusStats, Weighting};
+pub use inverted_index::InvertedIndex;
+pub use ann_index::{AnnConfig, AnnIndex};
+pub use passages::{DocumentPassages, Passage, PassageMatch};
+pub use history::{Fetch, UpdateStats};
+pub use fingerprint::{DuplicateIndex, Fingerprint};
+pub use density::DocumentDensity;
 pub use quantum_types::{MatrixComplex, VectorComplex};
 
 // Export key persistence theory functions
@@ -20,12 +41,15 @@ pub use entropy::{
     calculate_reversibility,
     entropy_pressure,
     buffering_capacity,
-    persistence_score
+    persistence_score,
+    DecayKernel
 };
 
 // Export quantum functions from their proper module
 pub use quantum_types::{
     mutual_information,
+    normalized_mutual_information,
+    jensen_shannon_divergence,
     calculate_redundancy,
     calculate_symmetry
 };
\ No newline at end of file
@@ -1,25 +1,24 @@
 // src/main.rs - Clean HDD Search Engine
 
-mod tokenizer;
-mod entropy;
-mod prime_hilbert;
-mod engine;
-mod crawler;
-mod quantum_types;
-
-use engine::ResonantEngine;
+use resonant_search::{Analyzer, AnalyzerConfig, AnnConfig, BiorthogonalViews, DecayKernel, QuantumScoring, ResonantEngine, SearchResult, Weighting};
+use resonant_search::language::{known_language_codes, language_tag};
+use std::fs;
 use std::io::{self, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::time::Instant;
 
+/// Vocabulary file in the index directory.
+const VOCABULARY_FILE: &str = "vocabulary.tsv";
+
+/// Document file in the index directory.
+const INDEX_FILE: &str = "index.bin";
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("=====================================================");
     println!("🔍 RESONANT HDD SEARCH ENGINE 🔍");
     println!("Find files by content, not just name!");
     println!("=====================================================");
 
-    let mut engine = ResonantEngine::new();
-
     // Get search path from user
     println!("\nEnter search path (e.g., D:/ or D:/code or C:/Users):");
     print!("> ");
@@ -65,13 +64,95 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let mut workers_input = String::new();
     io::stdin().read_line(&mut workers_input)?;
-    let num_workers: usize = workers_input.trim().parse().unwrap_or(8).min(32).max(1);
+    let num_workers: usize = workers_input.trim().parse().unwrap_or(8).clamp(1, 32);
+
+    // Get text analysis mode
+    println!("\nText analysis: 'english' (stemming, stop words, accent folding) or 'plain' (default: english):");
+    print!("> ");
+    io::stdout().flush()?;
+
+    let mut analyzer_input = String::new();
+    io::stdin().read_line(&mut analyzer_input)?;
+    let analyzer_name = match analyzer_input.trim() {
+        "" => "english",
+        name => name,
+    };
+    let analyzer_config = AnalyzerConfig::preset(analyzer_name).unwrap_or_else(AnalyzerConfig::english);
+
+    // Get term weighting
+    println!("\nTerm weighting: 'tfidf', 'bm25', 'logtf', 'raw', or query likelihood with 'dirichlet[:MU]' or 'jm[:LAMBDA]' smoothing (default: raw):");
+    print!("> ");
+    io::stdout().flush()?;
+
+    let mut weighting_input = String::new();
+    io::stdin().read_line(&mut weighting_input)?;
+    let weighting = Weighting::parse(&weighting_input).unwrap_or_default();
+
+    // Get biorthogonal document views
+    println!("\nDocument views: 'title-body' or 'cooccurrence[:WINDOW]' (default: title-body):");
+    print!("> ");
+    io::stdout().flush()?;
+
+    let mut views_input = String::new();
+    io::stdin().read_line(&mut views_input)?;
+    let views = BiorthogonalViews::parse(&views_input).unwrap_or_default();
+
+    // Get quantum scoring
+    println!("\nQuantum scoring: 'resonance', 'interference' or 'fidelity' (default: resonance):");
+    print!("> ");
+    io::stdout().flush()?;
+
+    let mut scoring_input = String::new();
+    io::stdin().read_line(&mut scoring_input)?;
+    let quantum_scoring = QuantumScoring::parse(&scoring_input).unwrap_or_default();
+
+    // Get temporal decay
+    println!("\nAge decay: 'exp[:HALF_LIFE]', 'power[:SCALE,EXPONENT]', 'logistic[:MIDPOINT,WIDTH]', 'none' or 'legacy', in days (default: exp:30):");
+    print!("> ");
+    io::stdout().flush()?;
+
+    let mut decay_input = String::new();
+    io::stdin().read_line(&mut decay_input)?;
+    let decay_kernel = DecayKernel::parse(&decay_input).unwrap_or_default();
+
+    // Get index directory
+    println!("\nIndex directory to load from and save to, or 'none' (default: resonant_index):");
+    print!("> ");
+    io::stdout().flush()?;
+
+    let mut index_input = String::new();
+    io::stdin().read_line(&mut index_input)?;
+    let index_dir = match index_input.trim() {
+        "" => Some(PathBuf::from("resonant_index")),
+        name if name.eq_ignore_ascii_case("none") => None,
+        name => Some(PathBuf::from(name)),
+    };
+
+    let mut engine = ResonantEngine::with_analyzer(Analyzer::new(analyzer_config));
+    engine.set_weighting(weighting);
+    engine.set_biorthogonal_views(views);
+    engine.set_quantum_scoring(quantum_scoring);
+    engine.set_decay_kernel(decay_kernel);
+
+    // Files from an earlier scan keep their primes and fetch history
+    if let Some(dir) = &index_dir {
+        match load_index(&mut engine, dir) {
+            Ok(Some(count)) => println!("\n📂 Loaded {} files from {}", count, dir.display()),
+            Ok(None) => {}
+            Err(e) => eprintln!("\n⚠️  Could not load the index in {}: {}", dir.display(), e),
+        }
+    }
 
     println!("\n🚀 Starting HDD scan...");
     println!("📁 Path: {}", search_path);
     println!("📊 Max depth: {}", max_depth);
     println!("📄 Max files: {}", max_files);
     println!("⚡ Workers: {}", num_workers);
+    println!("⚖️  Weighting: {}", weighting.name());
+    println!("🪞 Views: {}", views.spec());
+    println!("🔮 Quantum scoring: {}", quantum_scoring.name());
+    println!("⏳ Age decay: {}", decay_kernel.name());
+    println!("🔤 Analyzer: {}", engine.analyzer_config().spec());
     println!();
 
     let start_time = Instant::now();
@@ -91,15 +172,25 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
-    if engine.len() == 0 {
+    if engine.is_empty() {
         println!("❌ No files were indexed. Check your path and permissions.");
         return Ok(());
     }
 
+    if let Some(dir) = &index_dir {
+        match save_index(&engine, dir) {
+            Ok(()) => println!("💾 Index saved to {}", dir.display()),
+            Err(e) => eprintln!("⚠️  Could not save the index to {}: {}", dir.display(), e),
+        }
+    }
+
     println!("\n🎯 SEARCH MODE ACTIVATED");
     println!("Now you can search by content, concepts, or keywords!");
+    println!("Put words in \"quotes\" to rank exact phrases first.");
+    println!("Start with lang:<code> (e.g. lang:de) to search one language only.");
 
     // Interactive search loop
+    let mut ann_built = false;
     loop {
         println!("\n🔮 Enter your search query (or 'quit' to exit):");
         print!("> ");
@@ -118,35 +209,152 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             continue;
         }
 
+        // An optional leading "lang:xx" restricts the search to one language
+        let (lang, query) = match query.strip_prefix("lang:") {
+            Some(rest) => {
+                let (code, rest) = rest.split_once(' ').unwrap_or((rest, ""));
+                (Some(language_tag(code)), rest.trim())
+            }
+            None => (None, query),
+        };
+        if let Some(code) = &lang {
+            if !known_language_codes().any(|known| known == code) {
+                let known: Vec<&str> = known_language_codes().collect();
+                println!("❌ Unknown language '{}'. Try one of: {}", code, known.join(", "));
+                continue;
+            }
+            if query.is_empty() {
+                println!("💡 Put your query after the language, e.g. lang:{} resonance", code);
+                continue;
+            }
+        }
+        let lang = lang.as_deref();
+
         println!("\n🔍 Searching {} indexed files...", engine.len());
         let search_start = Instant::now();
-        let results = engine.search(query, 10); // Show top 10 results
+        let results = engine.search_in_language(query, 10, lang); // Show top 10 results
         let search_time = search_start.elapsed();
 
         println!("\n🎯 TOP RESONANT MATCHES (search took {:.3}s):", search_time.as_secs_f64());
         
-        if results.is_empty() {
-            println!("❌ No resonant patterns found.");
-            println!("💡 Try different keywords or concepts.");
-        } else {
-            for (idx, result) in results.iter().enumerate() {
-                println!("\n[{}] 📄 {}", idx + 1, result.title);
-                println!("    📍 Path: {}", result.path);
-                println!("    🎵 Resonance:      {:.4}", result.resonance);
-                println!("    🌀 Δ Entropy:      {:.4}", result.delta_entropy);
-                println!("    ⭐ Score:          {:.4}", result.score);
-                println!("    🔮 Quantum:        {:.4}", result.quantum_score);
-                println!("    🌊 Persistence:    {:.4}", result.persistence_score);
-                println!("    👁️  Preview:        {}", result.snippet);
-            }
-        }
+        print_results(&results);
 
         // Show some stats
         println!("\n📊 Search Stats:");
         println!("    Files searched: {}", engine.len());
         println!("    Results found: {}", results.len());
         println!("    Search time: {:.3}s", search_time.as_secs_f64());
+
+        if results.is_empty() {
+            continue;
+        }
+
+        // Relevance feedback refines the same query
+        println!("\n👍 Refine with +N / -N for results you liked / disliked (e.g. +1 +3 -2),");
+        println!("   ~N for files like result N, or press Enter:");
+        print!("> ");
+        io::stdout().flush()?;
+
+        let mut feedback = String::new();
+        io::stdin().read_line(&mut feedback)?;
+        let (mut relevant, mut nonrelevant, mut similar) = (Vec::new(), Vec::new(), Vec::new());
+        for mark in feedback.split_whitespace() {
+            let (ids, number) = match mark.split_at_checked(1) {
+                Some(("+", number)) => (&mut relevant, number),
+                Some(("-", number)) => (&mut nonrelevant, number),
+                Some(("~", number)) => (&mut similar, number),
+                _ => continue,
+            };
+            if let Some(result) = number.parse::<usize>().ok().and_then(|n| results.get(n.wrapping_sub(1))) {
+                ids.push(result.id);
+            }
+        }
+
+        for &id in &similar {
+            // The embedding graph is only built the first time it is needed
+            if !ann_built {
+                println!("\n🕸️ Building nearest-neighbour index...");
+                engine.build_ann_index(AnnConfig::default());
+                ann_built = true;
+            }
+            println!("\n🪞 FILES LIKE {}:", results.iter().find(|r| r.id == id).map_or("", |r| r.title.as_str()));
+            print_results(&engine.similar_documents(id, 10));
+        }
+        if relevant.is_empty() && nonrelevant.is_empty() {
+            continue;
+        }
+
+        let refined = engine.search_with_feedback(query, &relevant, &nonrelevant, 10, lang);
+        if !refined.expansion_terms.is_empty() {
+            let terms: Vec<String> = refined.expansion_terms
+                .iter()
+                .map(|(word, weight)| format!("{} ({:.3})", word, weight))
+                .collect();
+            println!("\n🧭 Expanded with: {}", terms.join(", "));
+        }
+        println!("\n🎯 REFINED MATCHES:");
+        print_results(&refined.results);
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+// Loads the vocabulary and documents saved in `dir`, if a scan saved any there
+fn load_index(engine: &mut ResonantEngine, dir: &Path) -> io::Result<Option<usize>> {
+    let (vocabulary, index) = (dir.join(VOCABULARY_FILE), dir.join(INDEX_FILE));
+    if !vocabulary.exists() || !index.exists() {
+        return Ok(None);
+    }
+    engine.load_vocabulary(&vocabulary)?;
+    engine.load_index(&index).map(Some)
+}
+
+fn save_index(engine: &ResonantEngine, dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    engine.save_vocabulary(dir.join(VOCABULARY_FILE))?;
+    engine.save_index(dir.join(INDEX_FILE))
+}
+
+fn print_results(results: &[SearchResult]) {
+    if results.is_empty() {
+        println!("❌ No resonant patterns found.");
+        println!("💡 Try different keywords or concepts.");
+        return;
+    }
+
+    for (idx, result) in results.iter().enumerate() {
+        println!("\n[{}] 📄 {}", idx + 1, result.title);
+        println!("    📍 Path: {}", result.path);
+        if result.duplicates > 0 {
+            println!("    🧬 Near-duplicates hidden: {}", result.duplicates);
+        }
+        if !result.lang.is_empty() {
+            println!("    🌐 Language: {}", result.lang);
+        }
+        println!("    🎵 Resonance:      {:.4}", result.resonance);
+        println!("    🌀 Δ Entropy:      {:.4}", result.delta_entropy);
+        println!("    ⭐ Score:          {:.4}", result.score);
+        println!("    🔮 Quantum:        {:.4}", result.quantum_score);
+        println!("    🌊 Persistence:    {:.4}", result.persistence_score);
+        if result.proximity > 0.0 {
+            println!("    📏 Proximity:      {:.4}", result.proximity);
+        }
+        if result.phrase_score > 0.0 {
+            println!("    🔗 Phrase:         {:.4}", result.phrase_score);
+        }
+        match &result.passage {
+            Some(passage) => println!("    📑 Passage ({}..{}): {}", passage.start, passage.end, preview(&passage.text)),
+            None => println!("    👁️  Preview:        {}", result.snippet),
+        }
+    }
+}
+
+// Passages can run to a few hundred words over many lines
+fn preview(text: &str) -> String {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    if words.len() > 60 {
+        format!("{}...", words[..60].join(" "))
+    } else {
+        words.join(" ")
+    }
+}
@@ -0,0 +1,216 @@
+// src/passages.rs
+
+use crate::entropy::{entropy_profile, passage_boundaries, PROFILE_STRIDE, PROFILE_WINDOW};
+use crate::postings::Postings;
+use crate::sparse_vector::SparseVector;
+use crate::weighting::log_tf;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// Longest passage in tokens; longer runs without an entropy shift are split
+/// into equal parts.
+pub const MAX_PASSAGE_TOKENS: usize = 4 * PROFILE_WINDOW;
+
+/// A run of a document's tokens between two entropy shifts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Passage {
+    /// Position of the first token.
+    pub first_token: u32,
+    /// Position just past the last token.
+    pub end_token: u32,
+    /// Byte offset in the document text where the first token's word starts.
+    pub start: usize,
+    /// Byte offset just past the last token's word.
+    pub end: usize,
+}
+
+/// A document's entropy profile over sliding token windows, and the passages
+/// its shifts cut the document into.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DocumentPassages {
+    /// Entropy of each `PROFILE_WINDOW`-token window, one every `PROFILE_STRIDE` tokens.
+    pub entropy_profile: Vec<f64>,
+    /// The passages in document order, covering every token.
+    pub passages: Vec<Passage>,
+}
+
+impl DocumentPassages {
+    /// Profiles a document's tokens and cuts it into passages. `offsets`
+    /// are the byte ranges of the words the tokens came from, as
+    /// `Analyzer::analyze_with_offsets` gives them.
+    pub fn new(primes: &[u64], offsets: &[Range<usize>]) -> Self {
+        let entropy_profile = entropy_profile(primes, PROFILE_WINDOW, PROFILE_STRIDE);
+        let token_count = primes.len().min(offsets.len());
+
+        let mut starts = vec![0];
+        starts.extend(
+            passage_boundaries(&entropy_profile, PROFILE_WINDOW, PROFILE_STRIDE)
+                .into_iter()
+                .filter(|&boundary| boundary > 0 && boundary < token_count),
+        );
+        starts.push(token_count);
+
+        let mut passages = Vec::new();
+        for pair in starts.windows(2) {
+            let (first, end) = (pair[0], pair[1]);
+            let parts = (end - first).div_ceil(MAX_PASSAGE_TOKENS);
+            for part in 0..parts {
+                let part_first = first + (end - first) * part / parts;
+                let part_end = first + (end - first) * (part + 1) / parts;
+                passages.push(Passage {
+                    first_token: part_first as u32,
+                    end_token: part_end as u32,
+                    start: offsets[part_first].start,
+                    end: offsets[part_end - 1].end,
+                });
+            }
+        }
+
+        DocumentPassages { entropy_profile, passages }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.passages.is_empty()
+    }
+
+    /// The passage where the query's words occur most, with its score: the
+    /// sum over query words of their weight times the log-TF of their count
+    /// in the passage. `None` if no passage has any of them.
+    pub fn best_match(&self, query: &SparseVector, postings: &Postings) -> Option<(&Passage, f64)> {
+        let mut best: Option<(&Passage, f64)> = None;
+        for passage in &self.passages {
+            let score: f64 = query
+                .iter()
+                .filter_map(|(prime, weight)| {
+                    let positions = postings.get(&prime)?;
+                    let count = positions.partition_point(|&position| position < passage.end_token)
+                        - positions.partition_point(|&position| position < passage.first_token);
+                    Some(weight * log_tf(count as f64))
+                })
+                .sum();
+            if score > 0.0 && best.is_none_or(|(_, best_score)| score > best_score) {
+                best = Some((passage, score));
+            }
+        }
+        best
+    }
+}
+
+/// The passage of a search result that best matches the query.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PassageMatch {
+    /// Byte offset of the passage in the document text.
+    pub start: usize,
+    /// Byte offset just past its end.
+    pub end: usize,
+    pub text: String,
+    /// How strongly the query's words occur in it, from `DocumentPassages::best_match`.
+    pub score: f64,
+}
+
+impl PassageMatch {
+    /// Cuts a passage out of the document text, unless its offsets do not fit the text.
+    pub fn new(passage: &Passage, text: &str, score: f64) -> Option<Self> {
+        Some(PassageMatch {
+            start: passage.start,
+            end: passage.end,
+            text: text.get(passage.start..passage.end)?.to_string(),
+            score,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::postings::build_postings;
+
+    /// Byte ranges for `count` four-letter words separated by spaces.
+    fn offsets(count: usize) -> Vec<Range<usize>> {
+        (0..count).map(|i| 5 * i..5 * i + 4).collect()
+    }
+
+    /// `low` tokens alternating between two words, then `high` different words.
+    fn shifting_text(low: usize, high: usize) -> Vec<u64> {
+        let mut primes: Vec<u64> = (0..low).map(|i| if i % 2 == 0 { 2 } else { 3 }).collect();
+        primes.extend(primal::Primes::all().skip(2).take(high).map(|prime| prime as u64));
+        primes
+    }
+
+    #[test]
+    fn passages_cover_every_token_in_order() {
+        let texts: [Vec<u64>; 5] = [
+            Vec::new(),
+            vec![2, 3, 5],
+            (0..600).map(|i| [2, 3, 5, 7, 11, 13, 17, 19][i % 8]).collect(),
+            shifting_text(128, 128),
+            shifting_text(300, 500),
+        ];
+        for primes in &texts {
+            let offsets = offsets(primes.len());
+            let passages = DocumentPassages::new(primes, &offsets);
+            assert_eq!(passages.is_empty(), primes.is_empty());
+
+            let mut next = 0;
+            for passage in &passages.passages {
+                assert_eq!(passage.first_token, next, "gap before {:?}", passage);
+                assert!(passage.end_token > passage.first_token, "empty passage {:?}", passage);
+                assert!((passage.end_token - passage.first_token) as usize <= MAX_PASSAGE_TOKENS);
+                assert_eq!(passage.start, offsets[passage.first_token as usize].start);
+                assert_eq!(passage.end, offsets[passage.end_token as usize - 1].end);
+                next = passage.end_token;
+            }
+            assert_eq!(next as usize, primes.len());
+        }
+    }
+
+    #[test]
+    fn an_entropy_shift_starts_a_passage() {
+        let primes = shifting_text(128, 128);
+        let passages = DocumentPassages::new(&primes, &offsets(primes.len()));
+        assert_eq!(passages.passages.len(), 2, "{:?}", passages.passages);
+        let shift = passages.passages[1].first_token as usize;
+        assert!(shift.abs_diff(128) <= PROFILE_STRIDE, "cut at {}", shift);
+
+        // Without a shift, a long run is split into equal parts
+        let uniform: Vec<u64> = (0..600).map(|i| [2, 3, 5, 7, 11, 13, 17, 19][i % 8]).collect();
+        let passages = DocumentPassages::new(&uniform, &offsets(uniform.len()));
+        let lengths: Vec<u32> = passages.passages.iter().map(|passage| passage.end_token - passage.first_token).collect();
+        assert_eq!(lengths, vec![200, 200, 200]);
+    }
+
+    #[test]
+    fn best_match_picks_the_passage_with_most_query_words() {
+        let primes = shifting_text(128, 128);
+        let passages = DocumentPassages::new(&primes, &offsets(primes.len()));
+        let postings = build_postings(&primes);
+
+        // 2 only occurs in the first passage, 541 (the 100th prime) only in the second
+        let query: SparseVector = [(2, 1.0), (541, 1.0)].into_iter().collect();
+        let (passage, score) = passages.best_match(&query, &postings).unwrap();
+        assert_eq!(passage.first_token, 0);
+        let twos = primes[..passage.end_token as usize].iter().filter(|&&prime| prime == 2).count();
+        assert!((score - log_tf(twos as f64)).abs() < 1e-12, "{}", score);
+
+        let query: SparseVector = [(2, 0.1), (541, 1.0)].into_iter().collect();
+        let (passage, _) = passages.best_match(&query, &postings).unwrap();
+        assert_eq!(passage.first_token, passages.passages[1].first_token);
+
+        let query: SparseVector = [(1_000_003, 1.0)].into_iter().collect();
+        assert!(passages.best_match(&query, &postings).is_none());
+        assert!(DocumentPassages::default().best_match(&query, &postings).is_none());
+    }
+
+    #[test]
+    fn passage_match_needs_offsets_inside_the_text() {
+        let passage = |start, end| Passage { first_token: 0, end_token: 1, start, end };
+        let text = "café near the harbour";
+
+        let found = PassageMatch::new(&passage(6, 10), text, 0.5).unwrap();
+        assert_eq!((found.start, found.end, found.text.as_str(), found.score), (6, 10, "near", 0.5));
+        // Past the end of the text, and inside the two bytes of "é"
+        assert!(PassageMatch::new(&passage(14, 40), text, 0.5).is_none());
+        assert!(PassageMatch::new(&passage(0, 4), text, 0.5).is_none());
+    }
+}
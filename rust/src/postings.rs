@@ -0,0 +1,172 @@
+// src/postings.rs
+
+use std::collections::{HashMap, HashSet};
+
+/// Positional postings for one document: each prime maps to the token
+/// positions it occurs at, in increasing order.
+pub type Postings = HashMap<u64, Vec<u32>>;
+
+/// Records the position of every token in a document.
+pub fn build_postings(primes: &[u64]) -> Postings {
+    let mut postings: Postings = HashMap::new();
+    for (position, &prime) in primes.iter().enumerate() {
+        postings.entry(prime).or_default().push(position as u32);
+    }
+    postings
+}
+
+/// Length of the shortest run of tokens that contains every query prime the
+/// document has, along with how many distinct query primes that is.
+pub fn min_span(query_primes: &[u64], postings: &Postings) -> Option<(u32, usize)> {
+    let terms: HashSet<u64> = query_primes.iter().copied().filter(|p| postings.contains_key(p)).collect();
+    if terms.is_empty() {
+        return None;
+    }
+
+    // Merge all occurrences into one position-ordered list
+    let terms: Vec<u64> = terms.into_iter().collect();
+    let mut occurrences: Vec<(u32, usize)> = terms
+        .iter()
+        .enumerate()
+        .flat_map(|(term, prime)| postings[prime].iter().map(move |&position| (position, term)))
+        .collect();
+    occurrences.sort_unstable();
+
+    // Sliding window over the occurrences that keeps every term covered
+    let mut counts = vec![0usize; terms.len()];
+    let mut covered = 0;
+    let mut best = u32::MAX;
+    let mut start = 0;
+    for &(end_position, term) in &occurrences {
+        if counts[term] == 0 {
+            covered += 1;
+        }
+        counts[term] += 1;
+
+        while covered == terms.len() {
+            let (start_position, start_term) = occurrences[start];
+            best = best.min(end_position - start_position + 1);
+            counts[start_term] -= 1;
+            if counts[start_term] == 0 {
+                covered -= 1;
+            }
+            start += 1;
+        }
+    }
+
+    Some((best, terms.len()))
+}
+
+/// Scores how close together the query words appear in a document (0 to 1).
+///
+/// 1.0 means every query word occurs and they are adjacent somewhere; the
+/// score shrinks as the words spread apart or go missing. A single query
+/// word has no proximity and scores 0.
+pub fn proximity_score(query_primes: &[u64], postings: &Postings) -> f64 {
+    let distinct: HashSet<u64> = query_primes.iter().copied().collect();
+    if distinct.len() < 2 {
+        return 0.0;
+    }
+
+    match min_span(query_primes, postings) {
+        Some((span, found)) if found > 1 => {
+            let coverage = found as f64 / distinct.len() as f64;
+            let compactness = found as f64 / span as f64;
+            coverage * compactness
+        }
+        _ => 0.0,
+    }
+}
+
+/// Checks whether the phrase occurs in the document with its words in order and adjacent.
+pub fn contains_phrase(phrase: &[u64], postings: &Postings) -> bool {
+    let Some(first) = phrase.first().and_then(|prime| postings.get(prime)) else {
+        return false;
+    };
+
+    first.iter().any(|&start| {
+        phrase.iter().enumerate().skip(1).all(|(offset, prime)| {
+            postings
+                .get(prime)
+                .is_some_and(|positions| positions.binary_search(&(start + offset as u32)).is_ok())
+        })
+    })
+}
+
+/// Relabels the primes of the postings using an old-prime to new-prime mapping.
+///
+/// Primes missing from the mapping are dropped.
+pub fn remap_postings(postings: &Postings, mapping: &HashMap<u64, u64>) -> Postings {
+    postings
+        .iter()
+        .filter_map(|(prime, positions)| mapping.get(prime).map(|&new_prime| (new_prime, positions.clone())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Words as primes: "the"=2, "quick"=3, "brown"=5, "fox"=7, "jumps"=11, "over"=13
+    const TEXT: [u64; 9] = [2, 3, 5, 7, 11, 13, 2, 3, 7];
+
+    #[test]
+    fn min_span_covers_the_query_words_found() {
+        let postings = build_postings(&TEXT);
+        let cases: [(&[u64], u32, usize); 6] = [
+            // Adjacent words
+            (&[5, 7], 2, 2),
+            // Gapped: two words between "brown" and "over"
+            (&[5, 13], 4, 2),
+            // The closest pair of occurrences wins: "over the quick"
+            (&[13, 3], 3, 2),
+            // A repeated query word counts once
+            (&[2, 2, 11], 3, 2),
+            // A missing word is left out of the span
+            (&[5, 7, 17], 2, 2),
+            (&[11], 1, 1),
+        ];
+        for (query, span, found) in cases {
+            assert_eq!(min_span(query, &postings), Some((span, found)), "{:?}", query);
+        }
+        assert_eq!(min_span(&[17, 19], &postings), None);
+    }
+
+    #[test]
+    fn proximity_falls_as_words_spread_or_go_missing() {
+        let postings = build_postings(&TEXT);
+        assert_eq!(proximity_score(&[5, 7], &postings), 1.0);
+        assert!((proximity_score(&[5, 13], &postings) - 0.5).abs() < 1e-12);
+        assert!((proximity_score(&[5, 7, 17], &postings) - 2.0 / 3.0).abs() < 1e-12);
+        // A single word, even repeated, has no proximity
+        assert_eq!(proximity_score(&[5], &postings), 0.0);
+        assert_eq!(proximity_score(&[5, 5], &postings), 0.0);
+        assert_eq!(proximity_score(&[17, 19], &postings), 0.0);
+    }
+
+    #[test]
+    fn phrases_match_words_in_order_and_adjacent() {
+        let postings = build_postings(&TEXT);
+        let cases: [(&[u64], bool); 9] = [
+            (&[3, 5, 7], true),
+            // Matched at its second occurrence: "the quick fox"
+            (&[2, 3, 7], true),
+            // Out of order or with a gap
+            (&[7, 5], false),
+            (&[5, 11], false),
+            // A repeated word must repeat in the text too
+            (&[2, 2], false),
+            (&[7, 11, 13, 2, 3, 7], true),
+            // A missing word
+            (&[5, 17], false),
+            // A single word is found wherever it occurs
+            (&[13], true),
+            (&[17], false),
+        ];
+        for (phrase, expected) in cases {
+            assert_eq!(contains_phrase(phrase, &postings), expected, "{:?}", phrase);
+        }
+        assert!(contains_phrase(&[7, 7], &build_postings(&[5, 7, 7])));
+        assert!(!contains_phrase(&[], &postings));
+    }
+}
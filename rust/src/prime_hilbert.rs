@@ -3,6 +3,12 @@
 use std::collections::HashMap;
 use serde::{Serialize, Deserialize};
 use num_complex::Complex;
+use std::f64::consts::PI;
+use crate::postings::{build_postings, Postings};
+use crate::density::DocumentDensity;
+use crate::sparse_vector::SparseVector;
+use crate::weighting::document_length;
+use crate::tokenizer::ngram_keys;
 
 // Define PrimeVector as a type alias for HashMap
 pub type PrimeVector = HashMap<u64, f64>;
@@ -29,6 +35,28 @@ pub fn build_vector(tokens: &[u64]) -> PrimeVector {
     vec
 }
 
+/// Builds a PrimeVector of the composite keys for runs of 2 to `max_n` words.
+///
+/// Each run length is normalized on its own. The word primes are left out, so
+/// the keys are kept apart from the document vector and add nothing to its
+/// norm or to bag-of-words scores.
+pub fn build_ngram_vector(primes: &[u64], max_n: usize) -> PrimeVector {
+    let mut vector = PrimeVector::new();
+    for n in 2..=max_n {
+        vector.extend(build_vector(&ngram_keys(primes, n)));
+    }
+    vector
+}
+
+/// Returns the fraction of a phrase's composite keys present in a vector (0 to 1).
+pub fn phrase_match(keys: &[u64], vector: &SparseVector) -> f64 {
+    if keys.is_empty() {
+        return 0.0;
+    }
+    let matched = keys.iter().filter(|&&key| vector.contains_key(key)).count();
+    matched as f64 / keys.len() as f64
+}
+
 /// Computes the dot product of two PrimeVectors (sparse representations).
 pub fn dot_product(vec1: &PrimeVector, vec2: &PrimeVector) -> f64 {
     let mut sum = 0.0;
@@ -40,43 +68,154 @@ pub fn dot_product(vec1: &PrimeVector, vec2: &PrimeVector) -> f64 {
     sum
 }
 
-/// Builds a biorthogonal vector (two components) from a list of prime tokens.
-pub fn build_biorthogonal_vector(primes: &[u64]) -> BiorthogonalVector {
-    let mut left = HashMap::new();
-    let mut right = HashMap::new();
+/// Adds two vectors, keeping every prime either of them holds.
+pub fn add_vectors(a: &PrimeVector, b: &PrimeVector) -> PrimeVector {
+    let mut sum = a.clone();
+    for (&prime, &value) in b {
+        *sum.entry(prime).or_insert(0.0) += value;
+    }
+    sum
+}
+
+/// Subtracts `b` from `a`; primes only `b` holds come out negative.
+pub fn subtract_vectors(a: &PrimeVector, b: &PrimeVector) -> PrimeVector {
+    add_vectors(a, &scale_vector(b, -1.0))
+}
 
-    for &prime in primes {
-        // Simple example: distribute token frequency to left and right components
-        // In a real system, this would be based on some deeper mathematical property
-        *left.entry(prime).or_insert(0.0) += 0.5;
-        *right.entry(prime).or_insert(0.0) += 0.5;
+/// Multiplies every value of a vector by `factor`.
+pub fn scale_vector(vector: &PrimeVector, factor: f64) -> PrimeVector {
+    vector.iter().map(|(&prime, &value)| (prime, value * factor)).collect()
+}
+
+/// The mean of the vectors, or an empty vector if there are none.
+pub fn centroid<'a>(vectors: impl IntoIterator<Item = &'a PrimeVector>) -> PrimeVector {
+    let mut sum = PrimeVector::new();
+    let mut count = 0;
+    for vector in vectors {
+        sum = add_vectors(&sum, vector);
+        count += 1;
+    }
+    if count == 0 {
+        return sum;
     }
+    scale_vector(&sum, 1.0 / count as f64)
+}
 
-    // Normalize (optional, depending on intended use)
-    let total_primes = primes.len() as f64;
-    if total_primes > 0.0 {
-        for (_prime, freq) in left.iter_mut() {
-            *freq /= total_primes;
+/// The `k` primes with the largest values, largest first. Ties go to the
+/// smaller prime, so the result does not depend on hash order.
+pub fn top_terms(vector: &PrimeVector, k: usize) -> Vec<(u64, f64)> {
+    let mut terms: Vec<(u64, f64)> = vector.iter().map(|(&prime, &value)| (prime, value)).collect();
+    terms.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal).then(a.0.cmp(&b.0)));
+    terms.truncate(k);
+    terms
+}
+
+/// Rocchio relevance feedback: `alpha` times the query, plus `beta` times the
+/// centroid of the relevant documents, minus `gamma` times the centroid of the
+/// non-relevant ones. Primes that end up at or below zero are dropped.
+pub fn rocchio(
+    query: &PrimeVector,
+    relevant: &[PrimeVector],
+    nonrelevant: &[PrimeVector],
+    alpha: f64,
+    beta: f64,
+    gamma: f64,
+) -> PrimeVector {
+    let toward = add_vectors(&scale_vector(query, alpha), &scale_vector(&centroid(relevant), beta));
+    let mut refined = subtract_vectors(&toward, &scale_vector(&centroid(nonrelevant), gamma));
+    refined.retain(|_, value| *value > 0.0);
+    refined
+}
+
+/// Co-occurrence window used when none is given.
+pub const COOCCURRENCE_WINDOW: usize = 5;
+
+/// The two views of a document that its biorthogonal vectors are built from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BiorthogonalViews {
+    /// Left: the title and headings. Right: the body text.
+    #[default]
+    TitleBody,
+    /// Left: the words. Right: pairs of different words at most `window`
+    /// tokens apart, keyed by the product of their primes.
+    Cooccurrence { window: usize },
+}
+
+impl BiorthogonalViews {
+    /// Parses "title-body", "cooccurrence" or "cooccurrence:N".
+    pub fn parse(spec: &str) -> Option<Self> {
+        match spec.trim() {
+            "title-body" => Some(BiorthogonalViews::TitleBody),
+            "cooccurrence" => Some(BiorthogonalViews::Cooccurrence { window: COOCCURRENCE_WINDOW }),
+            other => {
+                let window = other.strip_prefix("cooccurrence:")?.parse().ok()?;
+                (window > 0).then_some(BiorthogonalViews::Cooccurrence { window })
+            }
         }
-        for (_prime, freq) in right.iter_mut() {
-            *freq /= total_primes;
+    }
+
+    /// The spec `parse` reads back.
+    pub fn spec(&self) -> String {
+        match self {
+            BiorthogonalViews::TitleBody => "title-body".to_string(),
+            BiorthogonalViews::Cooccurrence { window } => format!("cooccurrence:{}", window),
         }
     }
+}
 
-    BiorthogonalVector { left, right }
+/// Builds a document's biorthogonal vector from the primes of its title and
+/// headings and the primes of its body.
+pub fn build_biorthogonal_vector(heading_primes: &[u64], body_primes: &[u64], views: BiorthogonalViews) -> BiorthogonalVector {
+    match views {
+        BiorthogonalViews::TitleBody => BiorthogonalVector {
+            left: build_vector(heading_primes),
+            right: build_vector(body_primes),
+        },
+        BiorthogonalViews::Cooccurrence { window } => BiorthogonalVector {
+            left: build_vector(body_primes),
+            right: cooccurrence_vector(body_primes, window),
+        },
+    }
+}
+
+/// Builds a query's biorthogonal vector for documents built with `views`.
+///
+/// A query has a single view, so it looks for its words in both the
+/// headings and the body, or for its word pairs anywhere near each other.
+pub fn build_query_biorthogonal(primes: &[u64], views: BiorthogonalViews) -> BiorthogonalVector {
+    let right = match views {
+        BiorthogonalViews::TitleBody => build_vector(primes),
+        BiorthogonalViews::Cooccurrence { .. } => cooccurrence_vector(primes, primes.len()),
+    };
+    BiorthogonalVector {
+        left: build_vector(primes),
+        right,
+    }
+}
+
+/// Counts pairs of different words at most `window` tokens apart, keyed by
+/// the product of their primes and normalized like `build_vector`.
+pub fn cooccurrence_vector(primes: &[u64], window: usize) -> PrimeVector {
+    let mut pairs = Vec::new();
+    for (i, &prime) in primes.iter().enumerate() {
+        for &other in primes.iter().skip(i + 1).take(window) {
+            if other != prime {
+                pairs.extend(prime.checked_mul(other));
+            }
+        }
+    }
+    build_vector(&pairs)
 }
 
 /// Calculates a complex resonance between two vectors with decay factor
-pub fn resonance_complex(vec1: &PrimeVector, vec2: &PrimeVector, decay_factor: f64) -> Complex<f64> {
-    let real_part = dot_product(vec1, vec2);
+pub fn resonance_complex(vec1: &SparseVector, vec2: &SparseVector, decay_factor: f64) -> Complex<f64> {
+    let real_part = vec1.dot(vec2);
     
     // Calculate a phase component based on the prime distribution
     let mut phase = 0.0;
-    for (prime, freq1) in vec1 {
-        if let Some(freq2) = vec2.get(prime) {
-            // Use the prime number itself to contribute to phase
-            phase += (*prime as f64).ln() * freq1 * freq2;
-        }
+    for (prime, freq1, freq2) in vec1.matches(vec2) {
+        // Use the prime number itself to contribute to phase
+        phase += (prime as f64).ln() * freq1 * freq2;
     }
     
     // Apply decay factor
@@ -86,22 +225,367 @@ pub fn resonance_complex(vec1: &PrimeVector, vec2: &PrimeVector, decay_factor: f
     Complex::new(decayed_real, decayed_imag)
 }
 
-/// Converts a sparse PrimeVector to a dense Vec<f64> of a specified max dimension.
-pub fn to_dense_vector(sparse_vec: &PrimeVector, max_prime_value: u64) -> Vec<f64> {
-    let mut dense_vec = vec![0.0; (max_prime_value + 1) as usize];
-    for (&prime, &freq) in sparse_vec {
-        if (prime as usize) < dense_vec.len() {
-            dense_vec[prime as usize] = freq;
+/// A sparse complex vector: one amplitude per prime.
+pub type ComplexPrimeVector = HashMap<u64, Complex<f64>>;
+
+/// Builds one complex amplitude per prime from where its tokens occur.
+///
+/// Each occurrence at position `k` of `n` tokens contributes a unit phasor at
+/// angle π·k/n, and the sum is scaled like `build_vector`. Words that occur
+/// close together end up with similar phases, while the start and the end of
+/// the document are in antiphase rather than wrapping around to meet; a word
+/// spread over the whole document partly cancels itself out.
+pub fn build_complex_vector(primes: &[u64]) -> ComplexPrimeVector {
+    complex_vector_from_postings(&build_postings(primes))
+}
+
+/// Builds the amplitudes of `build_complex_vector` from a document's postings.
+pub fn complex_vector_from_postings(postings: &Postings) -> ComplexPrimeVector {
+    let length = document_length(postings);
+    let scale = amplitude_scale(postings);
+    postings
+        .iter()
+        .map(|(&prime, positions)| (prime, phase_amplitude(positions, length, scale)))
+        .collect()
+}
+
+/// Complex inner product ⟨a|b⟩, conjugating the first vector.
+pub fn complex_inner_product(a: &ComplexPrimeVector, b: &ComplexPrimeVector) -> Complex<f64> {
+    let (small, large, conjugate_small) = if a.len() <= b.len() { (a, b, true) } else { (b, a, false) };
+    small
+        .iter()
+        .filter_map(|(prime, &x)| {
+            let y = *large.get(prime)?;
+            Some(if conjugate_small { x.conj() * y } else { y.conj() * x })
+        })
+        .sum()
+}
+
+/// Inner product of a real query vector with a document's position-derived
+/// amplitudes, the same as `complex_inner_product` with every query phase at
+/// zero but touching only the query's primes.
+///
+/// Its modulus is largest when the query words sit together in the document,
+/// where their phases agree and add up.
+pub fn interference_resonance(query: &SparseVector, postings: &Postings) -> Complex<f64> {
+    let length = document_length(postings);
+    let scale = amplitude_scale(postings);
+    query
+        .iter()
+        .filter_map(|(prime, weight)| Some(phase_amplitude(postings.get(&prime)?, length, scale) * weight))
+        .sum()
+}
+
+// What `build_vector` divides the counts by: the number of tokens.
+fn amplitude_scale(postings: &Postings) -> f64 {
+    document_length(postings) as f64
+}
+
+fn phase_amplitude(positions: &[u32], length: u64, scale: f64) -> Complex<f64> {
+    if length == 0 || scale <= 0.0 {
+        return Complex::new(0.0, 0.0);
+    }
+    let sum: Complex<f64> = positions
+        .iter()
+        .map(|&position| Complex::from_polar(1.0, PI * position as f64 / length as f64))
+        .sum();
+    sum / scale
+}
+
+/// How the quantum score is computed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QuantumScoring {
+    /// `resonance_complex`: the real overlap plus a prime-weighted phase term.
+    #[default]
+    Resonance,
+    /// The modulus of `interference_resonance`, so query words found close
+    /// together in a document interfere constructively.
+    Interference,
+    /// The fidelity of the document's `DocumentDensity`, a mixture of its
+    /// passages, with the query as a pure state: how likely the query is
+    /// to be found in a passage drawn from the document.
+    Fidelity,
+}
+
+impl QuantumScoring {
+    /// Parses "resonance", "interference" or "fidelity".
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "resonance" => Some(QuantumScoring::Resonance),
+            "interference" => Some(QuantumScoring::Interference),
+            "fidelity" => Some(QuantumScoring::Fidelity),
+            _ => None,
+        }
+    }
+
+    /// The name `parse` accepts for this option.
+    pub fn name(&self) -> &'static str {
+        match self {
+            QuantumScoring::Resonance => "resonance",
+            QuantumScoring::Interference => "interference",
+            QuantumScoring::Fidelity => "fidelity",
+        }
+    }
+
+    /// Combines a document's complex resonance with its biorthogonal score.
+    ///
+    /// Documents indexed without postings have no phases or density matrix,
+    /// so `Interference` and `Fidelity` fall back to the plain overlap for
+    /// them. Only `Fidelity` reads the density.
+    pub fn score(&self, query: &SparseVector, vector: &SparseVector, postings: &Postings, density: &DocumentDensity, decay_factor: f64, bio_score: f64) -> f64 {
+        match self {
+            QuantumScoring::Resonance => {
+                let complex_res = resonance_complex(query, vector, decay_factor);
+                complex_res.re * 0.6 + complex_res.im.abs() * 0.2 + bio_score * 0.2
+            }
+            QuantumScoring::Interference => {
+                let amplitude = if postings.is_empty() {
+                    query.dot(vector)
+                } else {
+                    interference_resonance(query, postings).norm()
+                };
+                amplitude * (-decay_factor).exp() * 0.8 + bio_score * 0.2
+            }
+            QuantumScoring::Fidelity => {
+                let fidelity = if density.is_empty() {
+                    query.dot(vector)
+                } else {
+                    density.fidelity(query)
+                };
+                fidelity * (-decay_factor).exp() * 0.8 + bio_score * 0.2
+            }
+        }
+    }
+}
+
+/// Returns the word primes of a vector, skipping composite n-gram keys.
+pub fn token_primes(vector: &PrimeVector) -> impl Iterator<Item = u64> + '_ {
+    vector.keys().copied().filter(|&key| primal::is_prime(key))
+}
+
+/// Like `token_primes`, for a `SparseVector`, in ascending order.
+pub fn token_primes_sparse(vector: &SparseVector) -> impl Iterator<Item = u64> + '_ {
+    vector.keys().filter(|&key| primal::is_prime(key))
+}
+
+/// Dimension of the dense vectors used for reversibility and buffering.
+pub const DENSE_DIMENSION: usize = 1024;
+
+/// Seed of the hash `project_dense` places primes with, fixed so stored and
+/// freshly computed dense vectors line up.
+pub const DENSE_SEED: u64 = 0x2545_f491_4f6c_dd1d;
+
+/// Projects a sparse PrimeVector onto a fixed number of dimensions by feature hashing.
+///
+/// Every word prime is hashed to one of the dimensions and its value added
+/// there, so no word is dropped however large its prime. Values are added
+/// without a sign, so a frequency vector stays a distribution. Composite
+/// n-gram keys are skipped.
+pub fn project_dense(sparse_vec: &PrimeVector, dimension: usize) -> Vec<f64> {
+    project_dense_with_seed(sparse_vec, dimension, DENSE_SEED)
+}
+
+/// Like `project_dense`, with a chosen hash seed.
+pub fn project_dense_with_seed(sparse_vec: &PrimeVector, dimension: usize, seed: u64) -> Vec<f64> {
+    project_entries(sparse_vec.iter().map(|(&prime, &freq)| (prime, freq)), dimension, seed)
+}
+
+/// Like `project_dense`, for a `SparseVector`.
+pub fn project_dense_sparse(sparse_vec: &SparseVector, dimension: usize) -> Vec<f64> {
+    project_entries(sparse_vec.iter(), dimension, DENSE_SEED)
+}
+
+fn project_entries(entries: impl Iterator<Item = (u64, f64)>, dimension: usize, seed: u64) -> Vec<f64> {
+    let mut dense_vec = vec![0.0; dimension];
+    if dimension == 0 {
+        return dense_vec;
+    }
+    for (prime, freq) in entries {
+        if primal::is_prime(prime) {
+            dense_vec[(mix_hash(prime ^ seed) % dimension as u64) as usize] += freq;
         }
     }
     dense_vec
 }
 
-/// Calculates a score based on the query's resonance with the biorthogonal components of a document.
-pub fn biorthogonal_score(query_bio: &BiorthogonalVector, doc_bio: &BiorthogonalVector) -> f64 {
-    // Calculate dot products with both left and right components
-    let score_left = dot_product(&query_bio.left, &doc_bio.left);
-    let score_right = dot_product(&query_bio.right, &doc_bio.right);
-    // Combine them
-    score_left + score_right
-}
\ No newline at end of file
+// SplitMix64 finalizer: a fixed, well-spread hash that does not depend on the
+// standard library's hasher.
+pub(crate) fn mix_hash(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e3779b97f4a7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+/// Scores how well a query matches a document in both of its views.
+///
+/// This is the geometric mean of the two view resonances, so a document has
+/// to match in both to score. A query with nothing in one view (a single
+/// word has no pairs) is scored on the other alone.
+pub fn biorthogonal_score(query: &BiorthogonalVector, doc: &BiorthogonalVector) -> f64 {
+    let left = dot_product(&query.left, &doc.left);
+    let right = dot_product(&query.right, &doc.right);
+    match (query.left.is_empty(), query.right.is_empty()) {
+        (false, false) => (left * right).sqrt(),
+        (false, true) => left,
+        (true, false) => right,
+        (true, true) => 0.0,
+    }
+}
+
+/// Relabels the primes of a vector using an old-prime to new-prime mapping.
+///
+/// Composite n-gram keys are factored over the vector's own word primes and
+/// rebuilt from the mapped factors. Keys that cannot be mapped are dropped, and
+/// so are composite keys whose new product no longer fits in a `u64`.
+pub fn remap_vector(vector: &PrimeVector, mapping: &HashMap<u64, u64>) -> PrimeVector {
+    let mut factors: Vec<u64> = vector.keys().copied().filter(|key| mapping.contains_key(key)).collect();
+    factors.sort_unstable();
+    remap_with_factors(vector, mapping, &factors)
+}
+
+/// Like `remap_vector`, for a `SparseVector`.
+pub fn remap_vector_sparse(vector: &SparseVector, mapping: &HashMap<u64, u64>) -> SparseVector {
+    remap_ngram_vector(vector, vector, mapping)
+}
+
+/// Relabels the composite keys of an n-gram vector, factored over the word
+/// primes of the document vector built from the same words, see `remap_vector`.
+pub fn remap_ngram_vector(ngrams: &SparseVector, words: &SparseVector, mapping: &HashMap<u64, u64>) -> SparseVector {
+    // Keys are in ascending order already
+    let factors: Vec<u64> = words.keys().filter(|key| mapping.contains_key(key)).collect();
+    ngrams
+        .iter()
+        .filter_map(|(key, value)| remap_key(key, mapping, &factors).map(|new_key| (new_key, value)))
+        .collect()
+}
+
+fn remap_with_factors(vector: &PrimeVector, mapping: &HashMap<u64, u64>, factors: &[u64]) -> PrimeVector {
+    vector
+        .iter()
+        .filter_map(|(&key, &value)| remap_key(key, mapping, factors).map(|new_key| (new_key, value)))
+        .collect()
+}
+
+fn remap_key(key: u64, mapping: &HashMap<u64, u64>, factors: &[u64]) -> Option<u64> {
+    if let Some(&new_prime) = mapping.get(&key) {
+        return Some(new_prime);
+    }
+
+    let mut rest = key;
+    let mut new_key: u64 = 1;
+    for &prime in factors {
+        if rest == 1 || prime > rest {
+            break;
+        }
+        while rest.is_multiple_of(prime) {
+            rest /= prime;
+            // A key whose new product overflows is dropped, like the runs
+            // `ngram_keys` skips
+            new_key = new_key.checked_mul(mapping[&prime])?;
+        }
+    }
+    (rest == 1 && new_key > 1).then_some(new_key)
+}
+
+/// Relabels both components of a biorthogonal vector, see `remap_vector`.
+///
+/// Composite keys are factored over the word primes of both components, since
+/// co-occurrence pairs on the right are made of the words on the left.
+pub fn remap_biorthogonal(vector: &BiorthogonalVector, mapping: &HashMap<u64, u64>) -> BiorthogonalVector {
+    let mut factors: Vec<u64> = vector
+        .left
+        .keys()
+        .chain(vector.right.keys())
+        .copied()
+        .filter(|key| mapping.contains_key(key))
+        .collect();
+    factors.sort_unstable();
+    factors.dedup();
+
+    BiorthogonalVector {
+        left: remap_with_factors(&vector.left, mapping, &factors),
+        right: remap_with_factors(&vector.right, mapping, &factors),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn vector(entries: &[(u64, f64)]) -> PrimeVector {
+        entries.iter().copied().collect()
+    }
+
+    fn assert_close(actual: &PrimeVector, expected: &PrimeVector) {
+        assert_eq!(actual.len(), expected.len(), "{:?} against {:?}", actual, expected);
+        for (prime, value) in expected {
+            let found = actual.get(prime).copied().unwrap_or(f64::NAN);
+            assert!((found - value).abs() < 1e-12, "{}: {} against {}", prime, found, value);
+        }
+    }
+
+    #[test]
+    fn project_dense_places_primes_the_same_way_every_time() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let entries: Vec<(u64, f64)> = primal::Primes::all()
+            .take(3000)
+            .filter(|_| rng.gen_bool(0.3))
+            .map(|prime| (prime as u64, 1.0))
+            .collect();
+        let forward = vector(&entries);
+        let backward: PrimeVector = entries.iter().rev().copied().collect();
+
+        let dense = project_dense(&forward, DENSE_DIMENSION);
+        assert_eq!(dense.len(), DENSE_DIMENSION);
+        // Counts are whole numbers, so the order they are added in cannot matter
+        assert_eq!(dense, project_dense(&backward, DENSE_DIMENSION));
+        assert_eq!(dense, project_dense_with_seed(&forward, DENSE_DIMENSION, DENSE_SEED));
+        assert_eq!(dense, project_dense_sparse(&SparseVector::from(&forward), DENSE_DIMENSION));
+        assert_ne!(dense, project_dense_with_seed(&forward, DENSE_DIMENSION, DENSE_SEED + 1));
+        assert!(project_dense(&forward, 0).is_empty());
+    }
+
+    #[test]
+    fn large_primes_contribute_and_composite_keys_do_not() {
+        for prime in [1009, 7919, 104_729, 1_000_003, 4_294_967_311] {
+            let dense = project_dense(&vector(&[(prime, 0.5)]), DENSE_DIMENSION);
+            assert_eq!(dense.iter().filter(|&&value| value == 0.5).count(), 1, "{}", prime);
+            assert_eq!(dense.iter().sum::<f64>(), 0.5, "{}", prime);
+        }
+        // The bigram key of 1009 and 7919 adds nothing
+        let dense = project_dense(&vector(&[(1009, 0.25), (7919, 0.25), (1009 * 7919, 0.5)]), DENSE_DIMENSION);
+        assert_eq!(dense.iter().sum::<f64>(), 0.5);
+    }
+
+    #[test]
+    fn rocchio_with_one_kind_of_feedback() {
+        let query = vector(&[(2, 1.0), (3, 0.5)]);
+        let relevant = [vector(&[(3, 1.0), (5, 1.0)]), vector(&[(5, 1.0), (7, 2.0)])];
+        let nonrelevant = [vector(&[(2, 0.5), (11, 1.0)]), vector(&[(3, 1.0), (11, 1.0)])];
+
+        // Only relevant documents: their centroid is added and nothing is dropped
+        let refined = rocchio(&query, &relevant, &[], 1.0, 0.5, 0.25);
+        assert_close(&refined, &vector(&[(2, 1.0), (3, 0.75), (5, 0.5), (7, 0.5)]));
+
+        // Only non-relevant documents: words pushed to zero or below go, and
+        // words only they hold never come in
+        let refined = rocchio(&query, &[], &nonrelevant, 1.0, 0.5, 1.0);
+        assert_close(&refined, &vector(&[(2, 0.75)]));
+
+        assert_close(&rocchio(&query, &[], &[], 2.0, 0.5, 0.25), &scale_vector(&query, 2.0));
+    }
+
+    #[test]
+    fn top_terms_break_ties_by_the_smaller_prime() {
+        let terms = vector(&[(13, 0.5), (2, 0.25), (7, 0.5), (3, 0.25), (11, 1.0), (5, 0.25)]);
+        assert_eq!(top_terms(&terms, 3), vec![(11, 1.0), (7, 0.5), (13, 0.5)]);
+        assert_eq!(top_terms(&terms, 4), vec![(11, 1.0), (7, 0.5), (13, 0.5), (2, 0.25)]);
+        assert_eq!(top_terms(&terms, 10).len(), 6);
+        assert_eq!(top_terms(&terms, 10)[3..], [(2, 0.25), (3, 0.25), (5, 0.25)]);
+        assert!(top_terms(&terms, 0).is_empty());
+        assert!(top_terms(&PrimeVector::new(), 3).is_empty());
+    }
+}
@@ -3,6 +3,7 @@
 use nalgebra::{DMatrix, Complex, Matrix2, Vector3};
 use num_complex::ComplexFloat;
 use num_complex::Complex64;
+use crate::sparse_vector::SparseVector;
 
 /// Type alias for complex-valued matrices used in quantum computations
 pub type MatrixComplex<T> = DMatrix<Complex<T>>;
@@ -34,36 +35,127 @@ pub fn density_matrix(state: &VectorComplex<f64>) -> MatrixComplex<f64> {
     rho
 }
 
-/// Calculate mutual information between two probability distributions
-pub fn mutual_information(p1: &[f64], p2: &[f64]) -> f64 {
-    // Ensure both distributions have the same length
-    if p1.len() != p2.len() {
+/// Number of equal-width bins per variable the joint distribution behind
+/// `mutual_information` is estimated with
+pub const MI_BINS: usize = 8;
+
+/// Estimate the joint distribution of two documents' word counts
+///
+/// Every prime in either vector is one observation of the pair (its weight
+/// in `a`, its weight in `b`), 0 where a document lacks the word. Both
+/// variables are split into `bins` equal-width bins over their own range, and
+/// the result holds the fraction of primes falling in each pair of bins,
+/// indexed `[a bin][b bin]`.
+pub fn joint_distribution(a: &SparseVector, b: &SparseVector, bins: usize) -> Vec<Vec<f64>> {
+    let bins = bins.max(1);
+    let mut joint = vec![vec![0.0; bins]; bins];
+    let mut primes: Vec<u64> = a.keys().chain(b.keys()).collect();
+    primes.sort_unstable();
+    primes.dedup();
+    if primes.is_empty() {
+        return joint;
+    }
+    
+    let x: Vec<f64> = primes.iter().map(|&prime| a.get(prime).unwrap_or(0.0)).collect();
+    let y: Vec<f64> = primes.iter().map(|&prime| b.get(prime).unwrap_or(0.0)).collect();
+    let (x_min, x_max) = value_range(&x);
+    let (y_min, y_max) = value_range(&y);
+    for (&u, &v) in x.iter().zip(&y) {
+        joint[bin_index(u, x_min, x_max, bins)][bin_index(v, y_min, y_max, bins)] += 1.0 / primes.len() as f64;
+    }
+    
+    joint
+}
+
+/// Calculate the mutual information (in bits) between two documents' word counts
+///
+/// The information is computed from their `joint_distribution` over the
+/// primes of both, with `MI_BINS` bins per document:
+/// I(X;Y) = H(X) + H(Y) - H(X,Y). It is never negative and never exceeds
+/// either marginal entropy.
+pub fn mutual_information(a: &SparseVector, b: &SparseVector) -> f64 {
+    let (entropy_x, entropy_y, joint_entropy) = entropies(&joint_distribution(a, b, MI_BINS));
+    (entropy_x + entropy_y - joint_entropy).max(0.0)
+}
+
+/// Mutual information scaled to 0-1 by the marginal entropies
+///
+/// I(X;Y) / sqrt(H(X) * H(Y)), from the same `joint_distribution` as
+/// `mutual_information`: 1 when either document's counts determine the
+/// other's, 0 when they share no information or either is constant.
+pub fn normalized_mutual_information(a: &SparseVector, b: &SparseVector) -> f64 {
+    let (entropy_x, entropy_y, joint_entropy) = entropies(&joint_distribution(a, b, MI_BINS));
+    if entropy_x <= 0.0 || entropy_y <= 0.0 {
         return 0.0;
     }
+    ((entropy_x + entropy_y - joint_entropy) / (entropy_x * entropy_y).sqrt()).clamp(0.0, 1.0)
+}
+
+/// Jensen-Shannon divergence (in bits) between two distributions
+///
+/// Both are normalized to sum to 1 first, with negative values treated as 0,
+/// and the shorter one padded with zeros. The divergence is symmetric and
+/// bounded: 0 for identical distributions, 1 for ones with no overlap.
+pub fn jensen_shannon_divergence(p: &[f64], q: &[f64]) -> f64 {
+    let n = p.len().max(q.len());
+    let p_total: f64 = p.iter().map(|&v| v.max(0.0)).sum();
+    let q_total: f64 = q.iter().map(|&v| v.max(0.0)).sum();
+    if p_total <= 0.0 || q_total <= 0.0 {
+        // Nothing to compare unless both are empty
+        return if p_total <= 0.0 && q_total <= 0.0 { 0.0 } else { 1.0 };
+    }
     
-    let n = p1.len();
+    let mut divergence = 0.0;
+    for i in 0..n {
+        let pi = p.get(i).map_or(0.0, |&v| v.max(0.0)) / p_total;
+        let qi = q.get(i).map_or(0.0, |&v| v.max(0.0)) / q_total;
+        let mi = (pi + qi) / 2.0;
+        if pi > 0.0 {
+            divergence += 0.5 * pi * (pi / mi).log2();
+        }
+        if qi > 0.0 {
+            divergence += 0.5 * qi * (qi / mi).log2();
+        }
+    }
+    
+    divergence.clamp(0.0, 1.0)
+}
+
+/// Entropies (in bits) of the two marginals and of a joint distribution
+fn entropies(joint: &[Vec<f64>]) -> (f64, f64, f64) {
+    let bins = joint.len();
+    let mut entropy_x = 0.0;
+    let mut entropy_y = 0.0;
     let mut joint_entropy = 0.0;
-    let mut entropy_p1 = 0.0;
-    let mut entropy_p2 = 0.0;
     
-    // Calculate entropies
-    for i in 0..n {
-        if p1[i] > 0.0 {
-            entropy_p1 -= p1[i] * p1[i].log2();
+    for i in 0..bins {
+        let px: f64 = joint[i].iter().sum();
+        let py: f64 = joint.iter().map(|row| row[i]).sum();
+        if px > 0.0 {
+            entropy_x -= px * px.log2();
         }
-        if p2[i] > 0.0 {
-            entropy_p2 -= p2[i] * p2[i].log2();
+        if py > 0.0 {
+            entropy_y -= py * py.log2();
         }
-        
-        // Simplified joint entropy calculation assuming independence
-        let joint_p = p1[i] * p2[i];
-        if joint_p > 0.0 {
-            joint_entropy -= joint_p * joint_p.log2();
+        for &pxy in &joint[i] {
+            if pxy > 0.0 {
+                joint_entropy -= pxy * pxy.log2();
+            }
         }
     }
     
-    // Mutual information is the sum of individual entropies minus joint entropy
-    entropy_p1 + entropy_p2 - joint_entropy
+    (entropy_x, entropy_y, joint_entropy)
+}
+
+fn value_range(values: &[f64]) -> (f64, f64) {
+    values.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), &v| (min.min(v), max.max(v)))
+}
+
+fn bin_index(value: f64, min: f64, max: f64, bins: usize) -> usize {
+    if max <= min {
+        return 0;
+    }
+    (((value - min) / (max - min) * bins as f64) as usize).min(bins - 1)
 }
 
 /// Calculate redundancy in a vector (how many repeated elements)
@@ -80,9 +172,7 @@ pub fn calculate_redundancy(vec: &[f64]) -> f64 {
     
     // Calculate redundancy as the proportion of elements that are duplicates
     let unique_elements = count_map.len();
-    let redundancy = 1.0 - (unique_elements as f64 / n as f64);
-    
-    redundancy
+    1.0 - (unique_elements as f64 / n as f64)
 }
 
 /// Calculate symmetry in a vector (how close it is to being symmetric around its midpoint)
@@ -179,7 +269,7 @@ pub fn lindblad_evolution(
         let mut half_term = &term2 + &term3;
         for i in 0..half_term.nrows() {
             for j in 0..half_term.ncols() {
-                half_term[(i, j)] = half_term[(i, j)] * Complex::new(0.5, 0.0);
+                half_term[(i, j)] *= Complex::new(0.5, 0.0);
             }
         }
         
@@ -190,7 +280,7 @@ pub fn lindblad_evolution(
     let mut scaled_evolution = &i_scaled_unitary + &dissipative_part;
     for i in 0..scaled_evolution.nrows() {
         for j in 0..scaled_evolution.ncols() {
-            scaled_evolution[(i, j)] = scaled_evolution[(i, j)] * Complex::new(dt, 0.0);
+            scaled_evolution[(i, j)] *= Complex::new(dt, 0.0);
         }
     }
 
@@ -248,3 +338,84 @@ pub fn eisenstein_unit_squared() -> Complex64 {
 pub fn query_bloch_vector(real_component: f64, z_component: f64) -> Vector3<f64> {
     Vector3::new(real_component, 0.0, z_component).normalize()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vector(entries: &[(u64, f64)]) -> SparseVector {
+        entries.iter().copied().collect()
+    }
+
+    #[test]
+    fn mutual_information_with_itself_is_its_entropy() {
+        let words = vector(&[(2, 1.0), (3, 1.0), (5, 2.0), (7, 3.0), (11, 5.0), (13, 8.0)]);
+        let (entropy_x, _, _) = entropies(&joint_distribution(&words, &words, MI_BINS));
+        assert!(entropy_x > 0.0);
+        assert!((mutual_information(&words, &words) - entropy_x).abs() < 1e-12);
+    }
+
+    #[test]
+    fn independent_counts_share_no_information() {
+        // Every pair of counts occurs for exactly one word
+        let a = vector(&[(2, 1.0), (3, 1.0), (5, 2.0), (7, 2.0)]);
+        let b = vector(&[(2, 1.0), (3, 2.0), (5, 1.0), (7, 2.0)]);
+        assert!(mutual_information(&a, &b).abs() < 1e-12);
+    }
+
+    #[test]
+    fn mutual_information_is_bounded_by_the_marginals() {
+        let a = vector(&[(2, 4.0), (3, 1.0), (5, 2.0), (7, 7.0), (11, 1.0)]);
+        let b = vector(&[(3, 2.0), (5, 2.0), (7, 1.0), (13, 3.0), (17, 9.0)]);
+        let (entropy_x, entropy_y, _) = entropies(&joint_distribution(&a, &b, MI_BINS));
+        let information = mutual_information(&a, &b);
+        assert!(information >= 0.0);
+        assert!(information <= entropy_x.min(entropy_y) + 1e-12);
+    }
+
+    #[test]
+    fn normalized_mutual_information_is_1_for_identical_counts() {
+        let words = vector(&[(2, 1.0), (3, 1.0), (5, 2.0), (7, 3.0), (11, 5.0), (13, 8.0)]);
+        assert!((normalized_mutual_information(&words, &words) - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn normalized_mutual_information_is_0_for_independent_counts() {
+        let a = vector(&[(2, 1.0), (3, 1.0), (5, 2.0), (7, 2.0)]);
+        let b = vector(&[(2, 1.0), (3, 2.0), (5, 1.0), (7, 2.0)]);
+        assert!(normalized_mutual_information(&a, &b).abs() < 1e-12);
+    }
+
+    #[test]
+    fn normalized_mutual_information_of_a_constant_document_is_0() {
+        // Every word counted the same carries no information to share
+        let constant = vector(&[(2, 1.0), (3, 1.0), (5, 1.0), (7, 1.0)]);
+        let varied = vector(&[(2, 1.0), (3, 4.0), (5, 2.0), (7, 9.0)]);
+        assert_eq!(normalized_mutual_information(&constant, &varied), 0.0);
+        assert_eq!(normalized_mutual_information(&varied, &constant), 0.0);
+        assert_eq!(normalized_mutual_information(&constant, &constant), 0.0);
+
+        let a = vector(&[(2, 4.0), (3, 1.0), (5, 2.0), (7, 7.0), (11, 1.0)]);
+        let b = vector(&[(3, 2.0), (5, 2.0), (7, 1.0), (13, 3.0), (17, 9.0)]);
+        assert!((0.0..=1.0).contains(&normalized_mutual_information(&a, &b)));
+    }
+
+    #[test]
+    fn jensen_shannon_divergence_is_bounded() {
+        // In bits, so the bound of ln 2 nats is 1
+        let cases: [(&[f64], &[f64]); 5] = [
+            (&[1.0, 2.0, 3.0], &[1.0, 2.0, 3.0]),
+            (&[1.0, 0.0], &[0.0, 1.0]),
+            (&[0.5, 0.5], &[0.9, 0.1]),
+            (&[3.0, 1.0, 0.0, 2.0], &[0.0, 1.0, 4.0]),
+            (&[0.0, 0.0], &[]),
+        ];
+        for (p, q) in cases {
+            let divergence = jensen_shannon_divergence(p, q);
+            assert!((0.0..=1.0).contains(&divergence), "{:?} {:?}: {}", p, q, divergence);
+            assert!((divergence - jensen_shannon_divergence(q, p)).abs() < 1e-12);
+        }
+        assert_eq!(jensen_shannon_divergence(&[1.0, 2.0], &[2.0, 4.0]), 0.0);
+        assert!((jensen_shannon_divergence(&[1.0, 0.0], &[0.0, 1.0]) - 1.0).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,218 @@
+// src/sparse_vector.rs
+
+use crate::prime_hilbert::PrimeVector;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+/// A sparse vector stored as `(prime, value)` pairs sorted by prime.
+///
+/// Scoring walks two vectors side by side instead of hashing every key, and
+/// the pairs sit in one allocation. Values stay `f64`, so converting from a
+/// `PrimeVector` and back is lossless. It is serialized as a `PrimeVector`,
+/// so stored indexes read the same either way.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(from = "PrimeVector", into = "PrimeVector")]
+pub struct SparseVector {
+    entries: Vec<(u64, f64)>,
+}
+
+impl SparseVector {
+    pub fn new() -> Self {
+        SparseVector { entries: Vec::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the value stored for a prime, if any.
+    pub fn get(&self, prime: u64) -> Option<f64> {
+        self.entries
+            .binary_search_by_key(&prime, |&(key, _)| key)
+            .ok()
+            .map(|index| self.entries[index].1)
+    }
+
+    pub fn contains_key(&self, prime: u64) -> bool {
+        self.get(prime).is_some()
+    }
+
+    /// Iterates over the entries in ascending prime order.
+    pub fn iter(&self) -> impl Iterator<Item = (u64, f64)> + '_ {
+        self.entries.iter().copied()
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = u64> + '_ {
+        self.entries.iter().map(|&(key, _)| key)
+    }
+
+    /// Yields `(prime, self value, other value)` for every prime both vectors hold.
+    pub fn matches<'a>(&'a self, other: &'a SparseVector) -> impl Iterator<Item = (u64, f64, f64)> + 'a {
+        let (mut i, mut j) = (0, 0);
+        std::iter::from_fn(move || {
+            while i < self.entries.len() && j < other.entries.len() {
+                let (left, right) = (self.entries[i], other.entries[j]);
+                match left.0.cmp(&right.0) {
+                    Ordering::Less => i += 1,
+                    Ordering::Greater => j += 1,
+                    Ordering::Equal => {
+                        i += 1;
+                        j += 1;
+                        return Some((left.0, left.1, right.1));
+                    }
+                }
+            }
+            None
+        })
+    }
+
+    pub fn dot(&self, other: &SparseVector) -> f64 {
+        self.matches(other).map(|(_, left, right)| left * right).sum()
+    }
+
+    /// Euclidean (L2) norm.
+    pub fn norm(&self) -> f64 {
+        self.entries.iter().map(|&(_, value)| value * value).sum::<f64>().sqrt()
+    }
+
+    /// Adds two vectors, keeping every prime either of them holds.
+    pub fn add(&self, other: &SparseVector) -> SparseVector {
+        let mut entries = Vec::with_capacity(self.entries.len() + other.entries.len());
+        let (mut i, mut j) = (0, 0);
+        while i < self.entries.len() && j < other.entries.len() {
+            let (left, right) = (self.entries[i], other.entries[j]);
+            match left.0.cmp(&right.0) {
+                Ordering::Less => {
+                    entries.push(left);
+                    i += 1;
+                }
+                Ordering::Greater => {
+                    entries.push(right);
+                    j += 1;
+                }
+                Ordering::Equal => {
+                    entries.push((left.0, left.1 + right.1));
+                    i += 1;
+                    j += 1;
+                }
+            }
+        }
+        entries.extend_from_slice(&self.entries[i..]);
+        entries.extend_from_slice(&other.entries[j..]);
+        SparseVector { entries }
+    }
+
+    pub fn to_prime_vector(&self) -> PrimeVector {
+        self.entries.iter().copied().collect()
+    }
+}
+
+impl From<&PrimeVector> for SparseVector {
+    fn from(vector: &PrimeVector) -> Self {
+        let mut entries: Vec<(u64, f64)> = vector.iter().map(|(&key, &value)| (key, value)).collect();
+        entries.sort_unstable_by_key(|&(key, _)| key);
+        SparseVector { entries }
+    }
+}
+
+impl From<PrimeVector> for SparseVector {
+    fn from(vector: PrimeVector) -> Self {
+        SparseVector::from(&vector)
+    }
+}
+
+impl FromIterator<(u64, f64)> for SparseVector {
+    /// Collects pairs in any order; of pairs with the same prime the last one
+    /// is kept, as when collecting into a `PrimeVector`.
+    fn from_iter<I: IntoIterator<Item = (u64, f64)>>(pairs: I) -> Self {
+        let mut entries: Vec<(u64, f64)> = pairs.into_iter().collect();
+        entries.sort_by_key(|&(key, _)| key);
+        entries.dedup_by(|later, earlier| {
+            let duplicate = later.0 == earlier.0;
+            if duplicate {
+                earlier.1 = later.1;
+            }
+            duplicate
+        });
+        SparseVector { entries }
+    }
+}
+
+impl From<SparseVector> for PrimeVector {
+    fn from(vector: SparseVector) -> Self {
+        vector.entries.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    // Random weights on a random subset of the first primes
+    fn random_vector(rng: &mut StdRng, primes: &[u64]) -> PrimeVector {
+        let mut vector = PrimeVector::new();
+        for &prime in primes {
+            if rng.gen_bool(0.4) {
+                vector.insert(prime, rng.gen_range(-2.0..2.0));
+            }
+        }
+        vector
+    }
+
+    #[test]
+    fn dot_and_add_match_a_hash_map() {
+        let primes: Vec<u64> = primal::Primes::all().take(60).map(|prime| prime as u64).collect();
+        let mut rng = StdRng::seed_from_u64(11);
+        for _ in 0..50 {
+            let (a, b) = (random_vector(&mut rng, &primes), random_vector(&mut rng, &primes));
+            let (sparse_a, sparse_b) = (SparseVector::from(&a), SparseVector::from(&b));
+
+            let dot: f64 = a.iter().filter_map(|(prime, x)| Some(x * b.get(prime)?)).sum();
+            assert!((sparse_a.dot(&sparse_b) - dot).abs() < 1e-12);
+            assert!((sparse_a.norm() - a.values().map(|x| x * x).sum::<f64>().sqrt()).abs() < 1e-12);
+
+            let mut sum = a.clone();
+            for (&prime, &y) in &b {
+                *sum.entry(prime).or_insert(0.0) += y;
+            }
+            let added = sparse_a.add(&sparse_b);
+            assert_eq!(added.to_prime_vector(), sum);
+            assert!(added.keys().zip(added.keys().skip(1)).all(|(prime, next)| prime < next));
+        }
+        assert_eq!(SparseVector::new().dot(&SparseVector::from(&PrimeVector::from([(2, 1.0)]))), 0.0);
+    }
+
+    #[test]
+    fn collecting_sorts_and_keeps_the_last_duplicate() {
+        let vector: SparseVector = [(7, 1.0), (2, 2.0), (7, 3.0), (5, 4.0), (2, 5.0)].into_iter().collect();
+        assert_eq!(vector.iter().collect::<Vec<_>>(), [(2, 5.0), (5, 4.0), (7, 3.0)]);
+        assert_eq!(vector.get(7), Some(3.0));
+        assert_eq!(vector.get(3), None);
+
+        // As collecting into a PrimeVector does
+        let map: PrimeVector = [(7, 1.0), (2, 2.0), (7, 3.0), (5, 4.0), (2, 5.0)].into_iter().collect();
+        assert_eq!(vector.to_prime_vector(), map);
+    }
+
+    #[test]
+    fn serializes_as_a_prime_vector() {
+        let map: PrimeVector = [(2, 0.1), (3, 1.0 / 3.0), (1009, -7.25), (6, 0.5)].into_iter().collect();
+        let vector = SparseVector::from(&map);
+
+        // Stored vectors read back the same whichever type wrote them
+        let json = serde_json::to_string(&vector).unwrap();
+        assert_eq!(serde_json::from_str::<PrimeVector>(&json).unwrap(), map);
+        let json = serde_json::to_string(&map).unwrap();
+        assert_eq!(serde_json::from_str::<SparseVector>(&json).unwrap(), vector);
+        assert_eq!(PrimeVector::from(vector.clone()), map);
+
+        assert_eq!(serde_json::from_str::<SparseVector>("{}").unwrap(), SparseVector::new());
+        assert_eq!(serde_json::to_string(&SparseVector::new()).unwrap(), "{}");
+    }
+}
@@ -0,0 +1,521 @@
+// src/weighting.rs
+
+use crate::inverted_index::TermBounds;
+use crate::postings::Postings;
+use crate::prime_hilbert::{build_vector, PrimeVector};
+use crate::sparse_vector::SparseVector;
+use std::collections::HashMap;
+
+/// BM25 term frequency saturation used by `Weighting::bm25`.
+pub const BM25_K1: f64 = 1.2;
+
+/// BM25 document length normalization used by `Weighting::bm25`.
+pub const BM25_B: f64 = 0.75;
+
+/// Dirichlet prior, in tokens, used by `Weighting::dirichlet`.
+pub const DIRICHLET_MU: f64 = 2000.0;
+
+/// Share of the collection model used by `Weighting::jelinek_mercer`.
+pub const JELINEK_MERCER_LAMBDA: f64 = 0.1;
+
+/// How term counts are turned into the weights that resonance is computed from.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Weighting {
+    /// The frequency vectors from `build_vector`, so every word counts the
+    /// same. The default, as it ranks like search did before weighting existed.
+    #[default]
+    Raw,
+    /// 1 + ln(tf), cosine-normalized on both sides.
+    LogTf,
+    /// Log-TF documents against log-TF × IDF queries, both cosine-normalized
+    /// (SMART lnc.ltc), so rare query words carry the score.
+    TfIdf,
+    /// Okapi BM25 with term saturation `k1` and length normalization `b`.
+    Bm25 { k1: f64, b: f64 },
+    /// Query likelihood under a document language model smoothed with a
+    /// Dirichlet prior of `mu` tokens drawn from the collection model.
+    Dirichlet { mu: f64 },
+    /// Query likelihood under a document language model interpolated with
+    /// the collection model, which gets a share of `lambda`.
+    JelinekMercer { lambda: f64 },
+}
+
+impl Weighting {
+    /// BM25 with the usual parameters.
+    pub fn bm25() -> Self {
+        Weighting::Bm25 { k1: BM25_K1, b: BM25_B }
+    }
+
+    /// Dirichlet-smoothed query likelihood with the usual prior.
+    pub fn dirichlet() -> Self {
+        Weighting::Dirichlet { mu: DIRICHLET_MU }
+    }
+
+    /// Jelinek-Mercer-smoothed query likelihood with the usual share.
+    pub fn jelinek_mercer() -> Self {
+        Weighting::JelinekMercer { lambda: JELINEK_MERCER_LAMBDA }
+    }
+
+    /// Parses "raw", "logtf", "tfidf", "bm25", "bm25:k1,b", "dirichlet",
+    /// "dirichlet:mu", "jm" or "jm:lambda".
+    pub fn parse(name: &str) -> Option<Self> {
+        let name = name.trim().to_lowercase();
+        match name.as_str() {
+            "raw" => Some(Weighting::Raw),
+            "logtf" | "log-tf" => Some(Weighting::LogTf),
+            "tfidf" | "tf-idf" => Some(Weighting::TfIdf),
+            "bm25" => Some(Weighting::bm25()),
+            "dirichlet" => Some(Weighting::dirichlet()),
+            "jm" | "jelinek-mercer" => Some(Weighting::jelinek_mercer()),
+            _ => {
+                if let Some(mu) = name.strip_prefix("dirichlet:") {
+                    let mu: f64 = mu.trim().parse().ok()?;
+                    return (mu > 0.0).then_some(Weighting::Dirichlet { mu });
+                }
+                if let Some(lambda) = name.strip_prefix("jm:") {
+                    let lambda: f64 = lambda.trim().parse().ok()?;
+                    return (lambda > 0.0 && lambda < 1.0).then_some(Weighting::JelinekMercer { lambda });
+                }
+                let (k1, b) = name.strip_prefix("bm25:")?.split_once(',')?;
+                let (k1, b) = (k1.trim().parse().ok()?, b.trim().parse().ok()?);
+                (k1 >= 0.0 && (0.0..=1.0).contains(&b)).then_some(Weighting::Bm25 { k1, b })
+            }
+        }
+    }
+
+    /// The name `parse` accepts for this scheme.
+    pub fn name(&self) -> String {
+        match self {
+            Weighting::Raw => "raw".to_string(),
+            Weighting::LogTf => "logtf".to_string(),
+            Weighting::TfIdf => "tfidf".to_string(),
+            Weighting::Bm25 { k1, b } => format!("bm25:{},{}", k1, b),
+            Weighting::Dirichlet { mu } => format!("dirichlet:{}", mu),
+            Weighting::JelinekMercer { lambda } => format!("jm:{}", lambda),
+        }
+    }
+
+    /// Whether documents are scored by query likelihood rather than by
+    /// matching term weights.
+    ///
+    /// The score is then the query's expected log-probability under the
+    /// smoothed document model, less a part that is the same for every
+    /// document, so it ranks like the query likelihood and like the negative
+    /// KL divergence from the query model to the document model.
+    pub fn is_language_model(&self) -> bool {
+        matches!(self, Weighting::Dirichlet { .. } | Weighting::JelinekMercer { .. })
+    }
+
+    /// Whether the query side carries the words' IDF, which the document
+    /// side leaves out.
+    pub fn weighs_query_by_idf(&self) -> bool {
+        matches!(self, Weighting::TfIdf | Weighting::Bm25 { .. })
+    }
+
+    /// Builds the query side of the resonance from the query's primes.
+    pub fn query_vector(&self, primes: &[u64], stats: &CorpusStats) -> SparseVector {
+        let vector = match self {
+            Weighting::Raw => return SparseVector::from(build_vector(primes)),
+            Weighting::LogTf => weigh_counts(primes, |_, tf| log_tf(tf)),
+            Weighting::TfIdf => weigh_counts(primes, |prime, tf| log_tf(tf) * stats.idf(prime)),
+            Weighting::Bm25 { .. } => weigh_counts(primes, |prime, tf| tf * stats.idf(prime)),
+            // The query model: how often each word occurs in the query
+            Weighting::Dirichlet { .. } | Weighting::JelinekMercer { .. } => {
+                weigh_counts(primes, |_, tf| tf / primes.len() as f64)
+            }
+        };
+        match self {
+            Weighting::Bm25 { .. } | Weighting::Dirichlet { .. } | Weighting::JelinekMercer { .. } => SparseVector::from(vector),
+            _ => SparseVector::from(cosine_normalize(vector)),
+        }
+    }
+
+    /// Builds the document side of the resonance from a document's postings.
+    pub fn document_vector(&self, postings: &Postings, stats: &CorpusStats) -> SparseVector {
+        if *self == Weighting::Raw {
+            let primes: Vec<u64> = postings
+                .iter()
+                .flat_map(|(&prime, positions)| std::iter::repeat_n(prime, positions.len()))
+                .collect();
+            return SparseVector::from(build_vector(&primes));
+        }
+
+        let scale = self.document_scale(postings, stats);
+        let vector: PrimeVector = postings
+            .iter()
+            .map(|(&prime, positions)| (prime, self.document_weight(prime, positions.len() as f64, scale, stats)))
+            .collect();
+        SparseVector::from(vector)
+    }
+
+    /// Scores a document against a query vector from `query_vector`.
+    ///
+    /// Document weights come from the positional postings, so they always
+    /// reflect the current corpus statistics; only the query's words are
+    /// weighed. Documents indexed without postings, and the `Raw` scheme, use
+    /// the stored frequency vector.
+    pub fn resonance(&self, query: &SparseVector, vector: &SparseVector, postings: &Postings, stats: &CorpusStats) -> f64 {
+        if postings.is_empty() || *self == Weighting::Raw {
+            return query.dot(vector);
+        }
+
+        let scale = self.document_scale(postings, stats);
+        let score: f64 = query
+            .iter()
+            .filter_map(|(prime, weight)| {
+                let tf = postings.get(&prime)?.len() as f64;
+                Some(weight * self.document_weight(prime, tf, scale, stats))
+            })
+            .sum();
+
+        match *self {
+            Weighting::Dirichlet { mu } => {
+                // The prior weighs less in longer documents, lowering the
+                // probability of every query word they do not contain
+                let mass: f64 = query.iter().map(|(_, weight)| weight).sum();
+                score + mass * (mu / (scale + mu)).ln()
+            }
+            _ => score,
+        }
+    }
+
+    /// The largest weight a word can have in any document, from the extremes
+    /// an `InvertedIndex` tracks for it, so top-k retrieval can skip documents
+    /// that cannot make it.
+    pub fn max_document_weight(&self, prime: u64, bounds: &TermBounds, stats: &CorpusStats) -> f64 {
+        match *self {
+            Weighting::Raw => bounds.max_raw,
+            Weighting::Bm25 { k1, b } => {
+                // Weights grow with the count and shrink with the length
+                let average = stats.average_length().max(1.0);
+                let scale = k1 * (1.0 - b + b * bounds.min_length as f64 / average);
+                self.document_weight(prime, bounds.max_tf as f64, scale, stats)
+            }
+            // The Dirichlet length term is never positive, so the count alone bounds it
+            Weighting::Dirichlet { .. } => self.document_weight(prime, bounds.max_tf as f64, 1.0, stats),
+            // Only the word's share of the document counts
+            Weighting::JelinekMercer { .. } => self.document_weight(prime, bounds.max_relative_tf, 1.0, stats),
+            _ => bounds.max_log_tf,
+        }
+    }
+
+    /// The per-document part of the weight: the log-TF vector norm, the
+    /// BM25 length factor or, for language models, the length itself. `Raw`
+    /// documents use their frequency vector instead.
+    fn document_scale(&self, postings: &Postings, stats: &CorpusStats) -> f64 {
+        match *self {
+            Weighting::Bm25 { k1, b } => {
+                let average = stats.average_length().max(1.0);
+                k1 * (1.0 - b + b * document_length(postings) as f64 / average)
+            }
+            Weighting::Dirichlet { .. } | Weighting::JelinekMercer { .. } => document_length(postings) as f64,
+            _ => postings
+                .values()
+                .map(|positions| log_tf(positions.len() as f64).powi(2))
+                .sum::<f64>()
+                .sqrt(),
+        }
+    }
+
+    /// A word's weight in a document. For language models this is the
+    /// log-ratio of its smoothed probability to the probability smoothing
+    /// alone would give it, which is 0 for words the document lacks; with
+    /// Jelinek-Mercer `tf` may be the word's share of a document of `scale` 1.
+    fn document_weight(&self, prime: u64, tf: f64, scale: f64, stats: &CorpusStats) -> f64 {
+        if scale <= 0.0 {
+            return 0.0;
+        }
+        match *self {
+            Weighting::Bm25 { k1, .. } => tf * (k1 + 1.0) / (tf + scale),
+            Weighting::Dirichlet { mu } => {
+                let collection = stats.collection_probability(prime);
+                if collection > 0.0 { (1.0 + tf / (mu * collection)).ln() } else { 0.0 }
+            }
+            Weighting::JelinekMercer { lambda } => {
+                let collection = stats.collection_probability(prime);
+                if collection > 0.0 { (1.0 + (1.0 - lambda) * tf / (lambda * scale * collection)).ln() } else { 0.0 }
+            }
+            _ => log_tf(tf) / scale,
+        }
+    }
+}
+
+/// Document and collection frequencies and lengths over an indexed corpus.
+#[derive(Debug, Clone, Default)]
+pub struct CorpusStats {
+    pub document_count: usize,
+    pub total_length: u64,
+    pub document_frequency: HashMap<u64, usize>,
+    /// How often each word occurs over all documents.
+    pub collection_frequency: HashMap<u64, u64>,
+}
+
+impl CorpusStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds the statistics from the postings of every indexed document.
+    pub fn from_postings<'a>(documents: impl IntoIterator<Item = &'a Postings>) -> Self {
+        let mut stats = Self::new();
+        for postings in documents {
+            stats.add_document(postings);
+        }
+        stats
+    }
+
+    /// Counts a document's words. Documents without postings are left out.
+    pub fn add_document(&mut self, postings: &Postings) {
+        if postings.is_empty() {
+            return;
+        }
+        self.document_count += 1;
+        self.total_length += document_length(postings);
+        for (&prime, positions) in postings {
+            *self.document_frequency.entry(prime).or_insert(0) += 1;
+            *self.collection_frequency.entry(prime).or_insert(0) += positions.len() as u64;
+        }
+    }
+
+    /// Takes back a document counted by `add_document`.
+    pub fn remove_document(&mut self, postings: &Postings) {
+        if postings.is_empty() {
+            return;
+        }
+        self.document_count = self.document_count.saturating_sub(1);
+        self.total_length = self.total_length.saturating_sub(document_length(postings));
+        for (prime, positions) in postings {
+            if let Some(count) = self.document_frequency.get_mut(prime) {
+                *count -= 1;
+                if *count == 0 {
+                    self.document_frequency.remove(prime);
+                }
+            }
+            if let Some(count) = self.collection_frequency.get_mut(prime) {
+                *count = count.saturating_sub(positions.len() as u64);
+                if *count == 0 {
+                    self.collection_frequency.remove(prime);
+                }
+            }
+        }
+    }
+
+    pub fn document_frequency(&self, prime: u64) -> usize {
+        self.document_frequency.get(&prime).copied().unwrap_or(0)
+    }
+
+    pub fn collection_frequency(&self, prime: u64) -> u64 {
+        self.collection_frequency.get(&prime).copied().unwrap_or(0)
+    }
+
+    /// The collection model: a word's share of all indexed tokens.
+    pub fn collection_probability(&self, prime: u64) -> f64 {
+        if self.total_length == 0 {
+            0.0
+        } else {
+            self.collection_frequency(prime) as f64 / self.total_length as f64
+        }
+    }
+
+    pub fn average_length(&self) -> f64 {
+        if self.document_count == 0 {
+            0.0
+        } else {
+            self.total_length as f64 / self.document_count as f64
+        }
+    }
+
+    /// Inverse document frequency, ln(1 + (N - df + 0.5) / (df + 0.5)).
+    ///
+    /// Stays positive even for words in every document, so common words
+    /// still count a little instead of pushing scores negative.
+    pub fn idf(&self, prime: u64) -> f64 {
+        let n = self.document_count as f64;
+        let df = (self.document_frequency(prime) as f64).min(n);
+        (1.0 + (n - df + 0.5) / (df + 0.5)).ln()
+    }
+}
+
+/// Number of tokens a document's postings were built from.
+pub fn document_length(postings: &Postings) -> u64 {
+    postings.values().map(|positions| positions.len() as u64).sum()
+}
+
+/// 1 + ln(tf), or 0 for a word that does not occur.
+pub fn log_tf(tf: f64) -> f64 {
+    if tf > 0.0 {
+        1.0 + tf.ln()
+    } else {
+        0.0
+    }
+}
+
+fn weigh_counts(primes: &[u64], weight: impl Fn(u64, f64) -> f64) -> HashMap<u64, f64> {
+    let mut counts: HashMap<u64, f64> = HashMap::new();
+    for &prime in primes {
+        *counts.entry(prime).or_insert(0.0) += 1.0;
+    }
+    counts.into_iter().map(|(prime, tf)| (prime, weight(prime, tf))).collect()
+}
+
+fn cosine_normalize(mut vector: HashMap<u64, f64>) -> HashMap<u64, f64> {
+    let norm = vector.values().map(|value| value * value).sum::<f64>().sqrt();
+    if norm > 0.0 {
+        for value in vector.values_mut() {
+            *value /= norm;
+        }
+    }
+    vector
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inverted_index::InvertedIndex;
+    use crate::postings::build_postings;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    const WORDS: [u64; 10] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29];
+
+    fn weightings() -> [Weighting; 9] {
+        [
+            Weighting::Raw,
+            Weighting::LogTf,
+            Weighting::TfIdf,
+            Weighting::bm25(),
+            Weighting::Bm25 { k1: 0.5, b: 1.0 },
+            Weighting::dirichlet(),
+            Weighting::Dirichlet { mu: 2.5 },
+            Weighting::jelinek_mercer(),
+            Weighting::JelinekMercer { lambda: 0.75 },
+        ]
+    }
+
+    // Documents of 1 to 30 tokens with a skewed word distribution
+    fn corpus(rng: &mut StdRng, size: usize) -> Vec<Vec<u64>> {
+        let mut documents = Vec::new();
+        for _ in 0..size {
+            let length = rng.gen_range(1..30);
+            let mut tokens = Vec::new();
+            for _ in 0..length {
+                tokens.push(WORDS[(rng.gen::<f64>().powi(2) * WORDS.len() as f64) as usize]);
+            }
+            documents.push(tokens);
+        }
+        documents
+    }
+
+    #[test]
+    fn names_parse_back_to_the_same_scheme() {
+        for weighting in weightings() {
+            assert_eq!(Weighting::parse(&weighting.name()), Some(weighting), "{}", weighting.name());
+        }
+        assert_eq!(Weighting::parse(" TF-IDF "), Some(Weighting::TfIdf));
+        assert_eq!(Weighting::parse("jelinek-mercer"), Some(Weighting::jelinek_mercer()));
+        for name in ["", "bm26", "bm25:1.2", "bm25:1.2,1.5", "dirichlet:0", "jm:1", "jm:x"] {
+            assert_eq!(Weighting::parse(name), None, "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn max_document_weight_bounds_every_document() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let documents: Vec<Postings> = corpus(&mut rng, 200).iter().map(|tokens| build_postings(tokens)).collect();
+        let stats = CorpusStats::from_postings(&documents);
+        let mut index = InvertedIndex::new();
+        for (doc, postings) in documents.iter().enumerate() {
+            index.add_document(doc, postings, &Weighting::Raw.document_vector(postings, &stats));
+        }
+
+        for weighting in weightings() {
+            for postings in &documents {
+                let vector = weighting.document_vector(postings, &stats);
+                for (prime, weight) in vector.iter() {
+                    let bound = weighting.max_document_weight(prime, &index.bounds(prime).unwrap(), &stats);
+                    assert!(weight <= bound + 1e-12, "{}: {} above {} for {}", weighting.name(), weight, bound, prime);
+                }
+            }
+
+            // So no document scores above the sum of the query words' bounds
+            let query = weighting.query_vector(&[2, 2, 7, 23], &stats);
+            let ceiling: f64 = query
+                .iter()
+                .map(|(prime, weight)| weight * weighting.max_document_weight(prime, &index.bounds(prime).unwrap(), &stats))
+                .sum();
+            for postings in &documents {
+                let vector = weighting.document_vector(postings, &stats);
+                let score = weighting.resonance(&query, &vector, postings, &stats);
+                assert!(score <= ceiling + 1e-12, "{}: {} above {}", weighting.name(), score, ceiling);
+            }
+        }
+    }
+
+    #[test]
+    fn query_vectors_are_normalized_per_scheme() {
+        let mut stats = CorpusStats::new();
+        for tokens in [vec![2, 3, 3], vec![2, 5], vec![2, 7, 7, 7]] {
+            stats.add_document(&build_postings(&tokens));
+        }
+        let primes = [2, 3, 3, 11];
+
+        for weighting in weightings() {
+            let query = weighting.query_vector(&primes, &stats);
+            let keys: Vec<u64> = query.keys().collect();
+            assert_eq!(keys, vec![2, 3, 11], "{}", weighting.name());
+            let total: f64 = query.iter().map(|(_, weight)| weight).sum();
+            match weighting {
+                Weighting::Raw => assert!((total - 1.0).abs() < 1e-12),
+                Weighting::LogTf | Weighting::TfIdf => assert!((query.norm() - 1.0).abs() < 1e-12, "{}", weighting.name()),
+                Weighting::Bm25 { .. } => assert!((query.get(3).unwrap() - 2.0 * stats.idf(3)).abs() < 1e-12),
+                _ => assert!((query.get(3).unwrap() - 0.5).abs() < 1e-12 && (total - 1.0).abs() < 1e-12),
+            }
+        }
+        // Rare words carry more of a TF-IDF query
+        let query = Weighting::TfIdf.query_vector(&[2, 5], &stats);
+        assert!(query.get(5).unwrap() > query.get(2).unwrap());
+    }
+
+    #[test]
+    fn document_vectors_follow_the_counts() {
+        let tokens = [2, 3, 3, 3, 5];
+        let postings = build_postings(&tokens);
+        let stats = CorpusStats::from_postings([&postings, &build_postings(&[2, 7])]);
+
+        let raw = Weighting::Raw.document_vector(&postings, &stats);
+        assert_eq!(raw, SparseVector::from(build_vector(&tokens)));
+        assert!((Weighting::LogTf.document_vector(&postings, &stats).norm() - 1.0).abs() < 1e-12);
+
+        for weighting in weightings() {
+            let vector = weighting.document_vector(&postings, &stats);
+            assert_eq!(vector.len(), 3, "{}", weighting.name());
+            // 3 occurs three times and only here, 2 once and in the other document too
+            assert!(vector.get(3).unwrap() > vector.get(2).unwrap(), "{}", weighting.name());
+        }
+        assert!(Weighting::bm25().document_vector(&Postings::new(), &stats).is_empty());
+    }
+
+    #[test]
+    fn idf_falls_with_document_frequency_and_stays_positive() {
+        let documents: Vec<Postings> = [vec![2, 3], vec![2, 5], vec![2, 3, 7], vec![2]]
+            .iter()
+            .map(|tokens| build_postings(tokens))
+            .collect();
+        let mut stats = CorpusStats::from_postings(&documents);
+        assert_eq!((stats.document_count, stats.total_length), (4, 8));
+        assert_eq!(stats.document_frequency(3), 2);
+
+        let idf: Vec<f64> = [11, 7, 3, 2].iter().map(|&prime| stats.idf(prime)).collect();
+        assert!(idf.windows(2).all(|pair| pair[0] > pair[1]), "{:?}", idf);
+        assert!(idf[3] > 0.0);
+        assert!((idf[1] - (1.0 + 3.5 / 1.5_f64).ln()).abs() < 1e-12);
+
+        // Removing a document takes back exactly what it added
+        stats.remove_document(&documents[2]);
+        let without = CorpusStats::from_postings([&documents[0], &documents[1], &documents[3]]);
+        assert_eq!((stats.document_count, stats.total_length), (without.document_count, without.total_length));
+        assert_eq!(stats.document_frequency, without.document_frequency);
+        assert_eq!(stats.collection_frequency, without.collection_frequency);
+        assert_eq!(CorpusStats::new().idf(2), 2.0_f64.ln());
+    }
+}